@@ -0,0 +1,10 @@
+extern crate tokio_coap;
+
+use tokio_coap::blocking;
+
+fn main() {
+    match blocking::get("coap://coap.sh/ip") {
+        Ok(response) => println!("{}", String::from_utf8_lossy(&response.payload)),
+        Err(e) => println!("error in request: {:?}", e),
+    }
+}
@@ -12,7 +12,6 @@ use tokio::net::{UdpFramed, UdpSocket};
 use tokio_coap::codec::CoapCodec;
 use tokio_coap::message::{Mtype, Code};
 use tokio_coap::message::Code::{Content, NotImplemented};
-use tokio_coap::message::option::UriPath;
 
 fn main() {
     pretty_env_logger::init();
@@ -28,9 +27,9 @@ fn main() {
 
         match request.mtype {
             Mtype::Confirmable | Mtype::NonConfirmable => {
-                let path = request.options.get::<UriPath>();
+                let path = request.uri_path();
                 match (&request.code, &path) {
-                    (&Code::Get, &Some(ref p)) if p == &["ip".into()] => {
+                    (&Code::Get, &Ok(ref p)) if p == "/ip" => {
                          Some((request.new_reply()
                             .with_code(Content)
                             .with_payload(addr.ip()
@@ -0,0 +1,432 @@
+//! Block-wise transfer of request payloads (RFC 7959) with Request-Tag
+//! (RFC 9175).
+//!
+//! Payloads too large for a single message are sent as a sequence of Block1
+//! requests. Every block of one upload carries the same Request-Tag, so that
+//! a server can keep concurrent uploads to the same resource apart, and no
+//! block of one can be mixed into the body of another.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::option::Option as StdOption;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use futures::prelude::*;
+use futures::future::{self, Loop};
+
+use client::{IoFuture, Transport};
+use message::{Code, Message};
+use message::option::{self, Block1, Block2, Echo, Option, RequestTag, Size1, Size2};
+use server::{Handler, Request};
+use util::random_u64;
+
+/// The block size used by a client unless told otherwise.
+pub const DEFAULT_BLOCK_SIZE: usize = 1024;
+
+/// The largest body a server reassembles unless told otherwise.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// How many uploads a server keeps track of at once.
+const MAX_UPLOADS: usize = 64;
+
+/// RFC 7959: 2.2.  Structure of a Block Option
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockValue {
+    /// the number of the block within the body
+    pub num: u32,
+    /// whether more blocks follow
+    pub more: bool,
+    /// the block size exponent, the block size is `2 ^ (szx + 4)`
+    pub szx: u8,
+}
+
+impl BlockValue {
+    /// Describe block `num` of `size` bytes, `size` is rounded down to a
+    /// power of two between 16 and 1024.
+    pub fn new(num: u32, more: bool, size: usize) -> BlockValue {
+        let mut szx = 0;
+        while szx < 6 && 1 << (szx + 5) <= size {
+            szx += 1;
+        }
+
+        BlockValue { num, more, szx }
+    }
+
+    pub fn size(&self) -> usize {
+        1 << (self.szx + 4)
+    }
+
+    /// The offset of this block within the body.
+    pub fn offset(&self) -> usize {
+        self.num as usize * self.size()
+    }
+
+    /// Decode an option value, `None` if it is malformed or uses the
+    /// reserved block size exponent 7.
+    pub fn from_bytes(bytes: &[u8]) -> StdOption<BlockValue> {
+        if bytes.len() > 3 {
+            return None;
+        }
+
+        let value = option::bytes_to_value(bytes);
+        let szx = (value & 0x07) as u8;
+
+        if szx == 7 {
+            return None;
+        }
+
+        Some(BlockValue {
+            num: (value >> 4) as u32,
+            more: value & 0x08 != 0,
+            szx,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let more = if self.more { 0x08 } else { 0 };
+
+        option::value_to_bytes(u64::from(self.num) << 4 | more | u64::from(self.szx))
+    }
+}
+
+/// Send the payload of `msg` in blocks of at most `block_size` bytes, using
+/// `exchange` for each block, and return the response to the last one.
+///
+/// RFC 7959: 2.5.  Using the Block1 Option
+///
+/// A server may ask for smaller blocks with the Block1 option of a 2.31
+/// Continue, which the following blocks then use, or of a 4.13 Request
+/// Entity Too Large, in which case the block is sent again in the smaller
+/// size.
+pub(crate) fn upload<T, F>(
+    transport: T,
+    msg: Message,
+    remote_addr: SocketAddr,
+    block_size: usize,
+    exchange: F,
+) -> IoFuture<(Message, T)>
+    where T: Transport,
+          F: Fn(T, Message, SocketAddr) -> IoFuture<(Message, T)> + Send + 'static,
+{
+    let szx = BlockValue::new(0, false, block_size).szx;
+    let tag = random_u64().to_be_bytes()[..4].to_vec();
+
+    // offsets are always a multiple of the current block size, as it only
+    // ever shrinks
+    let upload = future::loop_fn((transport, 0, szx), move |(transport, offset, szx)| {
+        let len = msg.payload.len();
+        let size = BlockValue { num: 0, more: false, szx }.size();
+        let block = BlockValue { num: (offset / size) as u32, more: offset + size < len, szx };
+        let end = ::std::cmp::min(offset + size, len);
+
+        let mut request = msg.clone().with_payload(msg.payload[offset..end].to_vec());
+        request.options.push_raw(Block1::NUMBER, block.to_bytes());
+        request.options.push_raw(RequestTag::NUMBER, tag.clone());
+        if offset == 0 {
+            request.options.push_raw(Size1::NUMBER, option::value_to_bytes(len as u64));
+        }
+
+        exchange(transport, request, remote_addr).map(move |(response, transport)| {
+            let smaller = response.options.map.get(&Block1::NUMBER)
+                .and_then(|values| values.first())
+                .and_then(|value| BlockValue::from_bytes(value))
+                .map(|value| value.szx)
+                .filter(|&server_szx| server_szx < szx);
+
+            match (response.code, smaller) {
+                (Code::Continue, smaller) if block.more => Loop::Continue((transport, end, smaller.unwrap_or(szx))),
+                (Code::RequestEntityTooLarge, Some(smaller)) => Loop::Continue((transport, offset, smaller)),
+                _ => Loop::Break((response, transport)),
+            }
+        })
+    });
+
+    Box::new(upload)
+}
+
+/// A `Handler` reassembling Block1 uploads before passing them on.
+///
+/// Uploads are told apart by the client's address, the method and the
+/// options apart from the block options themselves, so blocks carrying
+/// different Request-Tags never end up in the same body.
+pub struct Reassemble<H> {
+    handler: Arc<H>,
+    uploads: Arc<Mutex<HashMap<UploadKey, Upload>>>,
+    max_body_size: usize,
+}
+
+type UploadKey = (SocketAddr, u8, Vec<(u16, Vec<u8>)>);
+
+struct Upload {
+    started: Instant,
+    body: Vec<u8>,
+}
+
+impl<H: Handler> Reassemble<H> {
+    pub fn new(handler: H) -> Reassemble<H> {
+        Reassemble {
+            handler: Arc::new(handler),
+            uploads: Arc::new(Mutex::new(HashMap::new())),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+
+    /// Reject bodies larger than `size` bytes with 4.13 Request Entity Too
+    /// Large.
+    pub fn with_max_body_size(mut self, size: usize) -> Self {
+        self.max_body_size = size;
+        self
+    }
+}
+
+impl<H: Handler> Handler for Reassemble<H> {
+    fn handle(&self, request: Request) -> IoFuture<Message> {
+        let block = match request.message.options.map.get(&Block1::NUMBER) {
+            None => return self.handler.handle(request),
+            Some(values) => match (values.len(), values.first().and_then(|v| BlockValue::from_bytes(v))) {
+                (1, Some(block)) => block,
+                _ => return Box::new(future::ok(Message::new().with_code(Code::BadRequest))),
+            },
+        };
+
+        let Request { mut message, source } = request;
+
+        let key = {
+            let options = message.options.iter()
+                .filter(|&(number, _)| identifies_upload(number))
+                .map(|(number, value)| (number, value.to_vec()))
+                .collect();
+            (source, message.code.as_u8(), options)
+        };
+
+        let mut uploads = self.uploads.lock().unwrap();
+
+        if block.num == 0 {
+            if uploads.len() >= MAX_UPLOADS {
+                evict_oldest(&mut uploads);
+            }
+            uploads.insert(key.clone(), Upload { started: Instant::now(), body: Vec::new() });
+        }
+
+        let in_sequence = uploads.get(&key).map(|u| u.body.len() == block.offset()).unwrap_or(false);
+        let full_block = !block.more || message.payload.len() == block.size();
+
+        if !in_sequence || !full_block {
+            uploads.remove(&key);
+            return Box::new(future::ok(Message::new().with_code(Code::RequestEntityIncomplete)));
+        }
+
+        let body_size = block.offset() + message.payload.len();
+        if body_size > self.max_body_size {
+            uploads.remove(&key);
+            let mut response = Message::new().with_code(Code::RequestEntityTooLarge);
+            response.options.push_raw(Size1::NUMBER, option::value_to_bytes(self.max_body_size as u64));
+            return Box::new(future::ok(response));
+        }
+
+        if block.more {
+            uploads.get_mut(&key).expect("checked in sequence").body.extend(&message.payload);
+
+            let mut response = Message::new().with_code(Code::Continue);
+            response.options.push_raw(Block1::NUMBER, block.to_bytes());
+            return Box::new(future::ok(response));
+        }
+
+        let mut body = uploads.remove(&key).expect("checked in sequence").body;
+        drop(uploads);
+
+        body.extend(&message.payload);
+        message.payload = body;
+        message.options.map.remove(&Block1::NUMBER);
+        message.options.map.remove(&Size1::NUMBER);
+
+        let response = self.handler
+            .handle(Request { message, source })
+            .map(move |mut response| {
+                response.options.push_raw(Block1::NUMBER, block.to_bytes());
+                response
+            });
+
+        Box::new(response)
+    }
+
+    fn forwards_options(&self) -> bool {
+        self.handler.forwards_options()
+    }
+
+    fn reset(&self, source: SocketAddr, mid: u16) {
+        self.handler.reset(source, mid)
+    }
+}
+
+/// Every option but Block1, Block2, Size1, Size2 and Echo identifies an
+/// upload, Echo may change from block to block when freshness is required.
+fn identifies_upload(number: u16) -> bool {
+    !(number == Block2::NUMBER
+      || number == Block1::NUMBER
+      || number == Size2::NUMBER
+      || number == Size1::NUMBER
+      || number == Echo::NUMBER)
+}
+
+fn evict_oldest(uploads: &mut HashMap<UploadKey, Upload>) {
+    let oldest = uploads.iter().min_by_key(|&(_, u)| u.started).map(|(k, _)| k.clone());
+
+    if let Some(oldest) = oldest {
+        uploads.remove(&oldest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use client::Client;
+    use endpoint::Endpoint;
+    use freshness::RequireFresh;
+    use server::Server;
+    use sim::{Config, Network, Simulation};
+
+    fn echo_body() -> Reassemble<impl Handler> {
+        Reassemble::new(|req: Request| {
+            Ok(Message::new().with_code(Code::Changed).with_payload(req.message.payload))
+        })
+    }
+
+    fn block(num: u32, more: bool, tag: &[u8], payload: &[u8]) -> Request {
+        let mut message = Message::new().with_code(Code::Put).with_payload(payload.to_vec());
+        message.options.push_raw(Block1::NUMBER, BlockValue::new(num, more, 16).to_bytes());
+        message.options.push_raw(RequestTag::NUMBER, tag.to_vec());
+
+        Request { message, source: "192.0.2.1:5683".parse().unwrap() }
+    }
+
+    #[test]
+    fn block_value_round_trip() {
+        let value = BlockValue { num: 5, more: true, szx: 6 };
+
+        assert_eq!(value.to_bytes(), vec![0x5e]);
+        assert_eq!(BlockValue::from_bytes(&value.to_bytes()), Some(value));
+        assert_eq!(BlockValue::from_bytes(&[]), Some(BlockValue { num: 0, more: false, szx: 0 }));
+        assert_eq!(BlockValue::from_bytes(&[0x07]), None);
+        assert_eq!(BlockValue::new(0, false, 100).size(), 64);
+        assert_eq!(BlockValue::new(0, false, 4096).size(), 1024);
+    }
+
+    #[test]
+    fn request_tags_keep_uploads_apart() {
+        let handler = echo_body();
+
+        let a = [b'a'; 16];
+        let b = [b'b'; 16];
+
+        assert_eq!(handler.handle(block(0, true, &[1], &a)).wait().unwrap().code, Code::Continue);
+        assert_eq!(handler.handle(block(0, true, &[2], &b)).wait().unwrap().code, Code::Continue);
+
+        let response = handler.handle(block(1, false, &[2], b"end")).wait().unwrap();
+        assert_eq!(response.payload, [&b[..], b"end"].concat());
+
+        let response = handler.handle(block(1, false, &[1], b"end")).wait().unwrap();
+        assert_eq!(response.code, Code::Changed);
+        assert_eq!(response.payload, [&a[..], b"end"].concat());
+    }
+
+    #[test]
+    fn out_of_order_block_rejected() {
+        let handler = echo_body();
+
+        assert_eq!(handler.handle(block(0, true, &[1], &[0; 16])).wait().unwrap().code, Code::Continue);
+        assert_eq!(handler.handle(block(2, false, &[1], b"end")).wait().unwrap().code, Code::RequestEntityIncomplete);
+        assert_eq!(handler.handle(block(1, false, &[1], b"end")).wait().unwrap().code, Code::RequestEntityIncomplete);
+    }
+
+    #[test]
+    fn body_size_limited() {
+        let handler = echo_body().with_max_body_size(20);
+
+        assert_eq!(handler.handle(block(0, true, &[], &[0; 16])).wait().unwrap().code, Code::Continue);
+
+        let response = handler.handle(block(1, true, &[], &[0; 16])).wait().unwrap();
+        assert_eq!(response.code, Code::RequestEntityTooLarge);
+        assert_eq!(response.options.get_raw::<Size1>(), Some(vec![vec![20]]));
+    }
+
+    #[test]
+    fn client_adopts_smaller_blocks() {
+        let net = Network::new(Config::new());
+        let server_addr = net.bind_any().unwrap().local_addr();
+        let client_socket = net.bind_any().unwrap();
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let log = received.clone();
+
+        // asks for 256 byte blocks with a 4.13, then for 64 byte blocks with
+        // a 2.31
+        let exchange = move |transport, request: Message, _| -> IoFuture<(Message, _)> {
+            let block = request.options.map.get(&Block1::NUMBER)
+                .and_then(|values| BlockValue::from_bytes(&values[0]))
+                .unwrap();
+            log.lock().unwrap().push((block, request.payload.clone()));
+
+            let (code, szx) = match block.szx {
+                6 => (Code::RequestEntityTooLarge, Some(4)),
+                4 => (Code::Continue, Some(2)),
+                _ if block.more => (Code::Continue, None),
+                _ => (Code::Changed, None),
+            };
+            let mut response = Message::new().with_code(code);
+            if let Some(szx) = szx {
+                response.options.push_raw(Block1::NUMBER, BlockValue { num: 0, more: false, szx }.to_bytes());
+            }
+            Box::new(future::ok((response, transport)))
+        };
+
+        let payload: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        let message = Message::new().with_code(Code::Put).with_payload(payload.clone());
+        let upload = upload(client_socket, message, server_addr, 1024, exchange);
+        let (response, _) = Simulation::new(net).run(upload).unwrap();
+
+        assert_eq!(response.code, Code::Changed);
+
+        let received = received.lock().unwrap();
+        let blocks: Vec<(u32, u8)> = received.iter().map(|(block, _)| (block.num, block.szx)).collect();
+        assert_eq!(blocks, vec![(0, 6), (0, 4), (4, 2), (5, 2), (6, 2), (7, 2), (8, 2), (9, 2)]);
+
+        let body: Vec<u8> = received[1..].iter().flat_map(|(_, payload)| payload.clone()).collect();
+        assert_eq!(body, payload);
+    }
+
+    #[test]
+    fn client_uploads_in_blocks() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+
+        let net = Network::new(Config::new());
+        let server_socket = net.bind_any().unwrap();
+        let endpoint = Endpoint::Resolved(server_socket.local_addr());
+        let client_socket = net.bind_any().unwrap();
+
+        // every block has to prove its freshness
+        let handler = RequireFresh::new(Reassemble::new(move |req: Request| {
+            counted.fetch_add(1, Ordering::SeqCst);
+            Ok(Message::new().with_code(Code::Changed).with_payload(req.message.payload))
+        }));
+
+        let mut sim = Simulation::new(net);
+        sim.spawn(Server::new(handler).serve(server_socket).map_err(|_| ()));
+
+        let payload: Vec<u8> = (0..100).collect();
+        let client = Client::new()
+            .with_endpoint(endpoint)
+            .with_payload(payload.clone())
+            .with_block_size(32);
+        let response = sim.run(client.send_over(client_socket)).unwrap();
+
+        assert_eq!(response.code, Code::Changed);
+        assert_eq!(response.payload, payload);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,339 @@
+//! A client for synchronous code.
+//!
+//! Each request blocks the calling thread until its response arrives or its
+//! timeout passes, so simple programs and tests don't have to set up a
+//! runtime of their own:
+//!
+//! ```no_run
+//! let response = tokio_coap::blocking::get("coap://coap.sh/ip").unwrap();
+//! println!("{}", String::from_utf8_lossy(&response.payload));
+//! ```
+//!
+//! Requests are carried out by the asynchronous `Client`, on a runtime of
+//! the crate's own running on background threads, so they behave just the
+//! same, only waiting for them is different.
+
+use std::io;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+use futures::prelude::*;
+use futures::future;
+use futures::sync::oneshot;
+
+use client;
+use error::Error;
+use message::Message;
+use util::background;
+
+/// How long to wait for a response unless told otherwise.
+pub const DEFAULT_TIMEOUT: Duration = client::DEFAULT_TIMEOUT;
+
+/// Send a GET request to `url` and wait for the response.
+pub fn get(url: &str) -> Result<Message, Error> {
+    Client::new().get(url)
+}
+
+/// Send a POST request with `payload` to `url` and wait for the response.
+pub fn post(url: &str, payload: Vec<u8>) -> Result<Message, Error> {
+    Client::new().post(url, payload)
+}
+
+/// Send a PUT request with `payload` to `url` and wait for the response.
+pub fn put(url: &str, payload: Vec<u8>) -> Result<Message, Error> {
+    Client::new().put(url, payload)
+}
+
+/// Send a DELETE request to `url` and wait for the response.
+pub fn delete(url: &str) -> Result<Message, Error> {
+    Client::new().delete(url)
+}
+
+/// Observe the resource at `url`, see `Client::observe`.
+pub fn observe(url: &str) -> Result<Observation, Error> {
+    Client::new().observe(url)
+}
+
+/// Sends requests and waits for their responses.
+///
+/// Requests that need more than a method, URL and payload are set up with
+/// the asynchronous `Client` and handed to `send` or `send_observe`.
+#[derive(Debug, Clone)]
+pub struct Client {
+    /// how long to wait for a response
+    timeout: Duration,
+}
+
+impl Default for Client {
+    fn default() -> Client {
+        Client::new()
+    }
+}
+
+impl Client {
+    pub fn new() -> Client {
+        Client {
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Give up waiting for a response after `timeout`, with `Error::Timeout`,
+    /// or on an endpoint with several addresses, move on to the next.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.set_timeout(timeout);
+
+        self
+    }
+
+    pub fn get(&self, url: &str) -> Result<Message, Error> {
+        self.send(client::Client::get(url)?)
+    }
+
+    pub fn post(&self, url: &str, payload: Vec<u8>) -> Result<Message, Error> {
+        self.send(client::Client::post(url)?.with_payload(payload))
+    }
+
+    pub fn put(&self, url: &str, payload: Vec<u8>) -> Result<Message, Error> {
+        self.send(client::Client::put(url)?.with_payload(payload))
+    }
+
+    pub fn delete(&self, url: &str) -> Result<Message, Error> {
+        self.send(client::Client::delete(url)?)
+    }
+
+    /// Send `request` and wait for the response, giving up on each address
+    /// of the endpoint after the timeout.
+    pub fn send(&self, request: client::Client) -> Result<Message, Error> {
+        let request = request.with_timeout(self.timeout);
+
+        wait(future::lazy(move || request.send()))
+    }
+
+    /// Register interest in the resource at `url` and wait for the
+    /// response, see `send_observe`.
+    pub fn observe(&self, url: &str) -> Result<Observation, Error> {
+        self.send_observe(client::Client::get(url)?)
+    }
+
+    /// Register interest with `request` and wait for the response, which is
+    /// the first item of the returned `Observation`. Only the response is
+    /// subject to the timeout, notifications may take as long as they like.
+    pub fn send_observe(&self, request: client::Client) -> Result<Observation, Error> {
+        let request = request.with_timeout(self.timeout);
+        let (cancel, cancelled) = oneshot::channel();
+
+        let (response, notifications) = wait(future::lazy(move || {
+            request.observe_until(cancelled.map_err(|_| ()))
+                .into_future()
+                .map_err(|(e, _)| e)
+        }))?;
+        let response = response.ok_or_else(|| {
+            Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "no response to the registration"))
+        })?;
+
+        let (tx, rx) = mpsc::channel();
+        let forward = notifications.then(Ok).for_each(move |notification: Result<Message, Error>| {
+            let failed = notification.is_err();
+            tx.send(notification).map_err(|_| ())?;
+            if failed { Err(()) } else { Ok(()) }
+        });
+        background().executor().spawn(forward);
+
+        Ok(Observation {
+            response: Some(response),
+            notifications: rx,
+            _cancel: cancel,
+        })
+    }
+}
+
+/// Run `future` on the background runtime and wait for its outcome.
+fn wait<F>(future: F) -> Result<F::Item, Error>
+    where F: Future<Error = Error> + Send + 'static,
+          F::Item: Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+    background().executor().spawn(future.then(move |outcome| {
+        let _ = tx.send(outcome);
+        Ok(())
+    }));
+
+    rx.recv().unwrap_or_else(|_| Err(Error::Io(io::Error::other("background runtime shut down"))))
+}
+
+/// The response to an Observe registration followed by every notification,
+/// as long as the server keeps sending them.
+///
+/// Iterating waits for the next notification for however long it takes,
+/// `next_timeout` gives up after a while. Dropping the observation cancels
+/// the registration right away.
+pub struct Observation {
+    response: Option<Message>,
+    notifications: mpsc::Receiver<Result<Message, Error>>,
+    /// cancels the registration when dropped
+    _cancel: oneshot::Sender<()>,
+}
+
+impl Observation {
+    /// Wait at most `timeout` for the next notification.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Option<Result<Message, Error>> {
+        if let Some(response) = self.response.take() {
+            return Some(Ok(response));
+        }
+
+        match self.notifications.recv_timeout(timeout) {
+            Ok(notification) => Some(notification),
+            Err(RecvTimeoutError::Timeout) => Some(Err(Error::Timeout)),
+            Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+}
+
+impl Iterator for Observation {
+    type Item = Result<Message, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(response) = self.response.take() {
+            return Some(Ok(response));
+        }
+
+        self.notifications.recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net;
+    use std::thread;
+
+    use tokio::net::{UdpFramed, UdpSocket};
+
+    use codec::CoapCodec;
+    use message::{Code, Mtype};
+    use server::{Request, Server};
+
+    #[test]
+    fn requests() {
+        let socket = UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = socket.local_addr().unwrap();
+        let handler = |request: Request| {
+            let code = match request.message.code {
+                Code::Get => Code::Content,
+                Code::Put => Code::Changed,
+                _ => Code::MethodNotAllowed,
+            };
+            Ok(Message::new().with_code(code).with_payload(request.message.payload))
+        };
+        background().executor().spawn(Server::new(handler).serve(UdpFramed::new(socket, CoapCodec)).map_err(|_| ()));
+
+        let url = format!("coap://{}/mode", addr);
+        assert_eq!(get(&url).unwrap().code, Code::Content);
+        let response = put(&url, b"eco".to_vec()).unwrap();
+        assert_eq!(response.code, Code::Changed);
+        assert_eq!(response.payload, b"eco");
+        assert_eq!(delete(&url).unwrap().code, Code::MethodNotAllowed);
+
+        // nothing ever answers here
+        let silent = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = Client::new().with_timeout(Duration::from_millis(100));
+        match client.get(&format!("coap://{}/mode", silent.local_addr().unwrap())) {
+            Err(Error::Timeout) => {}
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn slow_server() {
+        let socket = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let mut buf = [0; 1152];
+            let (len, client) = socket.recv_from(&mut buf).unwrap();
+            let request = Message::from_bytes(&buf[..len]).unwrap();
+
+            thread::sleep(Duration::from_millis(1500));
+            let response = request.new_reply().with_code(Code::Content);
+            socket.send_to(&response.to_bytes().unwrap(), client).unwrap();
+        });
+
+        let client = Client::new().with_timeout(Duration::from_secs(3));
+        assert_eq!(client.get(&format!("coap://{}/slow", addr)).unwrap().code, Code::Content);
+    }
+
+    #[test]
+    fn silent_address_falls_back() {
+        use std::sync::Arc;
+
+        use resolver::StaticResolver;
+
+        let socket = UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let handler = |_: Request| Ok(Message::new().with_code(Code::Content));
+        background().executor().spawn(Server::new(handler).serve(UdpFramed::new(socket, CoapCodec)).map_err(|_| ()));
+
+        // bound, so nothing refuses the request, but never answering
+        let _silent = net::UdpSocket::bind(("127.0.0.2", port)).unwrap();
+
+        let hosts = StaticResolver::new()
+            .with_host("sensor.test", vec!["127.0.0.2".parse().unwrap(), "127.0.0.1".parse().unwrap()]);
+        let request = client::Client::get(&format!("coap://sensor.test:{}/temp", port)).unwrap()
+            .with_resolver(Arc::new(hosts));
+        let client = Client::new().with_timeout(Duration::from_millis(300));
+
+        assert_eq!(client.send(request).unwrap().code, Code::Content);
+    }
+
+    #[test]
+    fn observation() {
+        let socket = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let mut buf = [0; 1152];
+            let (len, client) = socket.recv_from(&mut buf).unwrap();
+            let request = Message::from_bytes(&buf[..len]).unwrap();
+            assert_eq!(request.observe(), Ok(Some(0)));
+
+            let response = request.new_reply()
+                .with_code(Code::Content)
+                .with_observe(1)
+                .with_payload(b"21.5".to_vec());
+            socket.send_to(&response.to_bytes().unwrap(), client).unwrap();
+
+            let notification = Message::new()
+                .with_mtype(Mtype::NonConfirmable)
+                .with_mid(request.mid.wrapping_add(1))
+                .with_token(&request.token)
+                .with_code(Code::Content)
+                .with_observe(2)
+                .with_payload(b"22.5".to_vec());
+            socket.send_to(&notification.to_bytes().unwrap(), client).unwrap();
+
+            socket.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+            let (len, _) = socket.recv_from(&mut buf).unwrap();
+            let deregistration = Message::from_bytes(&buf[..len]).unwrap();
+            assert_eq!(deregistration.observe(), Ok(Some(1)));
+            assert_eq!(deregistration.token, request.token);
+        });
+
+        let mut observation = observe(&format!("coap://{}/temp", addr)).unwrap();
+
+        assert_eq!(observation.next().unwrap().unwrap().payload, b"21.5");
+        assert_eq!(observation.next_timeout(Duration::from_secs(1)).unwrap().unwrap().payload, b"22.5");
+        match observation.next_timeout(Duration::from_millis(50)) {
+            Some(Err(Error::Timeout)) => {}
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+
+        // the server hears of it without sending another notification
+        drop(observation);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,372 @@
+//! A client-side response cache (RFC 7252: 5.6).
+//!
+//! Responses to GET requests are stored under the cache key of the request
+//! for as long as their Max-Age says. A stale response with an ETag is
+//! revalidated rather than fetched again: if the server answers 2.03 Valid
+//! the stored representation is served with its new Max-Age. Successful
+//! unsafe requests invalidate what is stored for their target.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::prelude::*;
+use futures::future;
+
+use client::{IoFuture, Transport};
+use message::{Code, Message};
+use message::option::{self, ETag, Option};
+
+/// How many responses are stored unless told otherwise.
+pub const DEFAULT_CAPACITY: usize = 128;
+
+/// The options making up the target URI of a request.
+const URI_OPTIONS: [u16; 4] = [3, 7, 11, 15];
+
+/// RFC 7252: 5.6.  Caching
+///
+/// The request method, the remote endpoint and every option of the request
+/// that is not marked NoCacheKey.
+type Key = (SocketAddr, Code, Vec<(u16, Vec<u8>)>);
+
+struct Entry {
+    response: Message,
+    stored: Instant,
+    max_age: Duration,
+}
+
+impl Entry {
+    fn is_fresh(&self, now: Instant) -> bool {
+        now.duration_since(self.stored) < self.max_age
+    }
+
+    /// RFC 7252: 5.6.1.  Freshness Model
+    ///
+    /// The stored response with its Max-Age less the whole seconds it has
+    /// been stored, so whoever it is passed on to doesn't keep it longer.
+    fn aged_response(&self, now: Instant) -> Message {
+        let age = now.duration_since(self.stored).as_secs();
+
+        let mut response = self.response.clone();
+        response.set_max_age(self.max_age.as_secs().saturating_sub(age) as u32);
+        response
+    }
+}
+
+/// Responses stored by one or more clients.
+pub struct Cache {
+    entries: HashMap<Key, Entry>,
+    capacity: usize,
+}
+
+impl Cache {
+    pub fn new() -> Cache {
+        Cache {
+            entries: HashMap::new(),
+            capacity: DEFAULT_CAPACITY,
+        }
+    }
+
+    /// Store at most `capacity` responses, dropping those expiring first
+    /// when full.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// How many responses are stored, fresh or stale.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn store(&mut self, key: Key, response: Message) {
+        let max_age = Duration::from_secs(u64::from(response.max_age().unwrap_or(0)));
+        let has_etag = response.etag().ok().and_then(|etag| etag).is_some();
+
+        // a response that is stale right away is only of use if it can be
+        // revalidated
+        if (max_age == Duration::from_secs(0) && !has_etag) || self.capacity == 0 {
+            self.entries.remove(&key);
+            return;
+        }
+
+        let now = Instant::now();
+        if !self.entries.contains_key(&key) && self.entries.len() >= self.capacity {
+            let first_to_expire = self.entries
+                .iter()
+                .min_by_key(|&(_, entry)| entry.stored + entry.max_age)
+                .map(|(key, _)| key.clone());
+            if let Some(first_to_expire) = first_to_expire {
+                self.entries.remove(&first_to_expire);
+            }
+        }
+
+        self.entries.insert(key, Entry { response, stored: now, max_age });
+    }
+
+    /// RFC 7252: 5.9.  Response Code Semantics
+    ///
+    /// Drop every response stored for the target of `request`.
+    fn invalidate(&mut self, remote_addr: SocketAddr, request: &Message) {
+        let target = uri_options(&request.options.map);
+
+        self.entries.retain(|&(addr, _, ref options), _| {
+            addr != remote_addr || options.iter().filter(|&&(n, _)| URI_OPTIONS.contains(&n)).ne(target.iter())
+        });
+    }
+}
+
+impl Default for Cache {
+    fn default() -> Cache {
+        Cache::new()
+    }
+}
+
+/// The options of a request making up its target URI, in order.
+pub(crate) fn uri_options(options: &BTreeMap<u16, Vec<Vec<u8>>>) -> Vec<(u16, Vec<u8>)> {
+    options.iter()
+        .filter(|&(n, _)| URI_OPTIONS.contains(n))
+        .flat_map(|(&n, values)| values.iter().map(move |v| (n, v.clone())))
+        .collect()
+}
+
+fn cache_key(remote_addr: SocketAddr, request: &Message) -> Key {
+    let options = request.options.map
+        .iter()
+        .filter(|&(&n, _)| !option::is_no_cache_key(n))
+        .flat_map(|(&n, values)| values.iter().map(move |v| (n, v.clone())))
+        .collect();
+
+    (remote_addr, request.code, options)
+}
+
+/// Whether requests with this method change the state of the server.
+fn is_unsafe(code: Code) -> bool {
+    matches!(code, Code::Post | Code::Put | Code::Delete | Code::Patch | Code::IPatch)
+}
+
+/// Exchange `msg` using `exchange`, unless a fresh response to it is stored
+/// in `cache`.
+pub(crate) fn exchange<T, F>(
+    transport: T,
+    mut msg: Message,
+    remote_addr: SocketAddr,
+    cache: Arc<Mutex<Cache>>,
+    exchange: F,
+) -> IoFuture<(Message, T)>
+    where T: Transport,
+          F: Fn(T, Message, SocketAddr) -> IoFuture<(Message, T)> + Send + 'static,
+{
+    if is_unsafe(msg.code) {
+        let request = msg.clone();
+        return Box::new(exchange(transport, msg, remote_addr).map(move |(response, transport)| {
+            if response.code.is_success() {
+                cache.lock().unwrap().invalidate(remote_addr, &request);
+            }
+            (response, transport)
+        }));
+    }
+
+    if msg.code != Code::Get {
+        return exchange(transport, msg, remote_addr);
+    }
+
+    let key = cache_key(remote_addr, &msg);
+
+    // the stored response if it is stale and can be revalidated
+    let stale = match cache.lock().unwrap().entries.get(&key) {
+        Some(entry) if entry.is_fresh(Instant::now()) => {
+            debug!("serving response from cache");
+            return Box::new(future::ok((entry.aged_response(Instant::now()), transport)));
+        }
+        Some(entry) => entry.response.etag().ok().and_then(|etag| etag).map(|etag| (etag, entry.response.clone())),
+        None => None,
+    };
+
+    // RFC 7252: 5.10.6.2.  ETag as a Request Option
+    if let Some((ref etag, _)) = stale {
+        if !msg.options.map.contains_key(&ETag::NUMBER) {
+            debug!("revalidating stale response");
+            msg.options.push(ETag::new(etag.clone()));
+        }
+    }
+
+    let response = exchange(transport, msg, remote_addr).map(move |(response, transport)| {
+        let mut cache = cache.lock().unwrap();
+
+        let response = match (response.code, stale) {
+            (Code::Valid, Some((etag, mut stored))) if response.etag() == Ok(Some(etag.clone())) => {
+                stored.set_max_age(response.max_age().unwrap_or(0));
+                cache.store(key, stored.clone());
+                stored
+            }
+            (Code::Content, _) => {
+                cache.store(key, response.clone());
+                response
+            }
+            _ => response,
+        };
+
+        (response, transport)
+    });
+
+    Box::new(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use client::Client;
+    use server::{Handler, Request, Server};
+    use sim::{Config, Network, Simulation};
+
+    /// Send each request to a server answering with `handler`, all through
+    /// the same cache.
+    fn run<H: Handler>(handler: H, requests: &[(Code, &str)]) -> Vec<Message> {
+        let net = Network::new(Config::new());
+        let server_socket = net.bind_any().unwrap();
+        let server_addr = server_socket.local_addr();
+
+        let mut sim = Simulation::new(net.clone());
+        sim.spawn(Server::new(handler).serve(server_socket).map_err(|_| ()));
+
+        let cache = Arc::new(Mutex::new(Cache::new()));
+        requests.iter().map(|&(method, path)| {
+            let url = format!("coap://{}{}", server_addr, path);
+            let client = match method {
+                Code::Get => Client::get(&url),
+                Code::Put => Client::put(&url),
+                _ => panic!("unexpected request {:?}", method),
+            };
+
+            let client = client.unwrap().with_cache(cache.clone());
+            sim.run(client.send_over(net.bind_any().unwrap())).unwrap()
+        }).collect()
+    }
+
+    #[test]
+    fn fresh_response_served_locally() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let handler = move |_: Request| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(Message::new().with_code(Code::Content).with_payload(b"22.5".to_vec()))
+        };
+
+        let responses = run(handler, &[(Code::Get, "/temp"), (Code::Get, "/temp"), (Code::Get, "/humidity")]);
+
+        assert!(responses.iter().all(|r| r.code == Code::Content && r.payload == b"22.5"));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn served_with_remaining_max_age() {
+        let net = Network::new(Config::new());
+        let server_addr = net.bind_any().unwrap().local_addr();
+        let request = Message::new().with_code(Code::Get);
+
+        let mut cache = Cache::new();
+        let key = cache_key(server_addr, &request);
+        cache.store(key.clone(), Message::new().with_code(Code::Content).with_max_age(60));
+        cache.entries.get_mut(&key).unwrap().stored -= Duration::from_secs(20);
+
+        let not_sent = |_, _, _| -> IoFuture<(Message, _)> { panic!("fresh response not served from cache") };
+        let cache = Arc::new(Mutex::new(cache));
+        let (response, _) = exchange(net.bind_any().unwrap(), request, server_addr, cache, not_sent).wait().unwrap();
+
+        assert_eq!(response.code, Code::Content);
+        assert_eq!(response.max_age(), Ok(40));
+    }
+
+    #[test]
+    fn stale_response_revalidated() {
+        let revalidated = Arc::new(AtomicUsize::new(0));
+        let counter = revalidated.clone();
+        let handler = move |request: Request| {
+            let mut response = if request.message.etag().unwrap() == Some(b"v1".to_vec()) {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Message::new().with_code(Code::Valid)
+            } else {
+                Message::new().with_code(Code::Content).with_payload(b"22.5".to_vec())
+            };
+            response.set_etag(b"v1").unwrap();
+            Ok(response.with_max_age(0))
+        };
+
+        let responses = run(handler, &[(Code::Get, "/temp"), (Code::Get, "/temp")]);
+
+        assert_eq!(responses[1].code, Code::Content);
+        assert_eq!(responses[1].payload, b"22.5");
+        assert_eq!(revalidated.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn valid_refreshes_stored_response() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let handler = move |request: Request| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let mut response = match request.message.etag().unwrap() {
+                Some(ref etag) if etag == b"v1" => Message::new().with_code(Code::Valid).with_max_age(60),
+                _ => Message::new().with_code(Code::Content).with_payload(b"22.5".to_vec()).with_max_age(0),
+            };
+            response.set_etag(b"v1").unwrap();
+            Ok(response)
+        };
+
+        let responses = run(handler, &[(Code::Get, "/temp"), (Code::Get, "/temp"), (Code::Get, "/temp")]);
+
+        // the stored representation is served with the new Max-Age, fresh
+        // again until it runs out
+        assert!(responses.iter().all(|r| r.code == Code::Content && r.payload == b"22.5"));
+        assert_eq!(responses[2].max_age(), Ok(60));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn uncacheable_response_not_stored() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let handler = move |_: Request| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(Message::new().with_code(Code::Created).with_max_age(60))
+        };
+
+        let responses = run(handler, &[(Code::Get, "/temp"), (Code::Get, "/temp")]);
+
+        assert!(responses.iter().all(|r| r.code == Code::Created));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn unsafe_request_invalidates() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let handler = move |request: Request| {
+            let code = match request.message.code {
+                Code::Get => {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    Code::Content
+                }
+                _ => Code::Changed,
+            };
+            Ok(Message::new().with_code(code))
+        };
+
+        run(handler, &[(Code::Get, "/config"), (Code::Get, "/config"), (Code::Put, "/config"), (Code::Get, "/config")]);
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
@@ -1,99 +1,98 @@
+use block;
+use cache::{self, Cache};
 use codec::CoapCodec;
 use Endpoint;
+use freshness;
 use error::{Error, UrlError};
-use message::{Message, Code};
-use message::option::{Option, Options, UriPath, UriHost, UriQuery};
+use message::{Message, Code, ContentFormat, Mtype};
+use message::option::{UriPath, UriHost, UriPort, UriQuery};
+use oscore::{self, SecurityContext};
+use proxy;
+use endpoint::prefer_family;
+use resolver::Resolver;
+use uri::{decompose, CoapUri, Scheme};
 
-use std::borrow::Cow;
-use std::net::Ipv4Addr;
+use std::io;
+use std::option::Option as StdOption;
+use std::net::{self, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use futures::prelude::*;
+use futures::future::{self, Either, Loop};
+use futures::stream;
 
 use tokio::net::{UdpSocket, UdpFramed};
-use tokio::util::FutureExt;
+use tokio::reactor::Handle;
+use tokio::timer::Delay;
 
-use percent_encoding::percent_decode;
 use url::Url;
 
+use util::random_u64;
+
+/// RFC 7252: 4.8.  Transmission Parameters
+const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_RETRANSMIT: u32 = 4;
+
+/// RFC 7252: 4.8.2.  Time Values Derived from Transmission Parameters
+///
+/// ACK_TIMEOUT * ((2 ** (MAX_RETRANSMIT + 1)) - 1) * ACK_RANDOM_FACTOR
+pub const MAX_TRANSMIT_WAIT: Duration = Duration::from_secs(93);
+
+/// How long a request waits for a response from each address unless told
+/// otherwise, long enough for every retransmission.
+pub const DEFAULT_TIMEOUT: Duration = MAX_TRANSMIT_WAIT;
+
 /// An alias for the futures produced by this library.
 pub type IoFuture<T> = Box<Future<Item = T, Error = Error> + Send>;
 
+/// An alias for the streams produced by this library.
+pub type IoStream<T> = Box<dyn Stream<Item = T, Error = Error> + Send>;
+
+/// Something messages can be sent over and received from, such as a framed
+/// UDP socket or a simulated socket.
+pub trait Transport
+    : Stream<Item = (Message, SocketAddr), Error = Error>
+    + Sink<SinkItem = (Message, SocketAddr), SinkError = Error>
+    + Send + 'static
+{
+    /// A future completing once `duration` has passed on the clock of the
+    /// transport, which times retransmissions and timeouts.
+    ///
+    /// By default that is the tokio timer, so the transport has to be used
+    /// on a tokio runtime.
+    fn sleep(&self, duration: Duration) -> IoFuture<()> {
+        Box::new(Delay::new(Instant::now() + duration).map_err(|e| Error::Io(io::Error::other(e))))
+    }
+}
+
+impl Transport for UdpFramed<CoapCodec> {}
+
 pub struct Client {
     /// the remote endpoint to contact
     endpoint: Endpoint,
     /// the message to be sent
     msg: Message,
+    /// how the message is to be sent
+    settings: Settings,
 }
 
-fn depercent(s: &str) -> Result<String, UrlError> {
-    percent_decode(s.as_bytes())
-        .decode_utf8()
-        .map(Cow::into_owned)
-        .map_err(UrlError::NonUtf8)
-}
-
-/// RFC 7252: 6.4.  Decomposing URIs into Options
-fn decompose(url: &Url) -> Result<(Endpoint, Options), UrlError> {
-    use url::Host;
-
-    let mut options = Options::new();
-
-    // Step 3, TODO: Support coaps
-    match url.scheme() {
-        "coap" => (),
-        other => Err(UrlError::UnsupportedScheme(other.to_string()))?,
-    }
-
-    // Step 4
-    if url.fragment().is_some() {
-        Err(UrlError::FragmentSpecified)?;
-    }
-
-    // Step 6
-    let port = url.port().unwrap_or(5683);
-
-    // Step 5
-    let endpoint = match url.host().ok_or(UrlError::NonAbsolutePath)? {
-        Host::Domain(domain) => {
-            // ! gross hack warning !
-            // The URL standard from whatwg (which the url crate follows) specifies that you try to
-            // parse an IPv6 address no matter whatm but you only try to parse an IPv4 address from
-            // a set of "special" url schemes that it defines. *Shockingly* coap isn't one of them.
-            // See https://url.spec.whatwg.org/#host-parsing
-            // and https://url.spec.whatwg.org/#url-miscellaneous
-            //
-            // This forces us to try to parse any domain name as an IPv4 address here to comply
-            // with the coap spec.
-
-            if let Ok(ip) = domain.parse::<Ipv4Addr>() {
-                Endpoint::Resolved((ip, port).into())
-            } else {
-                let host = domain.to_lowercase();
-                options.push(UriHost::new(host.clone()));
-                Endpoint::Unresolved(host, port)
-            }
-        },
-        Host::Ipv4(ip) => Endpoint::Resolved((ip, port).into()),
-        Host::Ipv6(ip) => Endpoint::Resolved((ip, port).into()),
-    };
-
-    // Step 8
-    if url.path() != "" && url.path() != "/" {
-        for segment in url.path_segments().ok_or(UrlError::NonAbsolutePath)? {
-            options.push(UriPath::new(depercent(segment)?));
-        }
-    }
-
-    // Step 9
-    let query = url.query().unwrap_or("");
-    if !query.is_empty() {
-        for segment in query.split('&') {
-            options.push(UriQuery::new(depercent(segment)?));
-        }
-    }
-
-    Ok((endpoint, options))
+#[derive(Clone)]
+struct Settings {
+    /// the security context to protect the request with, if any
+    oscore: StdOption<Arc<Mutex<SecurityContext>>>,
+    /// payloads larger than this are sent in blocks
+    block_size: usize,
+    /// the cache to serve responses from and store them in, if any
+    cache: StdOption<Arc<Mutex<Cache>>>,
+    /// the forward proxy to send the request through, if any
+    proxy: StdOption<Endpoint>,
+    /// what looks up the addresses of the endpoint, the default if none
+    resolver: StdOption<Arc<dyn Resolver>>,
+    /// the address to send from, any of the family of the remote if none
+    local_addr: StdOption<SocketAddr>,
+    /// how long to wait for a response from each address
+    timeout: Duration,
 }
 
 impl Client {
@@ -101,21 +100,96 @@ impl Client {
         Client {
             endpoint: Endpoint::Unset,
             msg: Message::new(),
+            settings: Settings {
+                oscore: None,
+                block_size: block::DEFAULT_BLOCK_SIZE,
+                cache: None,
+                proxy: None,
+                resolver: None,
+                local_addr: None,
+                timeout: DEFAULT_TIMEOUT,
+            },
         }
     }
 
     pub fn get(url: &str) -> Result<Client, Error> {
+        Client::from_url(Code::Get, url)
+    }
+
+    pub fn post(url: &str) -> Result<Client, Error> {
+        Client::from_url(Code::Post, url)
+    }
+
+    pub fn put(url: &str) -> Result<Client, Error> {
+        Client::from_url(Code::Put, url)
+    }
+
+    pub fn delete(url: &str) -> Result<Client, Error> {
+        Client::from_url(Code::Delete, url)
+    }
+
+    fn from_url(method: Code, url: &str) -> Result<Client, Error> {
         let mut client = Client::new();
         let url = Url::parse(url).map_err(UrlError::Parse)?;
 
         let (endpoint, options) = decompose(&url)?;
 
         client.set_endpoint(endpoint);
+        client.msg.code = method;
         client.msg.options = options;
 
         Ok(client)
     }
 
+    /// A client performing a request with `method` on `uri`.
+    pub fn request(method: Code, uri: &CoapUri) -> Result<Client, Error> {
+        let mut client = Client::new();
+        client.msg.code = method;
+        client.set_uri(uri)?;
+
+        Ok(client)
+    }
+
+    /// Direct the request at `uri`, replacing its endpoint and Uri-Host,
+    /// Uri-Port, Uri-Path and Uri-Query options.
+    ///
+    /// Only `coap` URIs can be sent to.
+    pub fn set_uri(&mut self, uri: &CoapUri) -> Result<(), Error> {
+        if uri.scheme() != Scheme::Coap {
+            return Err(UrlError::UnsupportedScheme(uri.scheme().to_string()).into());
+        }
+
+        let (endpoint, options) = uri.decompose();
+        self.msg.options.remove::<UriHost>();
+        self.msg.options.remove::<UriPort>();
+        self.msg.options.remove::<UriPath>();
+        self.msg.options.remove::<UriQuery>();
+        self.msg.options.map.extend(options.map);
+        self.set_endpoint(endpoint);
+
+        Ok(())
+    }
+
+    pub fn with_uri(mut self, uri: &CoapUri) -> Result<Self, Error> {
+        self.set_uri(uri)?;
+
+        Ok(self)
+    }
+
+    /// The URI the request is directed at, if it has an endpoint.
+    pub fn uri(&self) -> Result<CoapUri, Error> {
+        CoapUri::from_parts(Scheme::Coap, &self.endpoint, &self.msg.options)
+    }
+
+    /// A client sending `msg` to `endpoint` as it is.
+    pub(crate) fn from_message(endpoint: Endpoint, msg: Message) -> Client {
+        Client {
+            endpoint,
+            msg,
+            ..Client::new()
+        }
+    }
+
     pub fn set_endpoint(&mut self, endpoint: Endpoint) {
         self.endpoint = endpoint;
     }
@@ -126,48 +200,663 @@ impl Client {
         self
     }
 
+    pub fn set_payload(&mut self, payload: Vec<u8>) {
+        self.msg.payload = payload;
+    }
+
+    pub fn with_payload(mut self, payload: Vec<u8>) -> Self {
+        self.set_payload(payload);
+
+        self
+    }
+
+    /// Set the Content-Format of the payload.
+    pub fn set_content_format(&mut self, format: ContentFormat) {
+        self.msg.set_content_format(format);
+    }
+
+    pub fn with_content_format(mut self, format: ContentFormat) -> Self {
+        self.set_content_format(format);
+
+        self
+    }
+
+    /// Only perform the request if the target has this ETag, or, if it is
+    /// empty, exists at all. May be given several times to allow any of
+    /// several ETags.
+    pub fn set_if_match(&mut self, etag: &[u8]) -> Result<(), Error> {
+        Ok(self.msg.add_if_match(etag)?)
+    }
+
+    pub fn with_if_match(mut self, etag: &[u8]) -> Result<Self, Error> {
+        self.set_if_match(etag)?;
+
+        Ok(self)
+    }
+
+    /// Only perform the request if the target doesn't exist yet.
+    pub fn set_if_none_match(&mut self) {
+        self.msg.set_if_none_match(true);
+    }
+
+    pub fn with_if_none_match(mut self) -> Self {
+        self.set_if_none_match();
+
+        self
+    }
+
+    /// Ask for a response in this Content-Format.
+    pub fn set_accept(&mut self, format: ContentFormat) {
+        self.msg.set_accept(format);
+    }
+
+    pub fn with_accept(mut self, format: ContentFormat) -> Self {
+        self.set_accept(format);
+
+        self
+    }
+
+    /// Send payloads larger than `size` bytes in blocks of (at most) `size`
+    /// bytes, `size` is rounded down to a power of two between 16 and 1024.
+    pub fn set_block_size(&mut self, size: usize) {
+        self.settings.block_size = size;
+    }
+
+    pub fn with_block_size(mut self, size: usize) -> Self {
+        self.set_block_size(size);
+
+        self
+    }
+
+    /// Protect the request, and verify the response, with OSCORE.
+    pub fn set_oscore(&mut self, context: Arc<Mutex<SecurityContext>>) {
+        self.settings.oscore = Some(context);
+    }
+
+    pub fn with_oscore(mut self, context: Arc<Mutex<SecurityContext>>) -> Self {
+        self.set_oscore(context);
+
+        self
+    }
+
+    /// Serve fresh responses from `cache` and store new ones in it. A cache
+    /// may be shared between clients.
+    pub fn set_cache(&mut self, cache: Arc<Mutex<Cache>>) {
+        self.settings.cache = Some(cache);
+    }
+
+    pub fn with_cache(mut self, cache: Arc<Mutex<Cache>>) -> Self {
+        self.set_cache(cache);
+
+        self
+    }
+
+    /// Send the request through the forward proxy at `proxy`, which
+    /// performs it on the client's behalf.
+    pub fn set_proxy(&mut self, proxy: Endpoint) {
+        self.settings.proxy = Some(proxy);
+    }
+
+    pub fn with_proxy(mut self, proxy: Endpoint) -> Self {
+        self.set_proxy(proxy);
+
+        self
+    }
+
+    /// Look up the addresses of the endpoint with `resolver`.
+    pub fn set_resolver(&mut self, resolver: Arc<dyn Resolver>) {
+        self.settings.resolver = Some(resolver);
+    }
+
+    pub fn with_resolver(mut self, resolver: Arc<dyn Resolver>) -> Self {
+        self.set_resolver(resolver);
+
+        self
+    }
+
+    /// Send from a socket bound to `local_addr`. Addresses of the endpoint
+    /// in the same family are tried first.
+    pub fn set_local_addr(&mut self, local_addr: SocketAddr) {
+        self.settings.local_addr = Some(local_addr);
+    }
+
+    pub fn with_local_addr(mut self, local_addr: SocketAddr) -> Self {
+        self.set_local_addr(local_addr);
+
+        self
+    }
+
+    /// Give up on an address of the endpoint after `timeout` without a
+    /// response, moving on to the next or failing with `Error::Timeout`.
+    /// For an observation only the response to the registration has to
+    /// arrive in time.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.settings.timeout = timeout;
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.set_timeout(timeout);
+
+        self
+    }
+
+    /// Take the client apart into where to send what, and how.
+    fn into_parts(self) -> (Endpoint, Message, Settings) {
+        let Self { endpoint, msg, mut settings } = self;
+
+        match settings.proxy.take() {
+            Some(proxy) => {
+                let (endpoint, msg) = proxy::via(proxy, endpoint, msg);
+                (endpoint, msg, settings)
+            }
+            None => (endpoint, msg, settings),
+        }
+    }
+
+    /// Send the request from a freshly bound UDP socket.
+    ///
+    /// Confirmable messages are retransmitted until they are acknowledged,
+    /// as in RFC 7252 4.2. If the endpoint has several addresses and one of
+    /// them doesn't answer in time or can't be reached, the request is sent
+    /// to the next.
     pub fn send(self) -> IoFuture<Message> {
-        let local_addr = "0.0.0.0:0".parse().unwrap();
-
-        let Self { endpoint, msg } = self;
-        let client_request = endpoint
-            .resolve()
-            .and_then(move |remote_addr| {
-                let sock = UdpSocket::bind(&local_addr).unwrap();
-
-                let framed_socket = UdpFramed::new(sock, CoapCodec);
-
-                info!("sending request");
-                let client =  framed_socket
-                    .send((msg, remote_addr))
-                    .and_then(|sock| {
-                        let timeout_time = Instant::now() + Duration::from_millis(1000);
-                        sock
-                            .filter_map(|(msg, _addr)| {
-                                match msg.code {
-                                    Code::Content => {
-                                        Some(msg)
-                                    },
-                                    _ => {
-                                        warn!("Unexpeted Response");
-                                        None
-                                    },
-                                }
-                            })
-                            .take(1)
-                            .collect()
-                            .map(|mut list| {
-                                list.pop().expect("list of one somehow had nothing to pop")
-                            })
-                            .deadline(timeout_time)
-                            .map_err(|_| Error::Timeout)
-                    });
-
-                client
+        let (endpoint, msg, settings) = self.into_parts();
+
+        Box::new(candidates(endpoint, &settings).and_then(move |remote_addrs| {
+            send_to_any(remote_addrs, msg, settings)
+        }))
+    }
+
+    /// Send the request over an existing transport, such as a simulated
+    /// socket, rather than a freshly bound UDP socket.
+    ///
+    /// It is retransmitted and times out just as with `send`, but only the
+    /// first address of the endpoint is tried.
+    pub fn send_over<T: Transport>(self, transport: T) -> IoFuture<Message> {
+        let (endpoint, msg, settings) = self.into_parts();
+
+        Box::new(candidates(endpoint, &settings).and_then(move |remote_addrs| {
+            request(transport, msg, remote_addrs[0], settings).map(|(response, _)| response)
+        }))
+    }
+
+    /// RFC 7641: 3.  Client-Side Requirements
+    ///
+    /// Register interest in the resource from a freshly bound UDP socket and
+    /// receive the response followed by every notification, see
+    /// `observe_until`.
+    ///
+    /// Only the first address of the endpoint is tried, and neither
+    /// block-wise transfers, the cache nor OSCORE are used. Dropping the
+    /// stream closes the socket, the server forgets the registration once a
+    /// notification goes unacknowledged.
+    pub fn observe(self) -> IoStream<Message> {
+        self.observe_until(future::empty())
+    }
+
+    /// Observe as `observe` does until `cancel` completes or fails, then
+    /// cancel the registration.
+    pub(crate) fn observe_until<C>(self, cancel: C) -> IoStream<Message>
+        where C: Future<Item = (), Error = ()> + Send + 'static,
+    {
+        let (endpoint, msg, settings) = self.into_parts();
+
+        let notifications = candidates(endpoint, &settings).and_then(move |remote_addrs| {
+            let remote_addr = remote_addrs[0];
+            let local_addr = settings.local_addr.unwrap_or_else(|| unspecified(&remote_addr));
+            let sock = UdpSocket::bind(&local_addr)?;
+
+            Ok(observe_until(UdpFramed::new(sock, CoapCodec), msg, remote_addr, settings.timeout, cancel))
+        });
+
+        Box::new(notifications.flatten_stream())
+    }
+}
+
+/// The address to bind to for sending to `remote_addr` from any address of
+/// its family.
+pub(crate) fn unspecified(remote_addr: &SocketAddr) -> SocketAddr {
+    match *remote_addr {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    }
+}
+
+/// The addresses of `endpoint` to try, in order.
+fn candidates(endpoint: Endpoint, settings: &Settings) -> IoFuture<Vec<SocketAddr>> {
+    let addrs = match settings.resolver {
+        Some(ref resolver) => endpoint.resolve_all_with(resolver),
+        None => endpoint.resolve_all(),
+    };
+
+    let local_addr = settings.local_addr;
+    Box::new(addrs.map(move |mut addrs| {
+        if let Some(ref local_addr) = local_addr {
+            prefer_family(&mut addrs, local_addr);
+        }
+        addrs
+    }))
+}
+
+/// Send `msg` to the first of `remote_addrs`, moving on to the next if it
+/// times out or can't be reached.
+///
+/// Each socket is connected to its address, so that an ICMP error such as
+/// Port Unreachable is reported rather than waited out.
+fn send_to_any(remote_addrs: Vec<SocketAddr>, msg: Message, settings: Settings) -> IoFuture<Message> {
+    let remote_addr = remote_addrs[0];
+    let rest = remote_addrs[1..].to_vec();
+
+    let local_addr = settings.local_addr.unwrap_or_else(|| unspecified(&remote_addr));
+
+    let response = future::result(Connected::new(local_addr, remote_addr))
+        .map_err(Error::from)
+        .and_then({
+            let msg = msg.clone();
+            let settings = settings.clone();
+            move |sock| request(sock, msg, remote_addr, settings)
+        })
+        .map(|(response, _)| response);
+
+    Box::new(response.or_else(move |e| -> IoFuture<Message> {
+        match e {
+            Error::Timeout | Error::Io(_) if !rest.is_empty() => {
+                warn!("no response from {}, trying {}: {:?}", remote_addr, rest[0], e);
+                send_to_any(rest, msg, settings)
+            }
+            e => Box::new(future::err(e)),
+        }
+    }))
+}
+
+/// A framed UDP socket connected to its remote, failing once an error is
+/// pending on it.
+///
+/// The reactor wakes a task receiving from a socket when an error arrives,
+/// but doesn't count that as readable, so it is picked up here.
+struct Connected {
+    framed: UdpFramed<CoapCodec>,
+    /// the same socket, to take errors from
+    errors: net::UdpSocket,
+}
+
+impl Connected {
+    fn new(local_addr: SocketAddr, remote_addr: SocketAddr) -> io::Result<Connected> {
+        let sock = net::UdpSocket::bind(local_addr)?;
+        sock.connect(remote_addr)?;
+        let errors = sock.try_clone()?;
+        let sock = UdpSocket::from_std(sock, &Handle::default())?;
+
+        Ok(Connected { framed: UdpFramed::new(sock, CoapCodec), errors })
+    }
+}
+
+impl Transport for Connected {}
+
+impl Stream for Connected {
+    type Item = (Message, SocketAddr);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<StdOption<Self::Item>, Self::Error> {
+        if let Some(e) = self.errors.take_error()? {
+            return Err(Error::Io(e));
+        }
+
+        self.framed.poll()
+    }
+}
+
+impl Sink for Connected {
+    type SinkItem = (Message, SocketAddr);
+    type SinkError = Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        self.framed.start_send(item)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.framed.poll_complete()
+    }
+}
+
+/// Carry out a request: from the cache if possible, block-wise if its
+/// payload is too large, repeated if the server challenges its freshness,
+/// and protected if asked to, all within the timeout.
+fn request<T: Transport>(
+    transport: T,
+    msg: Message,
+    remote_addr: SocketAddr,
+    settings: Settings,
+) -> IoFuture<(Message, T)> {
+    let oscore = settings.oscore;
+    let secure_exchange = move |transport: T, msg: Message, remote_addr: SocketAddr| match oscore {
+        Some(ref context) => oscore::exchange(transport, msg, remote_addr, context.clone()),
+        None => exchange(transport, msg, remote_addr),
+    };
+    let fresh_exchange = move |transport: T, msg: Message, remote_addr: SocketAddr| {
+        freshness::exchange(transport, msg, remote_addr, secure_exchange.clone())
+    };
+
+    let block_size = settings.block_size;
+    let send = move |transport: T, msg: Message, remote_addr: SocketAddr| {
+        if msg.payload.len() > block_size {
+            block::upload(transport, msg, remote_addr, block_size, fresh_exchange.clone())
+        } else {
+            fresh_exchange(transport, msg, remote_addr)
+        }
+    };
+
+    let deadline = transport.sleep(settings.timeout);
+    let response = match settings.cache {
+        Some(cache) => cache::exchange(transport, msg, remote_addr, cache, send),
+        None => send(transport, msg, remote_addr),
+    };
+
+    before(response, deadline)
+}
+
+/// Fail with `Error::Timeout` unless `future` completes before `deadline`.
+fn before<I: Send + 'static>(future: IoFuture<I>, deadline: IoFuture<()>) -> IoFuture<I> {
+    Box::new(future.select2(deadline).then(|outcome| match outcome {
+        Ok(Either::A((item, _))) => Ok(item),
+        Ok(Either::B(((), _))) => Err(Error::Timeout),
+        Err(Either::A((e, _))) | Err(Either::B((e, _))) => Err(e),
+    }))
+}
+
+/// Send `msg` to `remote_addr` and wait for the matching response.
+///
+/// RFC 7252: 4.2.  Messages Transmitted Reliably
+///
+/// A confirmable request is retransmitted, each time after twice as long as
+/// before, until it is acknowledged or answered. Once it has been sent
+/// `MAX_RETRANSMIT` more times without either, the exchange fails with
+/// `Error::Timeout`.
+pub(crate) fn exchange<T: Transport>(transport: T, mut msg: Message, remote_addr: SocketAddr) -> IoFuture<(Message, T)> {
+    msg.mid = random_u64() as u16;
+    if msg.token.is_empty() {
+        let token = random_u64();
+        msg = msg.with_token(&[(token >> 24) as u8, (token >> 16) as u8, (token >> 8) as u8, token as u8]);
+    }
+
+    let mid = msg.mid;
+    let token = msg.token.to_vec();
+    let retransmitted = match msg.mtype {
+        Mtype::Confirmable => Some(msg.clone()),
+        _ => None,
+    };
+
+    info!("sending request");
+    let response = transport
+        .send((msg, remote_addr))
+        .and_then(move |transport| {
+            let retransmission = retransmitted.map(|msg| Retransmission::new(msg, &transport));
+            Receive { transport: Some(transport), remote_addr, mid, token, retransmission }
+        })
+        .and_then(move |(response, transport)| acknowledge(response, transport, remote_addr));
+
+    Box::new(response)
+}
+
+/// A confirmable request waiting to be acknowledged.
+struct Retransmission {
+    msg: Message,
+    retransmissions: u32,
+    timeout: Duration,
+    timer: IoFuture<()>,
+}
+
+impl Retransmission {
+    fn new<T: Transport>(msg: Message, transport: &T) -> Retransmission {
+        // between ACK_TIMEOUT and ACK_TIMEOUT * ACK_RANDOM_FACTOR (1.5)
+        let spread = ACK_TIMEOUT.as_millis() as u64 / 2;
+        let timeout = ACK_TIMEOUT + Duration::from_millis(random_u64() % spread);
+
+        Retransmission {
+            msg,
+            retransmissions: 0,
+            timeout,
+            timer: transport.sleep(timeout),
+        }
+    }
+}
+
+/// Waits for the response to the request with the given message ID and
+/// token, retransmitting it meanwhile if it is confirmable.
+struct Receive<T> {
+    transport: StdOption<T>,
+    remote_addr: SocketAddr,
+    mid: u16,
+    token: Vec<u8>,
+    retransmission: StdOption<Retransmission>,
+}
+
+impl<T: Transport> Future for Receive<T> {
+    type Item = (Message, T);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Error> {
+        let transport = self.transport.as_mut().expect("polled after completion");
+
+        loop {
+            if let Some(ref mut retransmission) = self.retransmission {
+                if let Async::Ready(()) = retransmission.timer.poll()? {
+                    if retransmission.retransmissions == MAX_RETRANSMIT {
+                        debug!("giving up on message {}", self.mid);
+                        return Err(Error::Timeout);
+                    }
+
+                    retransmission.retransmissions += 1;
+                    retransmission.timeout *= 2;
+                    retransmission.timer = transport.sleep(retransmission.timeout);
+
+                    debug!("retransmitting message {}", self.mid);
+                    if let AsyncSink::NotReady(_) = transport.start_send((retransmission.msg.clone(), self.remote_addr))? {
+                        warn!("transport busy, skipping retransmission of message {}", self.mid);
+                    }
+                    continue;
+                }
             }
-        );
+            transport.poll_complete()?;
 
-        Box::new(client_request)
+            let (msg, addr) = match transport.poll() {
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Ok(Async::Ready(Some(received))) => received,
+                Ok(Async::Ready(None)) => {
+                    return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "transport closed")));
+                }
+                // such as the remote refusing a connected socket
+                Err(Error::Io(e)) => return Err(Error::Io(e)),
+                Err(e) => {
+                    warn!("dropping undecodable message: {:?}", e);
+                    continue;
+                }
+            };
+
+            // an empty ACK means the response will follow separately, and
+            // the request needn't be sent again
+            if addr == self.remote_addr && msg.mid == self.mid && msg.mtype == Mtype::Acknowledgement {
+                self.retransmission = None;
+            }
+
+            match match_response(msg, addr, self.remote_addr, self.mid, &self.token) {
+                Some(Ok(response)) => {
+                    let transport = self.transport.take().expect("polled after completion");
+                    return Ok(Async::Ready((response, transport)));
+                }
+                Some(Err(e)) => return Err(e),
+                None => continue,
+            }
+        }
+    }
+}
+
+/// Acknowledge `response` if it is confirmable, as separate responses and
+/// notifications may be.
+fn acknowledge<T: Transport>(response: Message, transport: T, remote_addr: SocketAddr) -> IoFuture<(Message, T)> {
+    if response.mtype != Mtype::Confirmable {
+        return Box::new(future::ok((response, transport)));
+    }
+
+    let ack = Message::new()
+        .with_mtype(Mtype::Acknowledgement)
+        .with_code(Code::Empty)
+        .with_mid(response.mid);
+
+    Box::new(transport.send((ack, remote_addr)).map(move |transport| (response, transport)))
+}
+
+/// RFC 7641: 3.  Client-Side Requirements
+///
+/// Register interest in the resource targeted by `msg` and receive the
+/// response followed by every notification. The stream ends after a
+/// response that isn't a notification, such as an error or a response from a
+/// server not supporting Observe. The response has to arrive within
+/// `timeout`, notifications may take as long as they like.
+///
+/// RFC 7641: 3.6.  Cancellation
+///
+/// The stream also ends once `cancel` completes or fails, after cancelling
+/// the registration with a GET carrying Observe 1 over the same transport,
+/// whose response isn't waited for.
+pub(crate) fn observe_until<T, C>(
+    transport: T,
+    mut msg: Message,
+    remote_addr: SocketAddr,
+    timeout: Duration,
+    cancel: C,
+) -> IoStream<Message>
+    where T: Transport,
+          C: Future<Item = (), Error = ()> + Send + 'static,
+{
+    if msg.token.is_empty() {
+        let token = random_u64();
+        msg = msg.with_token(&[(token >> 24) as u8, (token >> 16) as u8, (token >> 8) as u8, token as u8]);
+    }
+    msg.set_observe(0);
+
+    let registration = msg.clone();
+
+    let deadline = transport.sleep(timeout);
+    let response = before(exchange(transport, msg, remote_addr), deadline);
+
+    let notifications = response.map(move |(response, transport)| {
+        let observing = is_notification(&response);
+        let first = stream::once(Ok(response));
+
+        let rest = stream::unfold(StdOption::Some((transport, cancel)), move |state| {
+            let registration = registration.clone();
+            state.map(|(transport, cancel)| {
+                let token = registration.token.to_vec();
+                next_notification(transport, remote_addr, token, cancel).and_then(move |next| -> IoFuture<_> {
+                    match next {
+                        Next::Notification(notification, transport, cancel) => {
+                            let state = if is_notification(&notification) { Some((transport, cancel)) } else { None };
+                            Box::new(future::ok((Some(notification), state)))
+                        }
+                        Next::Cancelled(transport) => {
+                            debug!("cancelling observation");
+                            let mut deregistration = registration
+                                .with_mtype(Mtype::NonConfirmable)
+                                .with_mid(random_u64() as u16);
+                            deregistration.set_observe(1);
+
+                            Box::new(transport.send((deregistration, remote_addr)).map(|_| (None, None)))
+                        }
+                    }
+                })
+            })
+        }).filter_map(|notification| notification);
+
+        let rest: IoStream<Message> = if observing {
+            Box::new(rest)
+        } else {
+            Box::new(stream::empty())
+        };
+
+        first.chain(rest)
+    });
+
+    Box::new(notifications.flatten_stream())
+}
+
+/// What became of waiting for a notification.
+enum Next<T, C> {
+    Notification(Message, T, C),
+    Cancelled(T),
+}
+
+/// Wait for the next notification with `token`, acknowledging it if
+/// needed, unless `cancel` completes or fails first.
+fn next_notification<T, C>(transport: T, remote_addr: SocketAddr, token: Vec<u8>, cancel: C) -> IoFuture<Next<T, C>>
+    where T: Transport,
+          C: Future<Item = (), Error = ()> + Send + 'static,
+{
+    let next = future::loop_fn((transport, cancel), move |(transport, cancel)| {
+            let token = token.clone();
+
+            transport.into_future().select2(cancel).then(move |outcome| match outcome {
+                Ok(Either::A(((Some((msg, addr)), transport), cancel))) => {
+                    match match_response(msg, addr, remote_addr, 0, &token) {
+                        Some(Ok(notification)) => Ok(Loop::Break(Next::Notification(notification, transport, cancel))),
+                        Some(Err(e)) => Err(e),
+                        None => Ok(Loop::Continue((transport, cancel))),
+                    }
+                }
+                Ok(Either::A(((None, _), _))) => Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "transport closed"))),
+                Err(Either::A(((e, transport), cancel))) => {
+                    warn!("dropping undecodable message: {:?}", e);
+                    Ok(Loop::Continue((transport, cancel)))
+                }
+                Ok(Either::B(((), receiving))) | Err(Either::B(((), receiving))) => {
+                    let transport = receiving.into_inner().expect("transport taken before it was received from");
+                    Ok(Loop::Break(Next::Cancelled(transport)))
+                }
+            })
+        })
+        .and_then(move |next| -> IoFuture<Next<T, C>> {
+            match next {
+                Next::Notification(notification, transport, cancel) => {
+                    let acknowledged = acknowledge(notification, transport, remote_addr);
+                    Box::new(acknowledged.map(move |(notification, transport)| Next::Notification(notification, transport, cancel)))
+                }
+                cancelled => Box::new(future::ok(cancelled)),
+            }
+        });
+
+    Box::new(next)
+}
+
+/// Whether more notifications may follow this response to an Observe
+/// registration.
+pub(crate) fn is_notification(response: &Message) -> bool {
+    response.code.is_success() && response.observe().ok().and_then(|o| o).is_some()
+}
+
+/// Decide whether a received message answers the request with the given
+/// message ID and token.
+fn match_response(
+    msg: Message,
+    addr: SocketAddr,
+    remote_addr: SocketAddr,
+    mid: u16,
+    token: &[u8],
+) -> StdOption<Result<Message, Error>> {
+    if addr != remote_addr {
+        warn!("Unexpected message from {}", addr);
+        return None;
+    }
+
+    match msg.mtype {
+        Mtype::Reset if msg.mid == mid => Some(Err(Error::Reset)),
+        // an empty ACK means the response will follow separately
+        Mtype::Acknowledgement if msg.code == Code::Empty => None,
+        // RFC 7252: 5.4.1.  Critical/Elective
+        _ if msg.token.as_slice() == token => Some(msg.check_options().map(|()| msg).map_err(Error::from)),
+        _ => {
+            warn!("Unexpected Response");
+            None
+        }
     }
 }
 
@@ -193,14 +882,170 @@ macro_rules! set_or_with {
 
 #[cfg(test)]
 mod tests {
-    use super::decompose;
+    use super::{decompose, Client, ACK_TIMEOUT, MAX_RETRANSMIT, MAX_TRANSMIT_WAIT};
     use endpoint::Endpoint;
+    use error::Error;
+    use message::Code;
     use message::option::{Option, Options, UriHost, UriPath, UriQuery};
+    use sim::{Config, Network, Simulation};
+
+    use futures::future::{self, Loop};
+    use futures::{Future, Sink, Stream};
 
     use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
 
     use url::Url;
 
+    #[test]
+    fn client_uri() {
+        use client::Client;
+        use message::Code;
+        use uri::CoapUri;
+
+        let uri = CoapUri::parse("coap://example.net/sensors/temp?unit=C").unwrap();
+        let client = Client::request(Code::Get, &uri).unwrap();
+        assert_eq!(client.uri().unwrap(), uri);
+
+        let client = Client::get("coap://198.51.100.1/a").unwrap()
+            .with_uri(&uri.join("humidity").unwrap()).unwrap();
+        assert_eq!(client.uri().unwrap().as_str(), "coap://example.net/sensors/humidity");
+
+        let tcp = CoapUri::parse("coap+tcp://example.net/").unwrap();
+        assert!(Client::new().with_uri(&tcp).is_err());
+    }
+
+    #[test]
+    fn address_fallback() {
+        use std::sync::Arc;
+        use std::time::{Duration, Instant};
+
+        use futures::Future;
+        use tokio::net::{UdpFramed, UdpSocket};
+        use tokio::runtime::Runtime;
+
+        use client::Client;
+        use codec::CoapCodec;
+        use message::{Code, Message};
+        use resolver::StaticResolver;
+        use server::{Request, Server};
+
+        let mut runtime = Runtime::new().unwrap();
+
+        let socket = UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let handler = |_: Request| Ok(Message::new().with_code(Code::Content).with_payload(b"22.5".to_vec()));
+        runtime.spawn(Server::new(handler).serve(UdpFramed::new(socket, CoapCodec)).map_err(|_| ()));
+
+        // nothing listens on the first address, so it is refused at once
+        let hosts = StaticResolver::new()
+            .with_host("sensor.test", vec!["127.0.0.2".parse().unwrap(), "127.0.0.1".parse().unwrap()]);
+        let client = Client::get(&format!("coap://sensor.test:{}/temp", port)).unwrap()
+            .with_resolver(Arc::new(hosts));
+        let start = Instant::now();
+        let response = runtime.block_on(client.send()).unwrap();
+
+        assert_eq!(response.payload, b"22.5");
+        assert!(start.elapsed() < Duration::from_millis(500), "fell back after {:?}", start.elapsed());
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    /// A server on `net` ignoring every copy of a request but the
+    /// `answered`th, noting when each arrived and with which message ID.
+    fn flaky_server(net: &Network, answered: usize) -> (SocketAddr, Arrivals, Box<dyn Future<Item = (), Error = ()>>) {
+        let socket = net.bind_any().unwrap();
+        let addr = socket.local_addr();
+        let arrivals = Arc::new(Mutex::new(Vec::new()));
+        let log = arrivals.clone();
+        let net = net.clone();
+
+        let server = future::loop_fn(socket, move |socket| {
+            let log = log.clone();
+            let net = net.clone();
+
+            socket.into_future().map_err(|_| ()).and_then(move |(received, socket)| -> Box<dyn Future<Item = _, Error = ()>> {
+                let (request, client) = match received {
+                    Some(received) => received,
+                    None => return Box::new(future::ok(Loop::Break(()))),
+                };
+
+                let mut log = log.lock().unwrap();
+                log.push((net.now(), request.mid));
+                if log.len() == answered {
+                    let response = request.new_reply().with_code(Code::Content);
+                    Box::new(socket.send((response, client)).map(Loop::Continue).map_err(|_| ()))
+                } else {
+                    Box::new(future::ok(Loop::Continue(socket)))
+                }
+            })
+        });
+
+        (addr, arrivals, Box::new(server))
+    }
+
+    type Arrivals = Arc<Mutex<Vec<(Instant, u16)>>>;
+
+    #[test]
+    fn retransmitted_until_answered() {
+        let net = Network::new(Config::new());
+        let (addr, arrivals, server) = flaky_server(&net, 3);
+        let mut sim = Simulation::new(net.clone());
+        sim.spawn(server);
+
+        let client = Client::new().with_endpoint(Endpoint::Resolved(addr));
+        let response = sim.run(client.send_over(net.bind_any().unwrap())).unwrap();
+
+        assert_eq!(response.code, Code::Content);
+
+        let arrivals = arrivals.lock().unwrap();
+        assert_eq!(arrivals.len(), 3);
+        assert!(arrivals.iter().all(|&(_, mid)| mid == arrivals[0].1));
+        let first = arrivals[1].0 - arrivals[0].0;
+        assert!(first >= ACK_TIMEOUT && first < ACK_TIMEOUT * 3 / 2, "retransmitted after {:?}", first);
+        assert_eq!(arrivals[2].0 - arrivals[1].0, first * 2);
+    }
+
+    #[test]
+    fn retransmission_gives_up() {
+        let net = Network::new(Config::new());
+        let (addr, arrivals, server) = flaky_server(&net, 0);
+        let mut sim = Simulation::new(net.clone());
+        sim.spawn(server);
+        let start = net.now();
+
+        let client = Client::new().with_endpoint(Endpoint::Resolved(addr));
+        match sim.run(client.send_over(net.bind_any().unwrap())) {
+            Err(Error::Timeout) => {}
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+
+        assert_eq!(arrivals.lock().unwrap().len(), 1 + MAX_RETRANSMIT as usize);
+        let waited = net.now() - start;
+        assert!(waited >= ACK_TIMEOUT * 31 && waited < MAX_TRANSMIT_WAIT, "gave up after {:?}", waited);
+    }
+
+    #[test]
+    fn timeout_over_any_transport() {
+        let net = Network::new(Config::new());
+        let (addr, arrivals, server) = flaky_server(&net, 0);
+        let mut sim = Simulation::new(net.clone());
+        sim.spawn(server);
+        let start = net.now();
+
+        let client = Client::new()
+            .with_endpoint(Endpoint::Resolved(addr))
+            .with_timeout(Duration::from_secs(5));
+        match sim.run(client.send_over(net.bind_any().unwrap())) {
+            Err(Error::Timeout) => {}
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+
+        assert_eq!(net.now() - start, Duration::from_secs(5));
+        assert_eq!(arrivals.lock().unwrap().len(), 2);
+    }
+
     #[test]
     fn uri_decompose_normalization() {
         let uri1 = Url::parse("coap://example.com:5683/~sensors/temp.xml").unwrap();
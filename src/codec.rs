@@ -13,8 +13,8 @@ impl Encoder for CoapCodec {
     type Error = Error;
 
     fn encode(&mut self, msg: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        let bytes = msg.to_bytes().unwrap();
-        dst.extend(bytes);
+        dst.reserve(msg.encoded_len()?);
+        msg.encode(dst)?;
 
         Ok(())
     }
@@ -25,9 +25,8 @@ impl Decoder for CoapCodec {
     type Error = Error;
 
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        match Message::from_bytes(&buf) {
-            Ok(msg) => Ok(Some(msg)),
-            Err(_) => Ok(None),
-        }
+        // Each buffer is a single datagram, so a message that fails to parse
+        // is an error rather than a sign that more data is needed.
+        Ok(Some(Message::from_bytes(buf)?))
     }
 }
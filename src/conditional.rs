@@ -0,0 +1,241 @@
+//! Conditional requests with If-Match and If-None-Match (RFC 7252: 5.10.8).
+//!
+//! A client updating a resource can make the update conditional on the
+//! resource still having the ETag it last saw, or on it not existing yet.
+//! The server compares the conditions with the current ETag of the resource
+//! and answers 4.12 Precondition Failed instead of performing the request if
+//! they don't hold.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::option::Option as StdOption;
+use std::sync::{Arc, Mutex};
+
+use futures::prelude::*;
+use futures::future;
+
+use tokio::sync::lock::Lock;
+
+use cache::uri_options;
+use client::IoFuture;
+use message::{Code, Message};
+use server::{Handler, Request};
+
+/// The options making up the target URI of a request.
+type Target = Vec<(u16, Vec<u8>)>;
+
+/// The lock of every resource some request is waiting for or holding, with
+/// the number of those requests.
+type Locks = Arc<Mutex<HashMap<Target, (Lock<()>, usize)>>>;
+
+/// A `Handler` only passing on requests whose preconditions hold.
+///
+/// `current_etag` looks up the ETag of the resource a request targets,
+/// `None` if the resource doesn't exist. Requests to the same resource are
+/// handled one at a time, from checking their preconditions until the
+/// handler has answered, so that no other request changes the resource in
+/// between.
+pub struct Conditional<H, F> {
+    handler: Arc<H>,
+    current_etag: Arc<F>,
+    locks: Locks,
+}
+
+impl<H, F> Conditional<H, F>
+    where H: Handler,
+          F: Fn(&Request) -> StdOption<Vec<u8>> + Send + Sync + 'static,
+{
+    pub fn new(handler: H, current_etag: F) -> Conditional<H, F> {
+        Conditional {
+            handler: Arc::new(handler),
+            current_etag: Arc::new(current_etag),
+            locks: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+/// A request's place in the queue for the lock of its resource, given up
+/// when dropped.
+struct Queued {
+    locks: Locks,
+    target: Target,
+    lock: Lock<()>,
+}
+
+impl Queued {
+    fn new(locks: &Locks, target: Target) -> Queued {
+        let lock = {
+            let mut locks = locks.lock().unwrap();
+            let entry = locks.entry(target.clone()).or_insert_with(|| (Lock::new(()), 0));
+            entry.1 += 1;
+            entry.0.clone()
+        };
+
+        Queued { locks: locks.clone(), target, lock }
+    }
+}
+
+impl Drop for Queued {
+    fn drop(&mut self) {
+        let mut locks = self.locks.lock().unwrap();
+        let last = match locks.get_mut(&self.target) {
+            Some(entry) => {
+                entry.1 -= 1;
+                entry.1 == 0
+            }
+            None => false,
+        };
+
+        if last {
+            locks.remove(&self.target);
+        }
+    }
+}
+
+/// Whether the conditions of `request` hold for a resource with the ETag
+/// `current`.
+fn preconditions_hold(request: &Message, current: StdOption<&[u8]>) -> bool {
+    let if_match = match request.if_match() {
+        Ok(if_match) => if_match,
+        Err(_) => return false,
+    };
+
+    let if_match_holds = if_match.is_empty() || match current {
+        Some(current) => if_match.iter().any(|etag| etag.is_empty() || etag.as_slice() == current),
+        None => false,
+    };
+
+    let if_none_match_holds = !request.if_none_match() || current.is_none();
+
+    if_match_holds && if_none_match_holds
+}
+
+impl<H, F> Handler for Conditional<H, F>
+    where H: Handler,
+          F: Fn(&Request) -> StdOption<Vec<u8>> + Send + Sync + 'static,
+{
+    fn handle(&self, request: Request) -> IoFuture<Message> {
+        let queued = Queued::new(&self.locks, uri_options(&request.message.options.map));
+        let mut lock = queued.lock.clone();
+        let handler = self.handler.clone();
+        let current_etag = self.current_etag.clone();
+
+        let response = future::poll_fn(move || Ok(lock.poll_lock())).and_then(move |guard| {
+            let current = current_etag(&request);
+
+            let response: IoFuture<Message> = match preconditions_hold(&request.message, current.as_deref()) {
+                true => handler.handle(request),
+                false => {
+                    debug!("precondition of request from {} failed", request.source);
+                    Box::new(future::ok(Message::new().with_code(Code::PreconditionFailed)))
+                }
+            };
+
+            response.then(move |response| {
+                drop((guard, queued));
+                response
+            })
+        });
+
+        Box::new(response)
+    }
+
+    fn forwards_options(&self) -> bool {
+        self.handler.forwards_options()
+    }
+
+    fn reset(&self, source: SocketAddr, mid: u16) {
+        self.handler.reset(source, mid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::{self, Notify};
+    use futures::sync::oneshot;
+
+    /// A configuration value, versioned by its ETag.
+    fn config(etag: Arc<Mutex<StdOption<Vec<u8>>>>) -> impl Handler {
+        let current = etag.clone();
+
+        Conditional::new(move |_: Request| {
+            let mut etag = etag.lock().unwrap();
+            let next = etag.as_ref().map_or(1, |etag| etag[0] + 1);
+            *etag = Some(vec![next]);
+
+            Ok(Message::new().with_code(Code::Changed))
+        }, move |_: &Request| current.lock().unwrap().clone())
+    }
+
+    fn put(if_match: &[&[u8]], if_none_match: bool) -> Request {
+        let mut message = Message::new().with_code(Code::Put).with_if_none_match(if_none_match);
+        for etag in if_match {
+            message.add_if_match(etag).unwrap();
+        }
+
+        Request { message, source: "192.0.2.1:5683".parse().unwrap() }
+    }
+
+    #[test]
+    fn if_none_match() {
+        let handler = config(Arc::new(Mutex::new(None)));
+
+        assert_eq!(handler.handle(put(&[], true)).wait().unwrap().code, Code::Changed);
+        assert_eq!(handler.handle(put(&[], true)).wait().unwrap().code, Code::PreconditionFailed);
+    }
+
+    #[test]
+    fn if_match() {
+        let handler = config(Arc::new(Mutex::new(None)));
+
+        assert_eq!(handler.handle(put(&[b""], false)).wait().unwrap().code, Code::PreconditionFailed);
+        assert_eq!(handler.handle(put(&[], false)).wait().unwrap().code, Code::Changed);
+
+        assert_eq!(handler.handle(put(&[b""], false)).wait().unwrap().code, Code::Changed);
+        assert_eq!(handler.handle(put(&[&[9], &[2]], false)).wait().unwrap().code, Code::Changed);
+        // a concurrent update already moved on from 2
+        assert_eq!(handler.handle(put(&[&[2]], false)).wait().unwrap().code, Code::PreconditionFailed);
+    }
+
+    struct Noop;
+
+    impl Notify for Noop {
+        fn notify(&self, _: usize) {}
+    }
+
+    #[test]
+    fn concurrent_updates() {
+        let etag = Arc::new(Mutex::new(Some(vec![1])));
+        let (open, gate) = oneshot::channel::<()>();
+        let gate = Mutex::new(Some(gate));
+
+        let current = etag.clone();
+        let update = etag.clone();
+        let handler = Conditional::new(move |_: Request| -> IoFuture<Message> {
+            let update = update.clone();
+            let changed = move |_| {
+                *update.lock().unwrap() = Some(vec![2]);
+                Ok(Message::new().with_code(Code::Changed))
+            };
+
+            // the first update takes its time
+            match gate.lock().unwrap().take() {
+                Some(gate) => Box::new(gate.then(changed)),
+                None => Box::new(future::result(changed(Ok(())))),
+            }
+        }, move |_: &Request| current.lock().unwrap().clone());
+
+        let notify = Arc::new(Noop);
+        let mut first = executor::spawn(handler.handle(put(&[&[1]], false)));
+        let mut second = executor::spawn(handler.handle(put(&[&[1]], false)));
+        assert!(first.poll_future_notify(&notify, 0).unwrap().is_not_ready());
+        assert!(second.poll_future_notify(&notify, 0).unwrap().is_not_ready());
+
+        open.send(()).unwrap();
+        assert_eq!(first.wait_future().unwrap().code, Code::Changed);
+        assert_eq!(second.wait_future().unwrap().code, Code::PreconditionFailed);
+        assert!(handler.locks.lock().unwrap().is_empty());
+    }
+}
@@ -4,12 +4,11 @@ use std::net::SocketAddr;
 use futures::prelude::*;
 use futures::future;
 
-use tokio_dns;
-
 use error::Error;
 use client::IoFuture;
+use resolver::{self, Resolver};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Endpoint {
     Unset,
     Resolved(SocketAddr),
@@ -17,11 +16,61 @@ pub enum Endpoint {
 }
 
 impl Endpoint {
+    /// The first address of the endpoint, found by the default resolver.
     pub fn resolve(self) -> IoFuture<SocketAddr> {
+        Box::new(self.resolve_all().map(|addrs| addrs[0]))
+    }
+
+    /// Every address of the endpoint, found by the default resolver.
+    pub fn resolve_all(self) -> IoFuture<Vec<SocketAddr>> {
+        self.resolve_all_with(&resolver::default_resolver())
+    }
+
+    /// Every address of the endpoint, found by `resolver`, most preferred
+    /// first. Resolving to no address at all is an error.
+    pub fn resolve_all_with<R: Resolver + ?Sized>(self, resolver: &R) -> IoFuture<Vec<SocketAddr>> {
         match self {
             Endpoint::Unset => Box::new(future::err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput, "endpoint unset")))),
-            Endpoint::Resolved(addr) => Box::new(future::ok(addr)),
-            Endpoint::Unresolved(host, port) => Box::new(tokio_dns::resolve::<&str>(&host).map_err(|e| Error::Io(e)).map(move |ip| SocketAddr::new(ip[0], port))),
+            Endpoint::Resolved(addr) => Box::new(future::ok(vec![addr])),
+            Endpoint::Unresolved(host, port) => Box::new(resolver.resolve(&host).and_then(move |ips| {
+                if ips.is_empty() {
+                    return Err(Error::Io(io::Error::new(io::ErrorKind::NotFound, format!("no addresses for {}", host))));
+                }
+                Ok(ips.into_iter().map(|ip| SocketAddr::new(ip, port)).collect())
+            })),
         }
     }
 }
+
+/// Move the addresses a socket bound to `local_addr` can send to, those of
+/// the same family, to the front, keeping the order otherwise.
+pub fn prefer_family(addrs: &mut [SocketAddr], local_addr: &SocketAddr) {
+    addrs.sort_by_key(|addr| addr.is_ipv4() != local_addr.is_ipv4());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use resolver::StaticResolver;
+
+    fn hosts() -> StaticResolver {
+        StaticResolver::new()
+            .with_host("dual.example", vec!["2001:db8::1".parse().unwrap(), "192.0.2.1".parse().unwrap()])
+            .with_host("none.example", vec![])
+    }
+
+    #[test]
+    fn resolve_all() {
+        let endpoint = Endpoint::Unresolved("dual.example".to_string(), 5683);
+        let mut addrs = endpoint.resolve_all_with(&hosts()).wait().unwrap();
+        assert_eq!(addrs, vec!["[2001:db8::1]:5683".parse().unwrap(), "192.0.2.1:5683".parse().unwrap()]);
+
+        prefer_family(&mut addrs, &"0.0.0.0:0".parse().unwrap());
+        assert_eq!(addrs[0], "192.0.2.1:5683".parse().unwrap());
+
+        let endpoint = Endpoint::Unresolved("none.example".to_string(), 5683);
+        assert!(endpoint.resolve_all_with(&hosts()).wait().is_err());
+        assert!(Endpoint::Unset.resolve_all_with(&hosts()).wait().is_err());
+    }
+}
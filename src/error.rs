@@ -1,4 +1,5 @@
 use message::Error as MessageError;
+use oscore::Error as OscoreError;
 use std::io::Error as IoError;
 use std::str::Utf8Error;
 use url::ParseError;
@@ -25,12 +26,16 @@ pub enum UrlError {
 pub enum Error {
     /// A timeout was reached while waiting for a reply or event
     Timeout,
+    /// The remote endpoint rejected a message with a reset
+    Reset,
     /// A message was unable to be parsed successfully.
     Message(MessageError),
     /// The system IO returned an error.
     Io(IoError),
     /// Error when attempting to parse a url
     Url(UrlError),
+    /// A message could not be protected or verified with OSCORE.
+    Oscore(OscoreError),
 
     #[doc(hidden)]
     __AlwaysWildcardMatchThisListWillChange,
@@ -48,6 +53,12 @@ impl From<MessageError> for Error {
     }
 }
 
+impl From<OscoreError> for Error {
+    fn from(e: OscoreError) -> Error {
+        Error::Oscore(e)
+    }
+}
+
 impl From<IoError> for Error {
     fn from(e: IoError) -> Error {
         Error::Io(e)
@@ -0,0 +1,224 @@
+//! Request freshness with the Echo option (RFC 9175).
+//!
+//! A server that must not act on delayed or replayed requests answers any
+//! request without a recent Echo value with 4.01 Unauthorized and a new
+//! Echo value. The client repeats the request with that value, proving the
+//! request was sent after the challenge.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::option::Option as StdOption;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::prelude::*;
+use futures::future::{self, Loop};
+
+use client::{IoFuture, Transport};
+use message::{Code, Message};
+use message::option::{Echo, Option};
+use server::{Handler, Request};
+use util::random_u64;
+
+/// How long an Echo value is accepted unless told otherwise.
+pub const DEFAULT_FRESHNESS: Duration = Duration::from_secs(10);
+
+/// The length of the Echo values issued.
+const ECHO_LEN: usize = 8;
+
+/// How many outstanding Echo values are remembered.
+const MAX_ISSUED: usize = 256;
+
+/// How many times a client repeats a request for a new challenge.
+const MAX_RETRIES: usize = 2;
+
+/// A `Handler` only passing on requests that prove their freshness.
+///
+/// Each Echo value is accepted once, and only within the freshness period.
+/// When combined with `Reassemble`, this handler should wrap it so that
+/// every block is checked.
+pub struct RequireFresh<H> {
+    handler: Arc<H>,
+    issued: Arc<Mutex<HashMap<Vec<u8>, Instant>>>,
+    freshness: Duration,
+}
+
+impl<H: Handler> RequireFresh<H> {
+    pub fn new(handler: H) -> RequireFresh<H> {
+        RequireFresh {
+            handler: Arc::new(handler),
+            issued: Arc::new(Mutex::new(HashMap::new())),
+            freshness: DEFAULT_FRESHNESS,
+        }
+    }
+
+    /// Accept Echo values for `freshness` after they were issued.
+    pub fn with_freshness(mut self, freshness: Duration) -> Self {
+        self.freshness = freshness;
+        self
+    }
+
+    /// Whether `echo` was issued by this handler recently and not used yet.
+    fn redeem(&self, echo: &[u8]) -> bool {
+        match self.issued.lock().unwrap().remove(echo) {
+            Some(issued) => issued.elapsed() <= self.freshness,
+            None => false,
+        }
+    }
+
+    fn issue(&self) -> Vec<u8> {
+        let now = Instant::now();
+        let freshness = self.freshness;
+        let mut issued = self.issued.lock().unwrap();
+
+        if issued.len() >= MAX_ISSUED {
+            issued.retain(|_, at| now.duration_since(*at) <= freshness);
+        }
+        if issued.len() >= MAX_ISSUED {
+            let oldest = issued.iter().min_by_key(|&(_, at)| *at).map(|(echo, _)| echo.clone());
+            if let Some(oldest) = oldest {
+                issued.remove(&oldest);
+            }
+        }
+
+        let echo = random_u64().to_be_bytes()[..ECHO_LEN].to_vec();
+        issued.insert(echo.clone(), now);
+
+        echo
+    }
+}
+
+impl<H: Handler> Handler for RequireFresh<H> {
+    fn handle(&self, request: Request) -> IoFuture<Message> {
+        let fresh = match request.message.options.map.get(&Echo::NUMBER) {
+            Some(values) => values.len() == 1 && self.redeem(&values[0]),
+            None => false,
+        };
+
+        if fresh {
+            return self.handler.handle(request);
+        }
+
+        debug!("challenging request from {} for freshness", request.source);
+        let mut response = Message::new().with_code(Code::Unauthorized);
+        response.options.push_raw(Echo::NUMBER, self.issue());
+
+        Box::new(future::ok(response))
+    }
+
+    fn forwards_options(&self) -> bool {
+        self.handler.forwards_options()
+    }
+
+    fn reset(&self, source: SocketAddr, mid: u16) {
+        self.handler.reset(source, mid)
+    }
+}
+
+/// The Echo value of a 4.01 Unauthorized response, if it has one.
+fn echo_challenge(response: &Message) -> StdOption<Vec<u8>> {
+    if response.code != Code::Unauthorized {
+        return None;
+    }
+
+    response.options.map.get(&Echo::NUMBER).and_then(|v| v.first()).cloned()
+}
+
+/// Exchange `msg` using `exchange`, repeating it with the Echo value of a
+/// freshness challenge, if the server responds with one.
+pub(crate) fn exchange<T, F>(transport: T, msg: Message, remote_addr: SocketAddr, exchange: F) -> IoFuture<(Message, T)>
+    where T: Transport,
+          F: Fn(T, Message, SocketAddr) -> IoFuture<(Message, T)> + Send + 'static,
+{
+    let response = future::loop_fn((transport, msg, 0), move |(transport, msg, retries)| {
+        exchange(transport, msg.clone(), remote_addr).map(move |(response, transport)| {
+            let sent = msg.options.map.get(&Echo::NUMBER).and_then(|v| v.first()).cloned();
+
+            match echo_challenge(&response) {
+                Some(echo) if retries < MAX_RETRIES && Some(&echo) != sent.as_ref() => {
+                    debug!("repeating request with echo from freshness challenge");
+                    let mut msg = msg;
+                    msg.options.map.insert(Echo::NUMBER, vec![echo]);
+                    Loop::Continue((transport, msg, retries + 1))
+                }
+                _ => Loop::Break((response, transport)),
+            }
+        })
+    });
+
+    Box::new(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use client::Client;
+    use endpoint::Endpoint;
+    use server::Server;
+    use sim::{Config, Network, Simulation};
+
+    fn toggle(calls: Arc<AtomicUsize>) -> RequireFresh<impl Handler> {
+        RequireFresh::new(move |_: Request| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Ok(Message::new().with_code(Code::Changed))
+        })
+    }
+
+    fn request(echo: StdOption<Vec<u8>>) -> Request {
+        let mut message = Message::new().with_code(Code::Post);
+        if let Some(echo) = echo {
+            message.options.push_raw(Echo::NUMBER, echo);
+        }
+
+        Request { message, source: "192.0.2.1:5683".parse().unwrap() }
+    }
+
+    #[test]
+    fn client_answers_challenge() {
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        let net = Network::new(Config::new());
+        let server_socket = net.bind_any().unwrap();
+        let endpoint = Endpoint::Resolved(server_socket.local_addr());
+        let client_socket = net.bind_any().unwrap();
+
+        let mut sim = Simulation::new(net);
+        sim.spawn(Server::new(toggle(calls.clone())).serve(server_socket).map_err(|_| ()));
+
+        let client = Client::new().with_endpoint(endpoint);
+        let response = sim.run(client.send_over(client_socket)).unwrap();
+
+        assert_eq!(response.code, Code::Changed);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn echo_accepted_once() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler = toggle(calls.clone());
+
+        let challenge = handler.handle(request(None)).wait().unwrap();
+        assert_eq!(challenge.code, Code::Unauthorized);
+        let echo = echo_challenge(&challenge).unwrap();
+
+        assert_eq!(handler.handle(request(Some(echo.clone()))).wait().unwrap().code, Code::Changed);
+        assert_eq!(handler.handle(request(Some(echo))).wait().unwrap().code, Code::Unauthorized);
+        assert_eq!(handler.handle(request(Some(vec![1, 2, 3]))).wait().unwrap().code, Code::Unauthorized);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn stale_echo_rejected() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler = toggle(calls.clone()).with_freshness(Duration::from_secs(0));
+
+        let echo = echo_challenge(&handler.handle(request(None)).wait().unwrap()).unwrap();
+        ::std::thread::sleep(Duration::from_millis(5));
+
+        assert_eq!(handler.handle(request(Some(echo))).wait().unwrap().code, Code::Unauthorized);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,54 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use futures::prelude::*;
+use futures::future;
+
+use tokio::net::TcpStream;
+use tokio::util::FutureExt;
+use tokio_io::codec::Decoder;
+
+use url::{Host, Position, Url};
+
+use client::IoFuture;
+use endpoint::Endpoint;
+use error::{Error, UrlError};
+use super::{ClientCodec, Request, Response};
+
+/// How long to wait for the origin server to respond.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Perform `request` on the origin server of `url`, over a new connection
+/// that is closed after the response.
+///
+/// Only `http` URLs can be sent to, there is no support for TLS.
+pub fn send(url: &Url, request: Request) -> IoFuture<Response> {
+    if url.scheme() != "http" {
+        return Box::new(future::err(UrlError::UnsupportedScheme(url.scheme().to_string()).into()));
+    }
+
+    let port = url.port_or_known_default().unwrap_or(80);
+    let endpoint = match url.host() {
+        Some(Host::Domain(domain)) => Endpoint::Unresolved(domain.to_string(), port),
+        Some(Host::Ipv4(ip)) => Endpoint::Resolved(SocketAddr::new(IpAddr::V4(ip), port)),
+        Some(Host::Ipv6(ip)) => Endpoint::Resolved(SocketAddr::new(IpAddr::V6(ip), port)),
+        None => Endpoint::Unset,
+    };
+
+    let request = request
+        .with_header("Host", &url[Position::BeforeHost..Position::AfterPort])
+        .with_header("Connection", "close");
+
+    let response = endpoint.resolve()
+        .and_then(|addr| TcpStream::connect(&addr).map_err(Error::from))
+        .and_then(|stream| ClientCodec::new().framed(stream).send(request))
+        .and_then(|framed| framed.into_future().map_err(|(e, _)| e))
+        .and_then(|(response, _)| {
+            response.ok_or_else(|| Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed")))
+        })
+        .timeout(TIMEOUT)
+        .map_err(|e| e.into_inner().unwrap_or(Error::Timeout));
+
+    Box::new(response)
+}
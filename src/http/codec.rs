@@ -0,0 +1,307 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::option::Option as StdOption;
+use std::str;
+
+use tokio_io::codec::{Decoder, Encoder};
+use bytes::{BufMut, BytesMut};
+
+use error::Error;
+use super::{reason, Headers, Request, Response, DEFAULT_MAX_BODY_SIZE};
+
+/// How large the header of a message may grow before giving up on it.
+const MAX_HEAD_LEN: usize = 8 * 1024;
+
+/// Decodes HTTP requests and encodes responses, for the server side of a
+/// connection.
+#[derive(Debug, Clone)]
+pub struct ServerCodec {
+    max_body_size: usize,
+}
+
+/// Encodes HTTP requests and decodes responses, for the client side of a
+/// connection.
+#[derive(Debug, Clone)]
+pub struct ClientCodec {
+    max_body_size: usize,
+}
+
+impl ServerCodec {
+    pub fn new() -> ServerCodec {
+        ServerCodec {
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+
+    /// Fail to decode requests with a larger body, see `is_too_large`.
+    pub fn with_max_body_size(mut self, size: usize) -> Self {
+        self.max_body_size = size;
+        self
+    }
+}
+
+impl Default for ServerCodec {
+    fn default() -> ServerCodec {
+        ServerCodec::new()
+    }
+}
+
+impl ClientCodec {
+    pub fn new() -> ClientCodec {
+        ClientCodec {
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+
+    /// Fail to decode responses with a larger body, see `is_too_large`.
+    pub fn with_max_body_size(mut self, size: usize) -> Self {
+        self.max_body_size = size;
+        self
+    }
+}
+
+impl Default for ClientCodec {
+    fn default() -> ClientCodec {
+        ClientCodec::new()
+    }
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::Io(io::Error::new(io::ErrorKind::InvalidData, reason))
+}
+
+/// The reason a message with a body larger than allowed isn't decoded.
+#[derive(Debug)]
+struct TooLarge;
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("body too large")
+    }
+}
+
+impl StdError for TooLarge {}
+
+/// Whether decoding a message failed because its body is larger than the
+/// codec allows.
+pub fn is_too_large(e: &Error) -> bool {
+    match *e {
+        Error::Io(ref e) => e.get_ref().is_some_and(|e| e.is::<TooLarge>()),
+        _ => false,
+    }
+}
+
+/// A message split into its start line, header fields and body.
+struct Parts {
+    start: String,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+/// Take the first complete message off `buf`, if there is one.
+///
+/// The body of a message without a Content-Length is empty, unless
+/// `until_close` says it runs until the connection closes, in which case
+/// the message is complete once `closed`. A body larger than
+/// `max_body_size` is an error.
+fn decode_parts(buf: &mut BytesMut, until_close: bool, closed: bool, max_body_size: usize) -> Result<StdOption<Parts>, Error> {
+    let head_len = match buf.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(end) => end + 4,
+        None if buf.len() > MAX_HEAD_LEN => return Err(invalid("header too large")),
+        None => return Ok(None),
+    };
+
+    let (start, headers) = {
+        let head = str::from_utf8(&buf[..head_len - 4]).map_err(|_| invalid("header not utf-8"))?;
+        let mut lines = head.split("\r\n");
+        let start = lines.next().unwrap_or("").to_string();
+
+        let headers = lines.map(|line| {
+            let colon = line.find(':').ok_or_else(|| invalid("malformed header field"))?;
+            Ok((line[..colon].trim().to_string(), line[colon + 1..].trim().to_string()))
+        }).collect::<Result<Headers, Error>>()?;
+
+        (start, headers)
+    };
+
+    if super::header(&headers, "Transfer-Encoding").is_some() {
+        return Err(invalid("transfer codings are not supported"));
+    }
+    let body_len = match super::header(&headers, "Content-Length") {
+        Some(len) => Some(len.parse::<usize>().map_err(|_| invalid("malformed Content-Length"))?),
+        None if until_close => None,
+        None => Some(0),
+    };
+
+    // a body running until the connection closes is as long as what has
+    // arrived of it so far
+    let received = buf.len() - head_len;
+    if body_len.unwrap_or(received) > max_body_size {
+        return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidData, TooLarge)));
+    }
+
+    let body_len = match body_len {
+        Some(len) => len,
+        None if closed => received,
+        None => return Ok(None),
+    };
+
+    if buf.len() < head_len + body_len {
+        return Ok(None);
+    }
+
+    buf.split_to(head_len);
+    let body = buf.split_to(body_len).to_vec();
+
+    Ok(Some(Parts { start, headers, body }))
+}
+
+fn encode_parts(start: &str, headers: &Headers, body: &[u8], dst: &mut BytesMut) {
+    let mut head = format!("{}\r\n", start);
+    // the length is always that of the body at hand
+    for (name, value) in headers.iter().filter(|(name, _)| !name.eq_ignore_ascii_case("Content-Length")) {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str(&format!("Content-Length: {}\r\n\r\n", body.len()));
+
+    dst.reserve(head.len() + body.len());
+    dst.put_slice(head.as_bytes());
+    dst.put_slice(body);
+}
+
+impl Decoder for ServerCodec {
+    type Item = Request;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<StdOption<Request>, Error> {
+        let Parts { start, headers, body } = match decode_parts(buf, false, false, self.max_body_size)? {
+            Some(parts) => parts,
+            None => return Ok(None),
+        };
+
+        let mut start = start.split(' ');
+        let (method, target) = match (start.next(), start.next(), start.next()) {
+            (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => (method, target),
+            _ => return Err(invalid("malformed request line")),
+        };
+
+        Ok(Some(Request {
+            method: method.to_string(),
+            target: target.to_string(),
+            headers,
+            body,
+        }))
+    }
+}
+
+impl Encoder for ServerCodec {
+    type Item = Response;
+    type Error = Error;
+
+    fn encode(&mut self, response: Response, dst: &mut BytesMut) -> Result<(), Error> {
+        let start = format!("HTTP/1.1 {} {}", response.status, reason(response.status));
+        encode_parts(&start, &response.headers, &response.body, dst);
+
+        Ok(())
+    }
+}
+
+impl Encoder for ClientCodec {
+    type Item = Request;
+    type Error = Error;
+
+    fn encode(&mut self, request: Request, dst: &mut BytesMut) -> Result<(), Error> {
+        let start = format!("{} {} HTTP/1.1", request.method, request.target);
+        encode_parts(&start, &request.headers, &request.body, dst);
+
+        Ok(())
+    }
+}
+
+impl ClientCodec {
+    fn decode_response(&mut self, buf: &mut BytesMut, closed: bool) -> Result<StdOption<Response>, Error> {
+        let Parts { start, headers, body } = match decode_parts(buf, true, closed, self.max_body_size)? {
+            Some(parts) => parts,
+            None => return Ok(None),
+        };
+
+        let mut start = start.splitn(3, ' ');
+        let status = match (start.next(), start.next()) {
+            (Some(version), Some(status)) if version.starts_with("HTTP/1.") => status.parse().ok(),
+            _ => None,
+        }.ok_or_else(|| invalid("malformed status line"))?;
+
+        Ok(Some(Response { status, headers, body }))
+    }
+}
+
+impl Decoder for ClientCodec {
+    type Item = Response;
+    type Error = Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<StdOption<Response>, Error> {
+        self.decode_response(buf, false)
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<StdOption<Response>, Error> {
+        match self.decode_response(buf, true)? {
+            Some(response) => Ok(Some(response)),
+            None if buf.is_empty() => Ok(None),
+            None => Err(invalid("connection closed within a response")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_in_pieces() {
+        let mut buf = BytesMut::from(&b"PUT /a HTTP/1.1\r\nHost: x\r\ncontent-length: 4\r\n\r\nab"[..]);
+        assert!(ServerCodec::new().decode(&mut buf).unwrap().is_none());
+
+        buf.extend_from_slice(b"cdGET");
+        let request = ServerCodec::new().decode(&mut buf).unwrap().unwrap();
+        assert_eq!(request, Request::new("PUT", "/a")
+            .with_header("Host", "x")
+            .with_header("content-length", "4")
+            .with_body(b"abcd".to_vec()));
+        assert_eq!(&buf[..], b"GET");
+
+        let mut buf = BytesMut::from(&b"GET /a\r\n\r\n"[..]);
+        assert!(ServerCodec::new().decode(&mut buf).is_err());
+    }
+
+    #[test]
+    fn encode_response() {
+        let mut buf = BytesMut::new();
+        ServerCodec::new().encode(Response::new(404).with_header("Content-Type", "text/plain").with_body(b"no".to_vec()), &mut buf).unwrap();
+
+        assert_eq!(&buf[..], &b"HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nContent-Length: 2\r\n\r\nno"[..]);
+    }
+
+    #[test]
+    fn response_until_close() {
+        let mut buf = BytesMut::from(&b"HTTP/1.0 200 OK\r\nServer: x\r\n\r\nhello"[..]);
+        assert!(ClientCodec::new().decode(&mut buf).unwrap().is_none());
+
+        let response = ClientCodec::new().decode_eof(&mut buf).unwrap().unwrap();
+        assert_eq!(response, Response::new(200).with_header("Server", "x").with_body(b"hello".to_vec()));
+        assert!(ClientCodec::new().decode_eof(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn body_too_large() {
+        let mut buf = BytesMut::from(&b"PUT /a HTTP/1.1\r\nContent-Length: 5\r\n\r\n"[..]);
+        assert!(is_too_large(&ServerCodec::new().with_max_body_size(4).decode(&mut buf).unwrap_err()));
+
+        // known to be too large before the connection closes
+        let mut buf = BytesMut::from(&b"HTTP/1.0 200 OK\r\n\r\nhello"[..]);
+        assert!(is_too_large(&ClientCodec::new().with_max_body_size(4).decode(&mut buf).unwrap_err()));
+
+        let mut buf = BytesMut::from(&b"GET /a\r\n\r\n"[..]);
+        assert!(!is_too_large(&ServerCodec::new().decode(&mut buf).unwrap_err()));
+    }
+}
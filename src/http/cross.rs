@@ -0,0 +1,565 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::net::SocketAddr;
+use std::option::Option as StdOption;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::prelude::*;
+use futures::future::{self, Loop};
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::util::FutureExt;
+use tokio_io::codec::Decoder;
+
+use percent_encoding::percent_decode;
+use url::Url;
+
+use client::{Client, IoFuture};
+use error::{Error, UrlError};
+use message::{Code, ContentFormat, Message, Mtype};
+use message::option::{ETag, Option};
+use uri::decompose;
+use super::{content_format, format_etag, is_too_large, parse_etag, status, Request, Response, ServerCodec};
+use super::{DEFAULT_MAX_BODY_SIZE, OCTET_STREAM};
+
+/// How many connections may be served at the same time.
+const CONCURRENT_CONNECTIONS: usize = 64;
+
+/// How long a connection may go without a complete request unless told
+/// otherwise.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// RFC 8075: 5.3.  Default Mapping
+///
+/// The path under which the rest of a request target is the CoAP URI to
+/// perform the request on.
+const WELL_KNOWN: &str = "/.well-known/coap/";
+
+/// An HTTP server performing the requests it receives with CoAP.
+///
+/// A request for `/.well-known/coap/coap://example.net/temp` is performed
+/// on `coap://example.net/temp`. Other requests are performed on the
+/// default target, if there is one, and answered with 404 Not Found
+/// otherwise. Methods with no CoAP counterpart are answered with 501 Not
+/// Implemented, media types with no Content-Format with 415 Unsupported
+/// Media Type or 406 Not Acceptable, requests that time out with 504
+/// Gateway Timeout, and any other failure to get a response with 502 Bad
+/// Gateway.
+///
+/// Request bodies larger than the maximum body size are answered with 413
+/// Payload Too Large, those too large for a single CoAP message are sent
+/// block-wise.
+///
+/// ETags are given in HTTP as quoted hexadecimal strings.
+///
+/// Connections on which no complete request arrives within the idle timeout
+/// are closed, so that clients keeping them open don't hold up others.
+pub struct CrossProxy<F> {
+    send: F,
+    default_target: StdOption<Url>,
+    max_body_size: usize,
+    idle_timeout: Duration,
+}
+
+impl CrossProxy<fn(Client) -> IoFuture<Message>> {
+    /// Perform requests over UDP, from a new socket for each.
+    pub fn new() -> Self {
+        CrossProxy::from_fn(Client::send)
+    }
+}
+
+impl Default for CrossProxy<fn(Client) -> IoFuture<Message>> {
+    fn default() -> Self {
+        CrossProxy::new()
+    }
+}
+
+impl<F> CrossProxy<F>
+    where F: Fn(Client) -> IoFuture<Message> + Send + Sync + 'static,
+{
+    /// Perform requests by sending the client made for each with `send`,
+    /// e.g. over a simulated socket.
+    pub fn from_fn(send: F) -> CrossProxy<F> {
+        CrossProxy {
+            send,
+            default_target: None,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            idle_timeout: IDLE_TIMEOUT,
+        }
+    }
+
+    /// Perform requests outside of `/.well-known/coap/` on the server at
+    /// `url`, e.g. `coap://[2001:db8::1]`, with their path and query.
+    pub fn with_default_target(mut self, url: &str) -> Result<Self, Error> {
+        self.default_target = Some(Url::parse(url).map_err(UrlError::Parse)?);
+        Ok(self)
+    }
+
+    /// Accept request bodies of at most `size` bytes rather than
+    /// `DEFAULT_MAX_BODY_SIZE`.
+    pub fn with_max_body_size(mut self, size: usize) -> Self {
+        self.max_body_size = size;
+        self
+    }
+
+    /// Close connections on which the next request hasn't completely
+    /// arrived after `timeout` rather than 30 seconds.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Serve HTTP requests on a TCP socket bound to `addr`.
+    pub fn bind(self, addr: &SocketAddr) -> Result<IoFuture<()>, Error> {
+        let listener = TcpListener::bind(addr)?;
+
+        Ok(self.serve(listener))
+    }
+
+    /// Serve HTTP requests on the connections accepted by `listener`.
+    pub fn serve(self, listener: TcpListener) -> IoFuture<()> {
+        let proxy = Arc::new(self);
+
+        let connections = listener.incoming()
+            .map_err(Error::from)
+            .map(move |stream| connection(proxy.clone(), stream))
+            .buffer_unordered(CONCURRENT_CONNECTIONS)
+            .for_each(|()| Ok(()));
+
+        Box::new(connections)
+    }
+
+    /// Perform `request` with CoAP and translate the response.
+    pub fn handle(&self, request: Request) -> IoFuture<Response> {
+        let client = match self.translate(&request) {
+            Ok(client) => client,
+            Err(status) => {
+                debug!("not performing {} {}: {}", request.method, request.target, status);
+                return Box::new(future::ok(Response::new(status)));
+            }
+        };
+
+        Box::new((self.send)(client).then(|response| Ok(match response {
+            Ok(response) => translate_response(response),
+            Err(e) => failure(e),
+        })))
+    }
+
+    /// RFC 8075: 5.  HTTP-to-CoAP Mapping
+    ///
+    /// The client performing `request`, or the status to answer with if
+    /// there is none.
+    fn translate(&self, request: &Request) -> Result<Client, u16> {
+        let code = method(&request.method).ok_or(501u16)?;
+        if request.body.len() > self.max_body_size {
+            return Err(413);
+        }
+
+        let url = self.target(&request.target)?;
+        let (endpoint, options) = decompose(&url).map_err(|_| 400u16)?;
+
+        let mut msg = Message::new()
+            .with_mtype(Mtype::Confirmable)
+            .with_code(code)
+            .with_payload(request.body.clone());
+        msg.options = options;
+
+        if let Some(content_type) = request.header("Content-Type") {
+            msg.set_content_format(content_format(content_type).ok_or(415u16)?);
+        }
+
+        if let Some(accept) = request.header("Accept") {
+            if let Some(format) = accept_format(accept)? {
+                msg.set_accept(format);
+            }
+        }
+
+        // RFC 8075: 6.4.  Conditional Requests
+        for etag in list(request, "If-Match") {
+            let etag = match etag {
+                "*" => Vec::new(),
+                etag => parse_etag(etag).ok_or(400u16)?,
+            };
+            msg.add_if_match(&etag).map_err(|_| 400u16)?;
+        }
+
+        for etag in list(request, "If-None-Match") {
+            match etag {
+                "*" => msg.set_if_none_match(true),
+                // validating a stored representation
+                etag if code == Code::Get => {
+                    let etag = parse_etag(etag).ok_or(400u16)?;
+                    if etag.is_empty() || etag.len() > 8 {
+                        return Err(400);
+                    }
+                    msg.options.push(ETag::new(etag));
+                }
+                _ => return Err(400),
+            }
+        }
+
+        Ok(Client::from_message(endpoint, msg))
+    }
+
+    /// The CoAP URI a request target maps to.
+    fn target(&self, target: &str) -> Result<Url, u16> {
+        if let Some(uri) = target.strip_prefix(WELL_KNOWN) {
+            // the URI may also be given percent-encoded as a whole
+            let uri = if uri.contains("://") {
+                Cow::Borrowed(uri)
+            } else {
+                percent_decode(uri.as_bytes()).decode_utf8().map_err(|_| 400u16)?
+            };
+            return Url::parse(&uri).map_err(|_| 400);
+        }
+
+        match self.default_target {
+            Some(ref base) => base.join(target).map_err(|_| 400),
+            None => Err(404),
+        }
+    }
+}
+
+/// The CoAP method performing an HTTP method.
+fn method(method: &str) -> StdOption<Code> {
+    match method {
+        "GET" => Some(Code::Get),
+        "POST" => Some(Code::Post),
+        "PUT" => Some(Code::Put),
+        "DELETE" => Some(Code::Delete),
+        "FETCH" => Some(Code::Fetch),
+        "PATCH" => Some(Code::Patch),
+        "iPATCH" => Some(Code::IPatch),
+        _ => None,
+    }
+}
+
+/// The elements of the comma separated header fields called `name`.
+fn list<'a>(request: &'a Request, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    request.headers.iter()
+        .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+        .flat_map(|(_, value)| value.split(','))
+        .map(str::trim)
+        .filter(|element| !element.is_empty())
+}
+
+/// RFC 8075: 6.2.  Content-Type and Content-Encoding Mapping
+///
+/// The Content-Format to ask for given an Accept header: the most preferred
+/// media type there is one for, none if a wildcard comes first.
+fn accept_format(accept: &str) -> Result<StdOption<ContentFormat>, u16> {
+    let mut ranges: Vec<(String, f32)> = accept.split(',').map(|range| {
+        let mut params = range.split(';');
+        let mut media_range = params.next().unwrap_or("").trim().to_string();
+        let mut quality = 1.0;
+
+        for param in params.map(str::trim) {
+            match param.strip_prefix("q=") {
+                Some(q) => quality = q.parse().unwrap_or(0.0),
+                None => {
+                    media_range.push_str("; ");
+                    media_range.push_str(param);
+                }
+            }
+        }
+
+        (media_range, quality)
+    }).filter(|&(_, quality)| quality > 0.0).collect();
+
+    // stable, so equally preferred types keep their order
+    ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+
+    for (media_range, _) in ranges {
+        if media_range.split(';').next().is_some_and(|media_type| media_type.ends_with("/*")) {
+            return Ok(None);
+        }
+        if let Some(format) = content_format(&media_range) {
+            return Ok(Some(format));
+        }
+    }
+
+    Err(406)
+}
+
+/// RFC 8075: 7.  Response Code Mapping
+fn translate_response(response: Message) -> Response {
+    let status = match response.code {
+        Code::Deleted | Code::Changed if response.payload.is_empty() => 204,
+        code => status(code),
+    };
+    let mut translated = Response::new(status);
+
+    if let Ok(Some(etag)) = response.etag() {
+        translated = translated.with_header("ETag", &format_etag(&etag));
+    }
+
+    if matches!(response.code, Code::Content | Code::Valid) {
+        if let Ok(max_age) = response.max_age() {
+            translated = translated.with_header("Cache-Control", &format!("max-age={}", max_age));
+        }
+    }
+
+    if status == 204 || status == 304 || response.payload.is_empty() {
+        return translated;
+    }
+
+    // RFC 7252: 5.5.2.  Diagnostic Payload
+    let content_type = match response.content_format() {
+        Ok(Some(format)) => format.media_type().unwrap_or(OCTET_STREAM),
+        Ok(None) if response.code.is_error() => "text/plain; charset=utf-8",
+        _ => OCTET_STREAM,
+    };
+
+    translated.with_header("Content-Type", content_type).with_body(response.payload)
+}
+
+/// The response when performing a request failed.
+fn failure(e: Error) -> Response {
+    warn!("performing request failed: {:?}", e);
+
+    match e {
+        Error::Timeout => Response::new(504),
+        _ => Response::new(502),
+    }
+}
+
+/// Answer the requests arriving on `stream` one after the other until the
+/// client closes the connection, asks for it to be closed, or lets it idle
+/// for too long.
+fn connection<F>(proxy: Arc<CrossProxy<F>>, stream: TcpStream) -> IoFuture<()>
+    where F: Fn(Client) -> IoFuture<Message> + Send + Sync + 'static,
+{
+    let codec = ServerCodec::new().with_max_body_size(proxy.max_body_size);
+    let idle_timeout = proxy.idle_timeout;
+
+    let connection = future::loop_fn(codec.framed(stream), move |framed| {
+        let proxy = proxy.clone();
+
+        framed.into_future().timeout(idle_timeout).then(move |received| -> IoFuture<Loop<(), _>> {
+            let (request, framed) = match received {
+                Ok((Some(request), framed)) => (request, framed),
+                Ok((None, _)) => return Box::new(future::ok(Loop::Break(()))),
+                Err(e) => match e.into_inner() {
+                    Some((e, framed)) => {
+                        debug!("rejecting malformed request: {:?}", e);
+                        let status = if is_too_large(&e) { 413 } else { 400 };
+                        let response = Response::new(status).with_header("Connection", "close");
+                        return Box::new(framed.send(response).map(|_| Loop::Break(())));
+                    }
+                    None => {
+                        debug!("closing idle connection");
+                        return Box::new(future::ok(Loop::Break(())));
+                    }
+                },
+            };
+
+            let close = request.closes_connection();
+            let response = proxy.handle(request).and_then(move |response| {
+                let response = if close { response.with_header("Connection", "close") } else { response };
+
+                framed.send(response).map(move |framed| if close { Loop::Break(()) } else { Loop::Continue(framed) })
+            });
+
+            Box::new(response)
+        })
+    });
+
+    Box::new(connection.or_else(|e| {
+        warn!("connection failed: {:?}", e);
+        Ok(())
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Read, Write};
+    use std::net;
+
+    use tokio::net::{UdpFramed, UdpSocket};
+    use tokio::runtime::Runtime;
+
+    use block::Reassemble;
+    use codec::CoapCodec;
+    use server::{self, Server};
+    use sim::{Config, Network, Simulation};
+
+    /// A temperature reading, and a configuration that can only be changed
+    /// by those knowing its ETag.
+    fn origin(request: server::Request) -> Result<Message, Error> {
+        let message = request.message;
+
+        match (message.code, message.uri_path()?.as_str()) {
+            (Code::Get, "/temp") if message.accepts(ContentFormat::Json)? => {
+                let mut response = Message::new()
+                    .with_code(Code::Content)
+                    .with_content_format(ContentFormat::Json)
+                    .with_max_age(30)
+                    .with_payload(b"{\"t\":22.5}".to_vec());
+                response.set_etag(b"v1")?;
+                Ok(response)
+            }
+            (Code::Get, "/temp") => Ok(Message::new().with_code(Code::NotAcceptable)),
+            (Code::Put, "/config") if message.if_match()? == vec![vec![1]] => Ok(Message::new().with_code(Code::Changed)),
+            (Code::Put, "/config") => Ok(Message::new().with_code(Code::PreconditionFailed)),
+            _ => Ok(Message::new().with_code(Code::NotFound).with_payload(b"no such resource".to_vec())),
+        }
+    }
+
+    fn setup() -> (Simulation, Network, SocketAddr) {
+        let net = Network::new(Config::new());
+        let origin_socket = net.bind_any().unwrap();
+        let origin_addr = origin_socket.local_addr();
+
+        let mut sim = Simulation::new(net.clone());
+        sim.spawn(Server::new(origin).serve(origin_socket).map_err(|_| ()));
+
+        (sim, net, origin_addr)
+    }
+
+    fn cross_proxy(net: &Network) -> CrossProxy<impl Fn(Client) -> IoFuture<Message> + Send + Sync + 'static> {
+        let net = net.clone();
+        CrossProxy::from_fn(move |client: Client| client.send_over(net.bind_any().unwrap()))
+    }
+
+    #[test]
+    fn well_known_get() {
+        let (mut sim, net, origin_addr) = setup();
+        let proxy = cross_proxy(&net);
+
+        let request = Request::new("GET", &format!("/.well-known/coap/coap://{}/temp", origin_addr))
+            .with_header("Accept", "text/html;q=0.5, application/json");
+        let response = sim.run(proxy.handle(request)).unwrap();
+
+        assert_eq!(response.status, 200);
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+        assert_eq!(response.header("ETag"), Some("\"7631\""));
+        assert_eq!(response.header("Cache-Control"), Some("max-age=30"));
+        assert_eq!(response.body, b"{\"t\":22.5}");
+
+        let request = Request::new("GET", &format!("/.well-known/coap/coap%3A%2F%2F{}%2Fmissing", origin_addr));
+        let response = sim.run(proxy.handle(request)).unwrap();
+        assert_eq!(response.status, 404);
+        assert_eq!(response.header("Content-Type"), Some("text/plain; charset=utf-8"));
+        assert_eq!(response.body, b"no such resource");
+    }
+
+    #[test]
+    fn default_target_conditional_put() {
+        let (mut sim, net, origin_addr) = setup();
+        let proxy = cross_proxy(&net).with_default_target(&format!("coap://{}", origin_addr)).unwrap();
+
+        let put = |etag: &str| Request::new("PUT", "/config")
+            .with_header("Content-Type", "text/plain")
+            .with_header("If-Match", etag)
+            .with_body(b"on".to_vec());
+
+        assert_eq!(sim.run(proxy.handle(put("\"01\""))).unwrap().status, 204);
+        assert_eq!(sim.run(proxy.handle(put("\"02\""))).unwrap().status, 412);
+    }
+
+    #[test]
+    fn large_bodies() {
+        let net = Network::new(Config::new());
+        let origin_socket = net.bind_any().unwrap();
+        let origin_addr = origin_socket.local_addr();
+        let origin = Reassemble::new(|request: server::Request| {
+            let received = request.message.payload.len().to_string();
+            Ok(Message::new().with_code(Code::Changed).with_payload(received.into_bytes()))
+        });
+
+        let mut sim = Simulation::new(net.clone());
+        sim.spawn(Server::new(origin).serve(origin_socket).map_err(|_| ()));
+
+        let proxy = cross_proxy(&net)
+            .with_max_body_size(4096)
+            .with_default_target(&format!("coap://{}", origin_addr)).unwrap();
+        let put = |len: usize| Request::new("PUT", "/upload").with_body(vec![b'x'; len]);
+
+        // more than fits in one message
+        let response = sim.run(proxy.handle(put(3000))).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"3000");
+
+        assert_eq!(sim.run(proxy.handle(put(5000))).unwrap().status, 413);
+    }
+
+    #[test]
+    fn untranslatable_requests() {
+        let proxy = CrossProxy::new();
+
+        let status = |request: Request| proxy.handle(request).wait().unwrap().status;
+        let temp = "/.well-known/coap/coap://192.0.2.1/temp";
+
+        assert_eq!(status(Request::new("GET", "/temp")), 404);
+        assert_eq!(status(Request::new("OPTIONS", temp)), 501);
+        assert_eq!(status(Request::new("GET", "/.well-known/coap/http://192.0.2.1/temp")), 400);
+        assert_eq!(status(Request::new("GET", temp).with_header("Accept", "text/html")), 406);
+        assert_eq!(status(Request::new("PUT", temp).with_header("Content-Type", "text/html")), 415);
+        assert_eq!(status(Request::new("PUT", temp).with_header("If-Match", "W/\"01\"")), 400);
+    }
+
+    #[test]
+    fn over_loopback() {
+        let mut runtime = Runtime::new().unwrap();
+
+        let origin_socket = UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let origin_addr = origin_socket.local_addr().unwrap();
+        let server = Server::new(origin).serve(UdpFramed::new(origin_socket, CoapCodec));
+        runtime.spawn(server.map_err(|_| ()));
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        let proxy = CrossProxy::new().with_default_target(&format!("coap://{}", origin_addr)).unwrap();
+        runtime.spawn(proxy.serve(listener).map_err(|_| ()));
+
+        let mut stream = net::TcpStream::connect(proxy_addr).unwrap();
+        write!(stream, "GET /temp HTTP/1.1\r\nHost: {0}\r\n\r\n\
+                        GET /temp HTTP/1.1\r\nHost: {0}\r\nAccept: text/html, */*\r\nConnection: close\r\n\r\n", proxy_addr).unwrap();
+        let mut responses = String::new();
+        stream.read_to_string(&mut responses).unwrap();
+
+        let body = "{\"t\":22.5}";
+        let mut responses = responses.split(body);
+        assert!(responses.next().unwrap().starts_with("HTTP/1.1 200 OK\r\n"));
+        let second = responses.next().unwrap();
+        assert!(second.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(second.contains("Connection: close\r\n"));
+        assert_eq!(responses.next(), Some(""));
+
+        // refused before the body arrives
+        let mut stream = net::TcpStream::connect(proxy_addr).unwrap();
+        write!(stream, "PUT /temp HTTP/1.1\r\nHost: {}\r\nContent-Length: 100000\r\n\r\n", proxy_addr).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn idle_connections_closed() {
+        let mut runtime = Runtime::new().unwrap();
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        let proxy = CrossProxy::new().with_idle_timeout(Duration::from_millis(200));
+        runtime.spawn(proxy.serve(listener).map_err(|_| ()));
+
+        // one saying nothing, one stopping halfway through the header
+        let silent = net::TcpStream::connect(proxy_addr).unwrap();
+        let mut partial = net::TcpStream::connect(proxy_addr).unwrap();
+        write!(partial, "GET /temp HTTP/1.1\r\n").unwrap();
+
+        for mut stream in [silent, partial] {
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let mut response = Vec::new();
+            stream.read_to_end(&mut response).unwrap();
+            assert!(response.is_empty());
+        }
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+}
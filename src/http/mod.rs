@@ -0,0 +1,265 @@
+//! Cross proxying between HTTP and CoAP (RFC 8075).
+//!
+//! Only as much of HTTP/1.1 as a proxy needs is implemented: messages with a
+//! Content-Length delimited body, responses running until the connection
+//! closes, and persistent connections handling one request at a time.
+//! `CrossProxy` is an HTTP server performing the requests it receives with
+//! CoAP, `send` performs a single request the other way around.
+
+use std::option::Option as StdOption;
+
+use message::{Code, ContentFormat};
+
+mod client;
+mod codec;
+mod cross;
+
+pub use self::client::send;
+pub use self::codec::{is_too_large, ClientCodec, ServerCodec};
+pub use self::cross::CrossProxy;
+
+/// The media type of payloads whose Content-Format isn't known.
+pub const OCTET_STREAM: &str = "application/octet-stream";
+
+/// How large a message body may be unless told otherwise.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// The fields of a message header, in the order they were received.
+pub type Headers = Vec<(String, String)>;
+
+/// An HTTP request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    /// the method, e.g. `GET`
+    pub method: String,
+    /// the request target, usually a path with an optional query
+    pub target: String,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn new(method: &str, target: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            target: target.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
+
+    /// The value of the first header field called `name`, ignoring case.
+    pub fn header(&self, name: &str) -> StdOption<&str> {
+        header(&self.headers, name)
+    }
+
+    /// Whether the client asked for the connection to be closed after the
+    /// response.
+    pub fn closes_connection(&self) -> bool {
+        self.header("Connection").is_some_and(|value| value.eq_ignore_ascii_case("close"))
+    }
+}
+
+/// An HTTP response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_body(mut self, body: Vec<u8>) -> Self {
+        self.body = body;
+        self
+    }
+
+    /// The value of the first header field called `name`, ignoring case.
+    pub fn header(&self, name: &str) -> StdOption<&str> {
+        header(&self.headers, name)
+    }
+}
+
+fn header<'a>(headers: &'a Headers, name: &str) -> StdOption<&'a str> {
+    headers.iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// The reason phrase sent along with `status`.
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        409 => "Conflict",
+        412 => "Precondition Failed",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        422 => "Unprocessable Entity",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        508 => "Loop Detected",
+        _ => "",
+    }
+}
+
+/// RFC 8075: 7.  Response Code Mapping
+///
+/// The status to answer an HTTP request with when its CoAP counterpart got
+/// a response with `code`. 2.02 Deleted and 2.04 Changed without a payload
+/// map to 204 No Content instead, that is left to the caller.
+pub fn status(code: Code) -> u16 {
+    match code {
+        Code::Created => 201,
+        Code::Deleted | Code::Changed | Code::Content => 200,
+        Code::Valid => 304,
+        Code::BadRequest | Code::BadOption => 400,
+        Code::Unauthorized | Code::Forbidden => 403,
+        Code::NotFound => 404,
+        // a 405 must come with an Allow header, which can't be known
+        Code::MethodNotAllowed => 400,
+        Code::NotAcceptable => 406,
+        Code::Conflict => 409,
+        Code::PreconditionFailed => 412,
+        Code::RequestEntityTooLarge => 413,
+        Code::UnsupportedContentFormat => 415,
+        Code::UnprocessableEntity => 422,
+        Code::TooManyRequests => 429,
+        Code::NotImplemented => 501,
+        Code::BadGateway | Code::ProxyingNotSupported => 502,
+        Code::ServiceUnavailable => 503,
+        Code::GatewayTimeout => 504,
+        Code::HopLimitReached => 508,
+        code if code.is_client_error() => 400,
+        _ => 500,
+    }
+}
+
+/// RFC 7252: 10.1.  CoAP-HTTP Proxying
+///
+/// The code to answer a CoAP request made with `method` with when its HTTP
+/// counterpart got a response with `status`. Redirects aren't followed, so
+/// they can only be answered with 5.02 Bad Gateway.
+pub fn code(status: u16, method: Code) -> Code {
+    match (status, method) {
+        (200, Code::Get) | (200, Code::Fetch) => Code::Content,
+        (200, Code::Delete) | (204, Code::Delete) => Code::Deleted,
+        (200, _) | (204, _) => Code::Changed,
+        (201, _) => Code::Created,
+        (304, _) => Code::Valid,
+        (400, _) => Code::BadRequest,
+        (401, _) => Code::Unauthorized,
+        (403, _) => Code::Forbidden,
+        (404, _) | (410, _) => Code::NotFound,
+        (405, _) => Code::MethodNotAllowed,
+        (406, _) => Code::NotAcceptable,
+        (409, _) => Code::Conflict,
+        (412, _) => Code::PreconditionFailed,
+        (413, _) => Code::RequestEntityTooLarge,
+        (415, _) => Code::UnsupportedContentFormat,
+        (422, _) => Code::UnprocessableEntity,
+        (429, _) => Code::TooManyRequests,
+        (400..=499, _) => Code::BadRequest,
+        (501, _) => Code::NotImplemented,
+        (503, _) => Code::ServiceUnavailable,
+        (504, _) => Code::GatewayTimeout,
+        (500..=599, _) if status != 502 => Code::InternalServerError,
+        _ => Code::BadGateway,
+    }
+}
+
+/// The Content-Format of a media type. Plain text without a charset is
+/// taken to be UTF-8, the only kind CoAP has a format for.
+pub fn content_format(media_type: &str) -> StdOption<ContentFormat> {
+    match media_type.trim() {
+        plain if plain.eq_ignore_ascii_case("text/plain") => Some(ContentFormat::TextPlain),
+        media_type => ContentFormat::from_media_type(media_type),
+    }
+}
+
+/// An ETag as the quoted hexadecimal string it is given as in HTTP.
+pub fn format_etag(etag: &[u8]) -> String {
+    let hex: String = etag.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("\"{}\"", hex)
+}
+
+/// The opaque value of an ETag given in HTTP by `format_etag`.
+pub fn parse_etag(etag: &str) -> StdOption<Vec<u8>> {
+    let hex = etag.trim().strip_prefix('"')?.strip_suffix('"')?;
+    if hex.len() % 2 != 0 || !hex.is_ascii() {
+        return None;
+    }
+
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_mapping() {
+        assert_eq!(status(Code::Content), 200);
+        assert_eq!(status(Code::Created), 201);
+        assert_eq!(status(Code::Valid), 304);
+        assert_eq!(status(Code::Unauthorized), 403);
+        assert_eq!(status(Code::MethodNotAllowed), 400);
+        assert_eq!(status(Code::ProxyingNotSupported), 502);
+        assert_eq!(status(Code::new(4, 31)), 400);
+        assert_eq!(status(Code::new(5, 31)), 500);
+    }
+
+    #[test]
+    fn code_mapping() {
+        assert_eq!(code(200, Code::Get), Code::Content);
+        assert_eq!(code(200, Code::Put), Code::Changed);
+        assert_eq!(code(204, Code::Delete), Code::Deleted);
+        assert_eq!(code(304, Code::Get), Code::Valid);
+        assert_eq!(code(302, Code::Get), Code::BadGateway);
+        assert_eq!(code(418, Code::Get), Code::BadRequest);
+        assert_eq!(code(502, Code::Get), Code::BadGateway);
+        assert_eq!(code(599, Code::Get), Code::InternalServerError);
+    }
+
+    #[test]
+    fn etags() {
+        assert_eq!(format_etag(&[0x0a, 0xff]), "\"0aff\"");
+        assert_eq!(parse_etag(" \"0aFF\""), Some(vec![0x0a, 0xff]));
+        assert_eq!(parse_etag("W/\"0a\""), None);
+        assert_eq!(parse_etag("\"0a0\""), None);
+    }
+}
@@ -1,23 +1,89 @@
 //! `tokio-coap` is a CoAP protocol implementaion
 //! that provides an implementaion of the protocol
 //! for use with`tokio-core`.
+//!
+//! Without the default `std` feature only the `message` core is built:
+//! `MessageRef` and `Packet` parse and encode messages without an allocator.
 
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+extern crate core as std;
+
+#[cfg(feature = "std")]
 extern crate futures;
+#[cfg(feature = "std")]
 extern crate tokio;
+#[cfg(feature = "std")]
 extern crate tokio_io;
+#[cfg(feature = "std")]
 extern crate tokio_dns;
+#[cfg(feature = "std")]
 extern crate bytes;
 extern crate arrayvec;
-#[macro_use]
+#[cfg_attr(feature = "std", macro_use)]
 extern crate log;
+#[cfg(feature = "std")]
 extern crate url;
+#[cfg(feature = "std")]
 extern crate percent_encoding;
+#[cfg(feature = "std")]
+extern crate hkdf;
+#[cfg(feature = "std")]
+extern crate sha2;
+#[cfg(feature = "std")]
+extern crate aes;
+#[cfg(feature = "std")]
+extern crate ccm;
 
+#[cfg(all(test, feature = "std"))]
+#[macro_use]
+extern crate quickcheck;
+
+#[cfg(feature = "std")]
+pub mod block;
+#[cfg(feature = "std")]
+pub mod blocking;
+#[cfg(feature = "std")]
+pub mod cache;
+#[cfg(feature = "std")]
 pub mod client;
+#[cfg(feature = "std")]
 pub mod codec;
+#[cfg(feature = "std")]
+pub mod conditional;
+#[cfg(feature = "std")]
 pub mod endpoint;
+#[cfg(feature = "std")]
 pub mod error;
+#[cfg(feature = "std")]
+pub mod freshness;
 pub mod message;
+#[cfg(feature = "std")]
+pub mod http;
+#[cfg(feature = "std")]
+pub mod negotiation;
+#[cfg(feature = "std")]
+pub mod oscore;
+#[cfg(feature = "std")]
+pub mod proxy;
+#[cfg(feature = "std")]
+pub mod resolver;
+#[cfg(feature = "std")]
+pub mod server;
+#[cfg(feature = "std")]
+pub mod sim;
+#[cfg(feature = "std")]
+pub mod uri;
+
+#[cfg(feature = "std")]
+mod util;
 
+#[cfg(feature = "std")]
 pub use client::Client;
+#[cfg(feature = "std")]
 pub use endpoint::Endpoint;
+#[cfg(feature = "std")]
+pub use server::Server;
+#[cfg(feature = "std")]
+pub use uri::CoapUri;
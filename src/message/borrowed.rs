@@ -0,0 +1,264 @@
+//! A view of a message that borrows its buffer instead of copying it.
+
+use super::{Code, Error, Mtype};
+#[cfg(feature = "std")]
+use super::Message;
+#[cfg(feature = "std")]
+use super::option::Options;
+
+#[cfg(feature = "std")]
+use arrayvec::ArrayVec;
+
+/// A message parsed in place.
+///
+/// Creating one checks the structure of the whole message, but no option
+/// value is copied or interpreted until it is asked for, and iterating over
+/// the options doesn't allocate.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MessageRef<'a> {
+    buf: &'a [u8],
+    /// where the options start, just after the token
+    options_start: usize,
+    /// where the payload starts, after the payload marker if there is one
+    payload_start: usize,
+}
+
+impl<'a> MessageRef<'a> {
+    /// RFC 7252: 3.  Message Format
+    pub fn new(buf: &'a [u8]) -> Result<MessageRef<'a>, Error> {
+        if buf.len() < 4 {
+            return Err(Error::MessageFormat);
+        }
+
+        let token_length = (buf[0] & 0x0F) as usize;
+
+        if token_length > 8 {
+            return Err(Error::InvalidToken);
+        }
+
+        if buf.len() < 4 + token_length {
+            return Err(Error::MessageFormat);
+        }
+
+        let options_start = 4 + token_length;
+        let mut options = OptionsIter::new(buf, options_start);
+        let mut options_end = options.position;
+
+        while options.read()?.is_some() {
+            options_end = options.position;
+        }
+
+        // a payload marker followed by a zero-length payload is a format
+        // error
+        if options.position != options_end && options.position == buf.len() {
+            return Err(Error::MessageFormat);
+        }
+
+        Ok(MessageRef {
+            buf,
+            options_start,
+            payload_start: options.position,
+        })
+    }
+
+    pub fn version(&self) -> u8 {
+        self.buf[0] >> 6
+    }
+
+    pub fn mtype(&self) -> Mtype {
+        Mtype::from_u8((self.buf[0] >> 4) & 0x03)
+    }
+
+    pub fn code(&self) -> Code {
+        Code::from_u8(self.buf[1])
+    }
+
+    pub fn mid(&self) -> u16 {
+        (u16::from(self.buf[2]) << 8) | u16::from(self.buf[3])
+    }
+
+    pub fn token(&self) -> &'a [u8] {
+        &self.buf[4..self.options_start]
+    }
+
+    /// The options in order of their number, as `(number, value)`.
+    pub fn options(&self) -> OptionsIter<'a> {
+        OptionsIter::new(&self.buf[..self.payload_start], self.options_start)
+    }
+
+    /// The first value of an option, if present.
+    pub fn option(&self, number: u16) -> Option<&'a [u8]> {
+        self.options()
+            .skip_while(|&(n, _)| n < number)
+            .take_while(|&(n, _)| n == number)
+            .map(|(_, value)| value)
+            .next()
+    }
+
+    pub fn payload(&self) -> &'a [u8] {
+        &self.buf[self.payload_start..]
+    }
+
+    /// The whole encoded message.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.buf
+    }
+
+    /// Copy the message out of the buffer.
+    #[cfg(feature = "std")]
+    pub fn to_message(&self) -> Message {
+        let mut token = ArrayVec::new();
+        token.extend(self.token().iter().cloned());
+
+        let mut options = Options::new();
+        for (number, value) in self.options() {
+            options.push_raw(number, value.to_vec());
+        }
+
+        Message {
+            version: self.version(),
+            mtype: self.mtype(),
+            code: self.code(),
+            mid: self.mid(),
+            token,
+            options,
+            payload: self.payload().to_vec(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a> From<MessageRef<'a>> for Message {
+    fn from(msg: MessageRef<'a>) -> Message {
+        msg.to_message()
+    }
+}
+
+/// An iterator over the options of a `MessageRef`.
+#[derive(Clone, Debug)]
+pub struct OptionsIter<'a> {
+    buf: &'a [u8],
+    position: usize,
+    last_number: u16,
+}
+
+impl<'a> OptionsIter<'a> {
+    pub(super) fn new(buf: &'a [u8], position: usize) -> OptionsIter<'a> {
+        OptionsIter {
+            buf,
+            position,
+            last_number: 0,
+        }
+    }
+
+    /// RFC 7252: 3.1.  Option Format
+    ///
+    /// Read the next option, `None` once the payload marker or the end of the
+    /// message is reached.
+    fn read(&mut self) -> Result<Option<(u16, &'a [u8])>, Error> {
+        let buf = self.buf;
+        let mut i = self.position;
+
+        if i >= buf.len() {
+            return Ok(None);
+        }
+
+        if buf[i] == 0xFF {
+            self.position = i + 1;
+            return Ok(None);
+        }
+
+        let header = buf[i];
+        i += 1;
+
+        let delta = read_extended(buf, &mut i, header >> 4)?;
+        let length = read_extended(buf, &mut i, header & 0x0F)?;
+
+        // RFC 7252: 5.4.6.  Option numbers are at most 65535
+        let number = self.last_number as usize + delta;
+        if number > u16::MAX as usize {
+            return Err(Error::InvalidOptionNumber);
+        }
+
+        if buf.len() < i + length {
+            return Err(Error::MessageFormat);
+        }
+
+        self.position = i + length;
+        self.last_number = number as u16;
+
+        Ok(Some((number as u16, &buf[i..i + length])))
+    }
+}
+
+impl<'a> Iterator for OptionsIter<'a> {
+    type Item = (u16, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // the structure was checked when the message was created
+        self.read().unwrap_or(None)
+    }
+}
+
+/// Read an option delta or length, extended by the bytes at `i` if `nibble`
+/// asks for it.
+fn read_extended(buf: &[u8], i: &mut usize, nibble: u8) -> Result<usize, Error> {
+    match nibble {
+        0..=12 => Ok(nibble as usize),
+        13 => {
+            let byte = *buf.get(*i).ok_or(Error::MessageFormat)?;
+            *i += 1;
+            Ok(byte as usize + 13)
+        }
+        14 => {
+            if buf.len() < *i + 2 {
+                return Err(Error::MessageFormat);
+            }
+            let value = ((buf[*i] as usize) << 8 | buf[*i + 1] as usize) + 269;
+            *i += 2;
+            Ok(value)
+        }
+        _ => Err(Error::MessageFormat),
+    }
+}
+
+#[test]
+fn test_msg_ref_view() {
+    // CON POST with token 0x99, Uri-Path "1a", "temp" and Uri-Query "a", payload
+    let bytes = [0x41, 0x02, 0x00, 0x37, 0x99, 0xb2, 0x31, 0x61, 0x04, 0x74, 0x65, 0x6d, 0x70,
+                 0x41, 0x61, 0xFF, 0x39, 0x39];
+    let msg = MessageRef::new(&bytes).unwrap();
+
+    assert_eq!(msg.version(), 1);
+    assert_eq!(msg.mtype(), Mtype::Confirmable);
+    assert_eq!(msg.code(), Code::Post);
+    assert_eq!(msg.mid(), 0x37);
+    assert_eq!(msg.token(), &[0x99]);
+    assert!(msg.options().eq([(11, &b"1a"[..]), (11, &b"temp"[..]), (15, &b"a"[..])].iter().cloned()));
+    assert_eq!(msg.option(11), Some(&b"1a"[..]));
+    assert_eq!(msg.option(15), Some(&b"a"[..]));
+    assert_eq!(msg.option(12), None);
+    assert_eq!(msg.payload(), b"99");
+
+    #[cfg(feature = "std")]
+    {
+        assert_eq!(msg.to_message(), Message::from_bytes(&bytes).unwrap());
+        assert_eq!(Message::from(msg).to_bytes().unwrap(), &bytes[..]);
+    }
+}
+
+#[test]
+fn test_msg_ref_malformed() {
+    assert_eq!(MessageRef::new(&[0x40, 0x01, 0x00]), Err(Error::MessageFormat));
+    assert_eq!(MessageRef::new(&[0x49, 0x01, 0x00, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0]), Err(Error::InvalidToken));
+    assert_eq!(MessageRef::new(&[0x40, 0x01, 0x00, 0x01, 0xb2, 0x31]), Err(Error::MessageFormat));
+    assert_eq!(MessageRef::new(&[0x40, 0x01, 0x00, 0x01, 0xf0]), Err(Error::MessageFormat));
+
+    assert_eq!(MessageRef::new(&[0x40, 0x01, 0x00, 0x01, 0xFF]), Err(Error::MessageFormat));
+    assert_eq!(MessageRef::new(&[0x40, 0x01, 0x00, 0x01, 0xb1, 0x61, 0xFF]), Err(Error::MessageFormat));
+
+    // an option value may end in 0xFF just as well
+    let msg = MessageRef::new(&[0x40, 0x01, 0x00, 0x01, 0xb1, 0xFF]).unwrap();
+    assert_eq!(msg.option(11), Some(&[0xFF][..]));
+    assert_eq!(msg.payload(), &[]);
+}
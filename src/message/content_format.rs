@@ -0,0 +1,179 @@
+//! RFC 7252: 12.3.  CoAP Content-Formats Registry
+
+use std::fmt;
+use std::str;
+
+use super::Error;
+
+macro_rules! content_formats {
+    ($(($num:expr, $name:ident, $media_type:expr),)+) => {
+        /// The format of a payload, as given by the Content-Format and Accept
+        /// options.
+        ///
+        /// Every Content-Format registered with IANA has a variant, other
+        /// numbers are kept as `Unknown`.
+        #[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+        pub enum ContentFormat {
+            $($name,)+
+            Unknown(u16),
+        }
+
+        impl ContentFormat {
+            pub fn from_u16(number: u16) -> ContentFormat {
+                match number {
+                    $($num => ContentFormat::$name,)+
+                    _ => ContentFormat::Unknown(number),
+                }
+            }
+
+            pub fn as_u16(&self) -> u16 {
+                match *self {
+                    $(ContentFormat::$name => $num,)+
+                    ContentFormat::Unknown(number) => number,
+                }
+            }
+
+            /// The media type with its parameters and content coding, if
+            /// this is a registered format.
+            pub fn media_type(&self) -> Option<&'static str> {
+                match *self {
+                    $(ContentFormat::$name => Some($media_type),)+
+                    ContentFormat::Unknown(_) => None,
+                }
+            }
+
+            /// The registered format of a media type such as
+            /// `application/cbor`. Case and the spacing around parameters
+            /// don't matter.
+            pub fn from_media_type(media_type: &str) -> Option<ContentFormat> {
+                const FORMATS: &[ContentFormat] = &[$(ContentFormat::$name,)+];
+
+                FORMATS.iter()
+                    .find(|format| format.media_type().map_or(false, |m| same_media_type(m, media_type)))
+                    .cloned()
+            }
+        }
+    }
+}
+
+content_formats! {
+    (0, TextPlain, "text/plain; charset=utf-8"),
+    (16, CoseEncrypt0, "application/cose; cose-type=\"cose-encrypt0\""),
+    (17, CoseMac0, "application/cose; cose-type=\"cose-mac0\""),
+    (18, CoseSign1, "application/cose; cose-type=\"cose-sign1\""),
+    (19, AceCbor, "application/ace+cbor"),
+    (21, Gif, "image/gif"),
+    (22, Jpeg, "image/jpeg"),
+    (23, Png, "image/png"),
+    (40, LinkFormat, "application/link-format"),
+    (41, Xml, "application/xml"),
+    (42, OctetStream, "application/octet-stream"),
+    (47, Exi, "application/exi"),
+    (50, Json, "application/json"),
+    (51, JsonPatchJson, "application/json-patch+json"),
+    (52, MergePatchJson, "application/merge-patch+json"),
+    (60, Cbor, "application/cbor"),
+    (61, Cwt, "application/cwt"),
+    (62, MultipartCore, "application/multipart-core"),
+    (63, CborSeq, "application/cbor-seq"),
+    (96, CoseEncrypt, "application/cose; cose-type=\"cose-encrypt\""),
+    (97, CoseMac, "application/cose; cose-type=\"cose-mac\""),
+    (98, CoseSign, "application/cose; cose-type=\"cose-sign\""),
+    (101, CoseKey, "application/cose-key"),
+    (102, CoseKeySet, "application/cose-key-set"),
+    (110, SenmlJson, "application/senml+json"),
+    (111, SensmlJson, "application/sensml+json"),
+    (112, SenmlCbor, "application/senml+cbor"),
+    (113, SensmlCbor, "application/sensml+cbor"),
+    (114, SenmlExi, "application/senml-exi"),
+    (115, SensmlExi, "application/sensml-exi"),
+    (140, YangDataCbor, "application/yang-data+cbor; id=sid"),
+    (256, CoapGroupJson, "application/coap-group+json"),
+    (271, DotsCbor, "application/dots+cbor"),
+    (272, MissingBlocksCborSeq, "application/missing-blocks+cbor-seq"),
+    (280, Pkcs7ServerGeneratedKey, "application/pkcs7-mime; smime-type=server-generated-key"),
+    (281, Pkcs7CertsOnly, "application/pkcs7-mime; smime-type=certs-only"),
+    (284, Pkcs8, "application/pkcs8"),
+    (285, CsrAttrs, "application/csrattrs"),
+    (286, Pkcs10, "application/pkcs10"),
+    (287, PkixCert, "application/pkix-cert"),
+    (310, SenmlXml, "application/senml+xml"),
+    (311, SensmlXml, "application/sensml+xml"),
+    (320, SenmlEtchJson, "application/senml-etch+json"),
+    (322, SenmlEtchCbor, "application/senml-etch+cbor"),
+    (432, TdJson, "application/td+json"),
+    (10001, OcfCbor, "application/vnd.ocf+cbor"),
+    (10002, Oscore, "application/oscore"),
+    (11542, Lwm2mTlv, "application/vnd.oma.lwm2m+tlv"),
+    (11543, Lwm2mJson, "application/vnd.oma.lwm2m+json"),
+}
+
+/// Compare media types ignoring case and whitespace.
+fn same_media_type(a: &str, b: &str) -> bool {
+    let a = a.bytes().filter(|c| !c.is_ascii_whitespace()).map(|c| c.to_ascii_lowercase());
+    let b = b.bytes().filter(|c| !c.is_ascii_whitespace()).map(|c| c.to_ascii_lowercase());
+
+    a.eq(b)
+}
+
+impl From<u16> for ContentFormat {
+    fn from(number: u16) -> ContentFormat {
+        ContentFormat::from_u16(number)
+    }
+}
+
+impl From<ContentFormat> for u16 {
+    fn from(format: ContentFormat) -> u16 {
+        format.as_u16()
+    }
+}
+
+/// Formats are written as their media type, unknown ones by their number.
+impl fmt::Display for ContentFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.media_type() {
+            Some(media_type) => f.write_str(media_type),
+            None => write!(f, "{}", self.as_u16()),
+        }
+    }
+}
+
+/// Parse a registered media type, or a Content-Format number.
+impl str::FromStr for ContentFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ContentFormat, Error> {
+        ContentFormat::from_media_type(s)
+            .or_else(|| s.parse().ok().map(ContentFormat::from_u16))
+            .ok_or(Error::UnknownMediaType)
+    }
+}
+
+#[test]
+fn test_content_format_registry() {
+    for number in 0..=u16::MAX {
+        let format = ContentFormat::from_u16(number);
+        assert_eq!(format.as_u16(), number);
+
+        if let Some(media_type) = format.media_type() {
+            assert_eq!(ContentFormat::from_media_type(media_type), Some(format));
+            assert_eq!(media_type.parse(), Ok(format));
+            #[cfg(feature = "std")]
+            assert_eq!(format.to_string().parse(), Ok(format));
+        }
+    }
+}
+
+#[test]
+fn test_content_format_media_types() {
+    assert_eq!(ContentFormat::Cbor.media_type(), Some("application/cbor"));
+    assert_eq!(ContentFormat::from_media_type("Text/Plain;charset=UTF-8"), Some(ContentFormat::TextPlain));
+    assert_eq!(ContentFormat::from_media_type("application/senml+cbor"), Some(ContentFormat::SenmlCbor));
+    assert_eq!(ContentFormat::from_media_type("application/x-unknown"), None);
+
+    #[cfg(feature = "std")]
+    assert_eq!(ContentFormat::Unknown(65000).to_string(), "65000");
+    assert_eq!("65000".parse(), Ok(ContentFormat::Unknown(65000)));
+    assert_eq!("50".parse(), Ok(ContentFormat::Json));
+    assert_eq!("application/x-unknown".parse::<ContentFormat>(), Err(Error::UnknownMediaType));
+}
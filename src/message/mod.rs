@@ -1,10 +1,28 @@
+#[macro_use]
 pub mod option;
+mod borrowed;
+mod content_format;
+mod packet;
 
-use self::option::Options;
+pub use self::borrowed::{MessageRef, OptionsIter};
+pub use self::content_format::ContentFormat;
+pub use self::packet::{Packet, MAX_OPTIONS};
 
+#[cfg(feature = "std")]
+use self::option::{Accept, ETag, IfMatch, IfNoneMatch, LocationPath, LocationQuery, MaxAge, Observe, Option, Options, UriPath};
+
+#[cfg(feature = "std")]
+use std::option::Option as StdOption;
+use std::fmt;
+use std::str;
+
+#[cfg(feature = "std")]
 use arrayvec::ArrayVec;
+#[cfg(feature = "std")]
+use bytes::BufMut;
 
-#[derive(PartialEq, Eq, Debug)]
+#[cfg(feature = "std")]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub struct Message {
     pub version: u8,
     pub mtype: Mtype,
@@ -15,15 +33,35 @@ pub struct Message {
     pub payload: Vec<u8>,
 }
 
+/// RFC 7252: 5.10.5.  Max-Age
+#[cfg(feature = "std")]
+const DEFAULT_MAX_AGE: u32 = 60;
+
 #[derive(PartialEq, Debug)]
 pub enum Error {
     MessageFormat,
     InvalidToken,
     InvalidOptionNumber,
-    UnrecognizedCriticalOption, // TODO: use
+    /// the value of this known option has the wrong length or format, or the
+    /// option is repeated but mustn't be
+    InvalidOption(u16),
+    /// this option is not known
+    UnrecognizedOption(u16),
+    /// this critical option is unknown, malformed or wrongly repeated
+    UnrecognizedCriticalOption(u16),
+    /// a different option is already known by this number
+    OptionAlreadyKnown(u16),
+    /// the value of this option is too long to be encoded
+    OptionTooLong(u16),
+    /// the message has more options than a `Packet` can hold
+    TooManyOptions,
+    /// the text isn't a code in `c.dd` format
+    InvalidCode,
+    /// the text isn't a registered media type or a Content-Format number
+    UnknownMediaType,
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
 #[repr(u8)]
 pub enum Mtype {
     Confirmable,
@@ -53,18 +91,26 @@ impl Mtype {
     }
 }
 
-#[derive(PartialEq, Eq, Debug)]
+/// RFC 7252: 12.1.  CoAP Code Registries
+///
+/// Every method, response and signaling code registered with IANA, other
+/// codes are kept as `Unknown`.
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
 pub enum Code {
     Empty,
     Get,
     Post,
     Put,
     Delete,
+    Fetch,
+    Patch,
+    IPatch,
     Created,
     Deleted,
     Valid,
     Changed,
     Content,
+    Continue,
     BadRequest,
     Unauthorized,
     BadOption,
@@ -72,15 +118,25 @@ pub enum Code {
     NotFound,
     MethodNotAllowed,
     NotAcceptable,
+    RequestEntityIncomplete,
+    Conflict,
     PreconditionFailed,
     RequestEntityTooLarge,
     UnsupportedContentFormat,
+    UnprocessableEntity,
+    TooManyRequests,
     InternalServerError,
     NotImplemented,
     BadGateway,
     ServiceUnavailable,
     GatewayTimeout,
     ProxyingNotSupported,
+    HopLimitReached,
+    Csm,
+    Ping,
+    Pong,
+    Release,
+    Abort,
     Unknown(u8),
 }
 
@@ -92,11 +148,15 @@ impl Code {
             2 => Code::Post,
             3 => Code::Put,
             4 => Code::Delete,
+            5 => Code::Fetch,
+            6 => Code::Patch,
+            7 => Code::IPatch,
             65 => Code::Created,
             66 => Code::Deleted,
             67 => Code::Valid,
             68 => Code::Changed,
             69 => Code::Content,
+            95 => Code::Continue,
             128 => Code::BadRequest,
             129 => Code::Unauthorized,
             130 => Code::BadOption,
@@ -104,19 +164,34 @@ impl Code {
             132 => Code::NotFound,
             133 => Code::MethodNotAllowed,
             134 => Code::NotAcceptable,
+            136 => Code::RequestEntityIncomplete,
+            137 => Code::Conflict,
             140 => Code::PreconditionFailed,
             141 => Code::RequestEntityTooLarge,
-            142 => Code::UnsupportedContentFormat,
+            143 => Code::UnsupportedContentFormat,
+            150 => Code::UnprocessableEntity,
+            157 => Code::TooManyRequests,
             160 => Code::InternalServerError,
             161 => Code::NotImplemented,
             162 => Code::BadGateway,
             163 => Code::ServiceUnavailable,
             164 => Code::GatewayTimeout,
             165 => Code::ProxyingNotSupported,
+            168 => Code::HopLimitReached,
+            225 => Code::Csm,
+            226 => Code::Ping,
+            227 => Code::Pong,
+            228 => Code::Release,
+            229 => Code::Abort,
             _ => Code::Unknown(raw_code),
         }
     }
 
+    /// The code `class.detail`.
+    pub fn new(class: u8, detail: u8) -> Code {
+        Code::from_u8(Self::build(class, detail))
+    }
+
     pub fn as_u8(&self) -> u8 {
         match *self {
             Code::Empty => Self::build(0, 00),
@@ -124,11 +199,15 @@ impl Code {
             Code::Post => Self::build(0, 02),
             Code::Put => Self::build(0, 03),
             Code::Delete => Self::build(0, 04),
+            Code::Fetch => Self::build(0, 05),
+            Code::Patch => Self::build(0, 06),
+            Code::IPatch => Self::build(0, 07),
             Code::Created => Self::build(2, 01),
             Code::Deleted => Self::build(2, 02),
             Code::Valid => Self::build(2, 03),
             Code::Changed => Self::build(2, 04),
             Code::Content => Self::build(2, 05),
+            Code::Continue => Self::build(2, 31),
             Code::BadRequest => Self::build(4, 00),
             Code::Unauthorized => Self::build(4, 01),
             Code::BadOption => Self::build(4, 02),
@@ -136,15 +215,25 @@ impl Code {
             Code::NotFound => Self::build(4, 04),
             Code::MethodNotAllowed => Self::build(4, 05),
             Code::NotAcceptable => Self::build(4, 06),
+            Code::RequestEntityIncomplete => Self::build(4, 08),
+            Code::Conflict => Self::build(4, 09),
             Code::PreconditionFailed => Self::build(4, 12),
             Code::RequestEntityTooLarge => Self::build(4, 13),
             Code::UnsupportedContentFormat => Self::build(4, 15),
+            Code::UnprocessableEntity => Self::build(4, 22),
+            Code::TooManyRequests => Self::build(4, 29),
             Code::InternalServerError => Self::build(5, 00),
             Code::NotImplemented => Self::build(5, 01),
             Code::BadGateway => Self::build(5, 02),
             Code::ServiceUnavailable => Self::build(5, 03),
             Code::GatewayTimeout => Self::build(5, 04),
             Code::ProxyingNotSupported => Self::build(5, 05),
+            Code::HopLimitReached => Self::build(5, 08),
+            Code::Csm => Self::build(7, 01),
+            Code::Ping => Self::build(7, 02),
+            Code::Pong => Self::build(7, 03),
+            Code::Release => Self::build(7, 04),
+            Code::Abort => Self::build(7, 05),
             Code::Unknown(code) => code,
         }
     }
@@ -161,8 +250,72 @@ impl Code {
     pub fn detail(&self) -> u8 {
         self.as_u8() & 0x1F
     }
+
+    /// Whether this is 0.00, used for empty messages such as a bare ACK.
+    pub fn is_empty(&self) -> bool {
+        *self == Code::Empty
+    }
+
+    /// Whether this is a method code.
+    pub fn is_request(&self) -> bool {
+        self.class() == 0 && !self.is_empty()
+    }
+
+    /// Whether this is a response code of any class.
+    pub fn is_response(&self) -> bool {
+        matches!(self.class(), 2 | 4 | 5)
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.class() == 2
+    }
+
+    pub fn is_client_error(&self) -> bool {
+        self.class() == 4
+    }
+
+    pub fn is_server_error(&self) -> bool {
+        self.class() == 5
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.is_client_error() || self.is_server_error()
+    }
+
+    /// RFC 8323: 5.  CoAP over Reliable Transports: Signaling
+    pub fn is_signaling(&self) -> bool {
+        self.class() == 7
+    }
+}
+
+/// Codes are written as `c.dd`, e.g. `2.05` for Content.
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{:02}", self.class(), self.detail())
+    }
 }
 
+/// Parse a code written as `c.dd`.
+impl str::FromStr for Code {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Code, Error> {
+        let (class, detail) = match *s.as_bytes() {
+            [c, b'.', d1, d2] if [c, d1, d2].iter().all(u8::is_ascii_digit) => {
+                (c - b'0', (d1 - b'0') * 10 + (d2 - b'0'))
+            }
+            _ => return Err(Error::InvalidCode),
+        };
+
+        if class > 7 || detail > 31 {
+            return Err(Error::InvalidCode);
+        }
+
+        Ok(Code::new(class, detail))
+    }
+}
+
+#[cfg(feature = "std")]
 impl Message {
     pub fn new() -> Self {
         Message {
@@ -217,147 +370,323 @@ impl Message {
         self
     }
 
-    pub fn from_bytes(pkt: &[u8]) -> Result<Message, Error> {
-        let mut i: usize;
+    /// Whether every critical option is known, well formed and only repeated
+    /// if it may be.
+    pub fn check_options(&self) -> Result<(), Error> {
+        for (&number, values) in &self.options.map {
+            if option::is_critical(number) && option::check(number, values).is_err() {
+                return Err(Error::UnrecognizedCriticalOption(number));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The Content-Format of the payload, if given.
+    pub fn content_format(&self) -> Result<StdOption<ContentFormat>, Error> {
+        Ok(self.options.get_first::<option::ContentFormat>()?.map(|o| ContentFormat::from_u16(o.into_value() as u16)))
+    }
+
+    pub fn set_content_format(&mut self, format: ContentFormat) {
+        self.options.set(option::ContentFormat::new(u64::from(format.as_u16())));
+    }
+
+    pub fn with_content_format(mut self, format: ContentFormat) -> Self {
+        self.set_content_format(format);
+        self
+    }
+
+    /// The Content-Format the response should have, if the request asks for
+    /// one.
+    pub fn accept(&self) -> Result<StdOption<ContentFormat>, Error> {
+        Ok(self.options.get_first::<Accept>()?.map(|o| ContentFormat::from_u16(o.into_value() as u16)))
+    }
 
-        if pkt.len() < 4 {
+    pub fn set_accept(&mut self, format: ContentFormat) {
+        self.options.set(Accept::new(u64::from(format.as_u16())));
+    }
+
+    pub fn with_accept(mut self, format: ContentFormat) -> Self {
+        self.set_accept(format);
+        self
+    }
+
+    /// Whether a response in `format` is acceptable for this request. If it
+    /// isn't, the server should answer with 4.06 Not Acceptable.
+    pub fn accepts(&self, format: ContentFormat) -> Result<bool, Error> {
+        Ok(self.accept()?.is_none_or(|accept| accept == format))
+    }
+
+    /// How many seconds the response may be cached for, 60 unless given.
+    pub fn max_age(&self) -> Result<u32, Error> {
+        Ok(self.options.get_first::<MaxAge>()?.map(|o| o.into_value() as u32).unwrap_or(DEFAULT_MAX_AGE))
+    }
+
+    pub fn set_max_age(&mut self, seconds: u32) {
+        self.options.set(MaxAge::new(u64::from(seconds)));
+    }
+
+    pub fn with_max_age(mut self, seconds: u32) -> Self {
+        self.set_max_age(seconds);
+        self
+    }
+
+    /// The (first) ETag, if given.
+    pub fn etag(&self) -> Result<StdOption<Vec<u8>>, Error> {
+        Ok(self.options.get_first::<ETag>()?.map(ETag::into_value))
+    }
+
+    /// Set the ETag, which must be between 1 and 8 bytes long.
+    pub fn set_etag(&mut self, etag: &[u8]) -> Result<(), Error> {
+        if etag.is_empty() || etag.len() > 8 {
             return Err(Error::MessageFormat);
         }
 
-        let version = pkt[0] >> 6;
-        let mtype = Mtype::from_u8((pkt[0] >> 4) & 0x03);
-        let token_length = pkt[0] & 0x0F;
-        let code = Code::from_u8(pkt[1]);
-        let mid = ((pkt[2] as u16) << 8) | pkt[3] as u16;
+        self.options.set(ETag::new(etag.to_vec()));
+        Ok(())
+    }
 
-        if pkt.len() < 4 + token_length as usize {
-            return Err(Error::MessageFormat);
+    /// The Uri-Path options joined into an absolute path, e.g.
+    /// `/sensors/temp`.
+    pub fn uri_path(&self) -> Result<String, Error> {
+        join_path(self.options.map.get(&UriPath::NUMBER))
+    }
+
+    /// Replace the Uri-Path options with the segments of `path`.
+    pub fn set_uri_path(&mut self, path: &str) -> Result<(), Error> {
+        let segments = split_path(path)?;
+        self.options.remove::<UriPath>();
+        for segment in segments {
+            self.options.push_raw(UriPath::NUMBER, segment);
+        }
+        Ok(())
+    }
+
+    /// The Location-Path and Location-Query options of a 2.01 Created
+    /// response, e.g. `/sensors/7?rev=2`, if given.
+    pub fn location(&self) -> Result<StdOption<String>, Error> {
+        let path = self.options.map.get(&LocationPath::NUMBER);
+        let query = self.options.map.get(&LocationQuery::NUMBER);
+
+        if path.is_none() && query.is_none() {
+            return Ok(None);
         }
 
-        let token = {
-            let mut token = ArrayVec::new();
-            token.extend(pkt[4..4+token_length as usize].iter().cloned());
-            token
+        let mut location = join_path(path)?;
+        if let Some(query) = query {
+            location.push('?');
+            location.push_str(&join_strings(query, "&")?);
+        }
+
+        Ok(Some(location))
+    }
+
+    /// Replace the Location-Path and Location-Query options with those of
+    /// `location`.
+    pub fn set_location(&mut self, location: &str) -> Result<(), Error> {
+        let (path, query) = match location.find('?') {
+            Some(i) => (&location[..i], Some(&location[i + 1..])),
+            None => (location, None),
         };
 
-        i = 4 + token_length as usize;
+        let path = split_path(path)?;
+        let query = match query {
+            Some(query) => split_segments(query.split('&'))?,
+            None => vec![],
+        };
 
-        let mut options = option::Options::new();
-        let mut option_number_offset = 0u16;
+        self.options.remove::<LocationPath>();
+        self.options.remove::<LocationQuery>();
+        for segment in path {
+            self.options.push_raw(LocationPath::NUMBER, segment);
+        }
+        if !query.is_empty() {
+            self.options.map.insert(LocationQuery::NUMBER, query);
+        }
+        Ok(())
+    }
 
-        while i < pkt.len() {
-            if pkt[i] == 0xFF {
-                i += 1;
-                break;
-            }
+    /// The Observe option, if given.
+    pub fn observe(&self) -> Result<StdOption<u32>, Error> {
+        Ok(self.options.get_first::<Observe>()?.map(|o| o.into_value() as u32))
+    }
 
-            // Note: length errors for 13 & 14 will be caught in the check below.
-            let delta = match pkt[i] >> 4 {
-                d @ 0...12 => d as u16,
-                13 => {
-                    i += 1;
-                    pkt[i] as u16 + 13
-                }
-                14 => {
-                    i += 2;
-                    (((pkt[i - 1] as u16) << 8) | pkt[i] as u16) + 269
-                }
-                15 => return Err(Error::MessageFormat),
-                _ => unreachable!(),
-            };
-            let length = match pkt[i] & 0x0F {
-                d @ 0...12 => d as u16,
-                13 => {
-                    i += 1;
-                    pkt[i] as u16 + 13
-                }
-                14 => {
-                    i += 2;
-                    ((pkt[i - 1] as u16) << 8) | pkt[i] as u16 + 269
-                }
-                15 => return Err(Error::MessageFormat),
-                _ => unreachable!(),
-            };
-
-            i += 1;
-
-            let option_number = option_number_offset + delta;
-            option_number_offset = option_number;
-
-            if length >= 65000 {
-                return Err(Error::MessageFormat);
-            }
+    /// Set the Observe option, only the lower 24 bits are sent.
+    pub fn set_observe(&mut self, observe: u32) {
+        self.options.set(Observe::new(u64::from(observe & 0xFF_FFFF)));
+    }
 
-            if pkt.len() >= i + (length as usize) {
-                options.push_raw(option_number, pkt[i..i+(length as usize)].into());
-            } else {
-                return Err(Error::MessageFormat);
-            }
+    pub fn with_observe(mut self, observe: u32) -> Self {
+        self.set_observe(observe);
+        self
+    }
 
-            i += length as usize;
+    /// RFC 7252: 5.10.8.1.  If-Match
+    ///
+    /// The ETags the target must have one of for the request to be
+    /// performed, an empty one matching any. Empty if the request isn't
+    /// conditional on it.
+    pub fn if_match(&self) -> Result<Vec<Vec<u8>>, Error> {
+        self.options.get::<IfMatch>()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|o| o.map(IfMatch::into_value))
+            .collect()
+    }
+
+    /// Add an ETag to If-Match, which must be at most 8 bytes long. An empty
+    /// one only requires the target to exist.
+    pub fn add_if_match(&mut self, etag: &[u8]) -> Result<(), Error> {
+        if etag.len() > 8 {
+            return Err(Error::MessageFormat);
         }
 
-        let payload = if i < pkt.len() {
-            pkt[i..].to_vec()
+        self.options.push(IfMatch::new(etag.to_vec()));
+        Ok(())
+    }
+
+    /// RFC 7252: 5.10.8.2.  If-None-Match
+    ///
+    /// Whether the request may only be performed if the target doesn't
+    /// exist.
+    pub fn if_none_match(&self) -> bool {
+        self.options.map.contains_key(&IfNoneMatch::NUMBER)
+    }
+
+    pub fn set_if_none_match(&mut self, if_none_match: bool) {
+        if if_none_match {
+            self.options.set(IfNoneMatch::new(()));
         } else {
-            vec![]
-        };
+            self.options.remove::<IfNoneMatch>();
+        }
+    }
+
+    pub fn with_if_none_match(mut self, if_none_match: bool) -> Self {
+        self.set_if_none_match(if_none_match);
+        self
+    }
+
+    pub fn from_bytes(pkt: &[u8]) -> Result<Message, Error> {
+        let mut msg = Message::parse(pkt)?;
+
+        // RFC 7252: 5.4.1.  Critical/Elective
+        //
+        // Unrecognized options are kept, whether to ignore or reject them is
+        // up to whoever processes the message, see `check_options`, and
+        // proxies forward them. Malformed and wrongly repeated options are
+        // treated like unrecognized ones, but as their typed values can't be
+        // used, elective ones are dropped right away.
+        msg.options.map.retain(|&number, values| {
+            option::info(number).is_none() || option::is_critical(number) || option::check(number, values).is_ok()
+        });
+
+        Ok(msg)
+    }
 
-        Ok(Message {
-            version: version,
-            mtype: mtype,
-            code: code,
-            mid: mid,
-            token: token,
-            options: options,
-            payload: payload,
-        })
+    /// Decode the structure of a message, without looking at the options.
+    fn parse(pkt: &[u8]) -> Result<Message, Error> {
+        MessageRef::new(pkt).map(|msg| msg.to_message())
     }
 
     pub fn to_bytes(&self) -> Result<Vec<u8>, Error> {
-        if self.token.len() > 8 {
-            return Err(Error::MessageFormat);
-        }
+        let mut pkt = Vec::with_capacity(self.encoded_len()?);
+
+        self.encode(&mut pkt)?;
 
-        // estimate packet size
-        let mut est_pkt_size: usize = 4 + self.token.len() + 1 + 1 + self.payload.len();
+        Ok(pkt)
+    }
 
-         for (number, bytes) in self.options.iter() {
-             est_pkt_size += 2 + bytes.len() as usize;
+    /// The exact number of bytes `encode` writes.
+    pub fn encoded_len(&self) -> Result<usize, Error> {
+        let mut len = 4 + self.token.len();
+        let mut last_option_number = 0;
 
-             if number >= 65000 {
-                 return Err(Error::MessageFormat);
-             }
-         }
+        for (&number, values) in &self.options.map {
+            for value in values {
+                len += option::Header::new(number, value.len(), last_option_number)?.as_bytes().len() + value.len();
+                last_option_number = number;
+            }
+        }
 
-        let mut pkt = Vec::with_capacity(est_pkt_size);
+        if !self.payload.is_empty() {
+            len += 1 + self.payload.len();
+        }
 
-        pkt.push((self.version << 6) | self.mtype.as_u8() << 4 | self.token.len() as u8);
-        pkt.push(self.code.as_u8());
-        pkt.push((self.mid >> 8) as u8);
-        pkt.push((self.mid & 0xFF) as u8);
+        Ok(len)
+    }
 
-        for byte in &self.token {
-            pkt.push(*byte)
+    /// Write the message to `buf`, which must have room for `encoded_len`
+    /// bytes. Nothing is written if the message can't be encoded.
+    pub fn encode<B: BufMut>(&self, buf: &mut B) -> Result<(), Error> {
+        if buf.remaining_mut() < self.encoded_len()? {
+            return Err(Error::MessageFormat);
         }
 
-         let mut last_option_number = 0;
+        buf.put_u8((self.version << 6) | self.mtype.as_u8() << 4 | self.token.len() as u8);
+        buf.put_u8(self.code.as_u8());
+        buf.put_u16_be(self.mid);
+        buf.put_slice(&self.token);
 
-         for (number, bytes) in self.options.iter() {
-             pkt.extend(option::build_header(number, bytes, &mut last_option_number).iter());
-             pkt.extend(bytes);
-         }
+        let mut last_option_number = 0;
 
-        if self.payload.len() > 0 {
-            pkt.push(0xFF);
-            pkt.extend(&self.payload);
+        for (&number, values) in &self.options.map {
+            for value in values {
+                let header = option::Header::new(number, value.len(), last_option_number)?;
+                buf.put_slice(header.as_bytes());
+                buf.put_slice(value);
+                last_option_number = number;
+            }
         }
 
-        Ok(pkt)
+        if !self.payload.is_empty() {
+            buf.put_u8(0xFF);
+            buf.put_slice(&self.payload);
+        }
+
+        Ok(())
     }
 }
 
+/// Join path segments into an absolute path, `/` if there are none.
+#[cfg(feature = "std")]
+fn join_path(segments: StdOption<&Vec<Vec<u8>>>) -> Result<String, Error> {
+    match segments {
+        Some(segments) => Ok(format!("/{}", join_strings(segments, "/")?)),
+        None => Ok("/".to_string()),
+    }
+}
 
+#[cfg(feature = "std")]
+fn join_strings(values: &[Vec<u8>], separator: &str) -> Result<String, Error> {
+    let strings = values
+        .iter()
+        .map(|v| str::from_utf8(v).map_err(|_| Error::MessageFormat))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(strings.join(separator))
+}
+
+/// Split an absolute path into segments, `/` has none.
+#[cfg(feature = "std")]
+fn split_path(path: &str) -> Result<Vec<Vec<u8>>, Error> {
+    let path = path.strip_prefix('/').unwrap_or(path);
+
+    if path.is_empty() {
+        return Ok(vec![]);
+    }
+
+    split_segments(path.split('/'))
+}
+
+#[cfg(feature = "std")]
+fn split_segments<'a, I: Iterator<Item = &'a str>>(segments: I) -> Result<Vec<Vec<u8>>, Error> {
+    segments
+        .map(|s| if s.len() <= 255 { Ok(s.as_bytes().to_vec()) } else { Err(Error::MessageFormat) })
+        .collect()
+}
+
+
+#[cfg(feature = "std")]
 #[test]
 fn test_msg_parse_empty() {
     let ref_bin = [64, 0, 0, 0];
@@ -375,6 +704,7 @@ fn test_msg_parse_empty() {
     assert!(msg.payload.len() == 0);
 }
 
+#[cfg(feature = "std")]
 #[test]
 fn test_msg_serialize_empty() {
     let ref_bin = [64, 0, 0, 0];
@@ -393,6 +723,7 @@ fn test_msg_serialize_empty() {
     assert!(test_bin == ref_bin);
 }
 
+#[cfg(feature = "std")]
 #[test]
 fn test_msg_parse_empty_con_with_token() {
     let ref_bin = [66, 0, 0, 0, 37, 42];
@@ -410,6 +741,7 @@ fn test_msg_parse_empty_con_with_token() {
     assert!(msg.payload.len() == 0);
 }
 
+#[cfg(feature = "std")]
 #[test]
 fn test_msg_parse_get_con() {
     let ref_bin = [0x41, 0x01, 0x00, 0x37, 0x99, 0xFF, 0x01, 0x02];
@@ -427,6 +759,7 @@ fn test_msg_parse_get_con() {
     assert!(msg.payload == [0x01, 0x02]);
 }
 
+#[cfg(feature = "std")]
 #[test]
 fn test_msg_parse_get_con_with_opts() {
     use self::option::{Option, Options, UriPath, UriQuery};
@@ -455,6 +788,7 @@ fn test_msg_parse_get_con_with_opts() {
     assert!(msg.payload == [0x39, 0x39]);
 }
 
+#[cfg(feature = "std")]
 #[test]
 fn test_msg_encode_get_con_with_opts() {
     use self::option::{Option, Options, UriPath, UriQuery};
@@ -488,3 +822,285 @@ fn test_msg_encode_get_con_with_opts() {
         assert_eq!(test_bin[i], ref_bin[i]);
     }
 }
+
+#[cfg(feature = "std")]
+#[test]
+fn test_msg_parse_extended_option_delta() {
+    // Echo (252) with a two byte value, the delta needs an extra byte
+    let ref_bin = [0x40, 0x01, 0x00, 0x01, 0xd2, 0xef, 0xbe, 0xef];
+
+    let msg = Message::from_bytes(&ref_bin).unwrap();
+
+    assert_eq!(msg.options.map.get(&252), Some(&vec![vec![0xbe, 0xef]]));
+    assert_eq!(msg.to_bytes().unwrap(), ref_bin);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_msg_typed_options() {
+    let mut msg = Message::new().with_code(Code::Content).with_content_format(ContentFormat::Json).with_observe(0x1234_5678);
+
+    assert_eq!(msg.content_format(), Ok(Some(ContentFormat::Json)));
+    assert_eq!(msg.observe(), Ok(Some(0x34_5678)));
+    assert_eq!(msg.max_age(), Ok(60));
+    assert_eq!(msg.etag(), Ok(None));
+
+    msg.set_max_age(0);
+    msg.set_content_format(ContentFormat::TextPlain);
+    msg.set_etag(b"v1").unwrap();
+    msg.set_etag(b"v2").unwrap();
+
+    assert_eq!(msg.max_age(), Ok(0));
+    assert_eq!(msg.content_format(), Ok(Some(ContentFormat::TextPlain)));
+    assert_eq!(msg.etag(), Ok(Some(b"v2".to_vec())));
+    assert_eq!(msg.options.map[&4].len(), 1);
+    assert_eq!(msg.set_etag(&[0; 9]), Err(Error::MessageFormat));
+
+    // a 5 byte Max-Age is malformed
+    msg.options.map.insert(14, vec![vec![1, 2, 3, 4, 5]]);
+    assert_eq!(msg.max_age(), Err(Error::InvalidOption(14)));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_msg_paths() {
+    let mut msg = Message::new();
+    assert_eq!(msg.uri_path(), Ok("/".to_string()));
+    assert_eq!(msg.location(), Ok(None));
+
+    msg.set_uri_path("/sensors/temp").unwrap();
+    assert_eq!(msg.uri_path(), Ok("/sensors/temp".to_string()));
+    assert_eq!(msg.options.map[&11], vec![b"sensors".to_vec(), b"temp".to_vec()]);
+
+    msg.set_uri_path("/").unwrap();
+    assert_eq!(msg.uri_path(), Ok("/".to_string()));
+
+    msg.set_location("/sensors/7?rev=2&x").unwrap();
+    assert_eq!(msg.location(), Ok(Some("/sensors/7?rev=2&x".to_string())));
+
+    msg.set_location("/a").unwrap();
+    assert_eq!(msg.location(), Ok(Some("/a".to_string())));
+
+    msg.options.push_raw(11, vec![0xff]);
+    assert_eq!(msg.uri_path(), Err(Error::MessageFormat));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_msg_parse_invalid_option() {
+    // a 20 byte Uri-Host is fine
+    let mut msg = Message::new();
+    msg.options.push_raw(3, vec![b'a'; 20]);
+    assert_eq!(Message::from_bytes(&msg.to_bytes().unwrap()), Ok(msg.clone()));
+
+    // a 9 byte ETag is not, but it is elective and ignored
+    let mut malformed = msg.clone();
+    malformed.options.push_raw(4, vec![0; 9]);
+    assert_eq!(Message::from_bytes(&malformed.to_bytes().unwrap()), Ok(msg));
+
+    let mut msg = Message::new();
+    msg.options.push_raw(5, vec![]);
+    assert_eq!(Message::from_bytes(&msg.to_bytes().unwrap()), Ok(msg.clone()));
+    assert_eq!(msg.check_options(), Ok(()));
+
+    // critical options are kept to be rejected
+    msg.options.map.insert(5, vec![vec![0]]);
+    let parsed = Message::from_bytes(&msg.to_bytes().unwrap()).unwrap();
+    assert_eq!(parsed.check_options(), Err(Error::UnrecognizedCriticalOption(5)));
+
+    let mut msg = Message::new();
+    msg.options.push_raw(11, vec![0xff]);
+    assert_eq!(msg.check_options(), Err(Error::UnrecognizedCriticalOption(11)));
+    assert_eq!(msg.options.get::<option::UriPath>(), Some(vec![Err(Error::InvalidOption(11))]));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_msg_unrecognized_options() {
+    let mut msg = Message::new();
+    msg.options.push_raw(6, vec![1]);
+    msg.options.push_raw(6, vec![2]);
+    msg.options.push_raw(2048, vec![1]);
+    msg.options.push_raw(11, b"a".to_vec());
+    msg.options.push_raw(11, b"b".to_vec());
+
+    // a repeated Observe is dropped, an unknown elective option is kept but
+    // doesn't fail the check
+    let parsed = Message::from_bytes(&msg.to_bytes().unwrap()).unwrap();
+    assert_eq!(parsed.options.map.keys().collect::<Vec<_>>(), vec![&11, &2048]);
+    assert_eq!(parsed.check_options(), Ok(()));
+
+    msg.options.push_raw(2049, vec![]);
+    let parsed = Message::from_bytes(&msg.to_bytes().unwrap()).unwrap();
+    assert_eq!(parsed.check_options(), Err(Error::UnrecognizedCriticalOption(2049)));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_option_classification() {
+    use self::option::{info, is_critical, is_no_cache_key, is_unsafe};
+
+    // Uri-Host, ETag, Max-Age, Size1
+    assert!(is_critical(3) && is_unsafe(3) && !is_no_cache_key(3));
+    assert!(!is_critical(4) && !is_unsafe(4) && !is_no_cache_key(4));
+    assert!(!is_critical(14) && is_unsafe(14));
+    assert!(!is_critical(60) && !is_unsafe(60) && is_no_cache_key(60));
+
+    assert_eq!(info(39).map(|i| i.name), Some("ProxyScheme"));
+    assert!(info(11).unwrap().repeatable);
+    assert!(info(2048).is_none());
+
+    for pair in option::KNOWN_OPTIONS.windows(2) {
+        assert!(pair[0].number < pair[1].number);
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_msg_parse_truncated_option_header() {
+    // extended deltas and lengths running past the end of the message
+    assert_eq!(Message::from_bytes(&[0x40, 0x01, 0x00, 0x01, 0xd0]), Err(Error::MessageFormat));
+    assert_eq!(Message::from_bytes(&[0x40, 0x01, 0x00, 0x01, 0xe0, 0x01]), Err(Error::MessageFormat));
+    assert_eq!(Message::from_bytes(&[0x40, 0x01, 0x00, 0x01, 0x1d]), Err(Error::MessageFormat));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_registered_option() {
+    coap_option!(65002, Vendor, string, 1, 4, false);
+
+    let mut msg = Message::new();
+    msg.options.push_raw(65002, b"toolong".to_vec());
+    msg.options.push_raw(65002, b"ok".to_vec());
+    assert_eq!(msg.check_options(), Ok(()));
+
+    option::register(Vendor::INFO).unwrap();
+    option::register(Vendor::INFO).unwrap();
+    assert_eq!(option::register(option::OptionInfo { number: 11, ..Vendor::INFO }),
+               Err(Error::OptionAlreadyKnown(11)));
+
+    // an unknown option is accepted as it is, once registered it is validated
+    assert_eq!(option::validate(65002, b"toolong"), Err(Error::InvalidOption(65002)));
+    assert_eq!(option::check(65002, &msg.options.map[&65002]), Err(Error::InvalidOption(65002)));
+
+    let msg = Message::new().with_option(Vendor::new("ok".to_string())).with_content_format(ContentFormat::TextPlain);
+    let parsed = Message::from_bytes(&msg.to_bytes().unwrap()).unwrap();
+    assert_eq!(parsed.options.get_first::<Vendor>(), Ok(Some(Vendor::new("ok".to_string()))));
+    assert_eq!(parsed.options.to_string(), "ContentFormat: 0, Vendor: \"ok\"");
+
+    option::unregister(65002);
+    assert_eq!(option::info(65002), None);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_msg_option_extremes() {
+    let mut msg = Message::new();
+    msg.options.push_raw(65535, vec![0; option::MAX_EXTENDED]);
+    assert_eq!(Message::parse(&msg.to_bytes().unwrap()), Ok(msg.clone()));
+
+    msg.options.push_raw(65535, vec![0; option::MAX_EXTENDED + 1]);
+    assert_eq!(msg.to_bytes(), Err(Error::OptionTooLong(65535)));
+
+    // delta 14 with the largest extended delta, after option 65535
+    let pkt = [0x40, 0x01, 0x00, 0x01, 0x10, 0xe0, 0xff, 0xff];
+    assert_eq!(Message::parse(&pkt), Err(Error::InvalidOptionNumber));
+    let pkt = [0x40, 0x01, 0x00, 0x01, 0xe0, 0xfe, 0xf2];
+    assert_eq!(Message::parse(&pkt).unwrap().options.map.keys().collect::<Vec<_>>(), vec![&65535]);
+}
+
+#[cfg(all(test, feature = "std"))]
+quickcheck! {
+    fn prop_msg_options_round_trip(options: Vec<(u16, u32)>, payload: Vec<u8>) -> bool {
+        let mut msg = Message::new().with_payload(payload);
+
+        // a handful of options, with lengths anywhere in the encodable range
+        for &(number, length) in options.iter().take(4) {
+            let length = length as usize % (option::MAX_EXTENDED + 1);
+            msg.options.push_raw(number, vec![number as u8; length]);
+        }
+
+        Message::parse(&msg.to_bytes().unwrap()) == Ok(msg)
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_msg_encode_into_buf() {
+    use bytes::BytesMut;
+    use codec::CoapCodec;
+    use tokio_io::codec::Encoder;
+
+    let mut msg = Message::new()
+        .with_code(Code::Post)
+        .with_token(&[1, 2, 3])
+        .with_content_format(ContentFormat::Json)
+        .with_payload(b"{}".to_vec());
+    msg.set_uri_path("/a/very/long/path/segment/that/needs/an/extended/length/header/xxxxxxxxxx").unwrap();
+    msg.options.push_raw(2000, vec![]);
+
+    let bytes = msg.to_bytes().unwrap();
+    assert_eq!(msg.encoded_len(), Ok(bytes.len()));
+
+    let mut buf = BytesMut::new();
+    CoapCodec.encode(msg.clone(), &mut buf).unwrap();
+    assert_eq!(&buf[..], &bytes[..]);
+
+    // nothing is written to a buffer that is too small
+    let mut small = BytesMut::with_capacity(8);
+    assert_eq!(msg.encode(&mut small), Err(Error::MessageFormat));
+    assert!(small.is_empty());
+
+    msg.options.push_raw(2001, vec![0; option::MAX_EXTENDED + 1]);
+    let mut buf = BytesMut::new();
+    match CoapCodec.encode(msg, &mut buf) {
+        Err(::error::Error::Message(Error::OptionTooLong(2001))) => (),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert!(buf.is_empty());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_code_registry() {
+    for raw in 0..=255u8 {
+        assert_eq!(Code::from_u8(raw).as_u8(), raw);
+    }
+
+    assert_eq!(Code::new(4, 9), Code::Conflict);
+    assert_eq!(Code::TooManyRequests.as_u8(), 157);
+    assert_eq!(Code::HopLimitReached.to_string(), "5.08");
+    assert_eq!(Code::Abort.to_string(), "7.05");
+    assert_eq!(Code::Unknown(0x26).to_string(), "1.06");
+
+    assert_eq!("2.05".parse(), Ok(Code::Content));
+    assert_eq!("4.22".parse(), Ok(Code::UnprocessableEntity));
+    assert_eq!("6.31".parse(), Ok(Code::Unknown(0xDF)));
+    for invalid in &["", "2.5", "2,05", "8.00", "2.32", "+.05", "2.05 "] {
+        assert_eq!(invalid.parse::<Code>(), Err(Error::InvalidCode));
+    }
+
+    assert!(Code::Fetch.is_request());
+    assert!(!Code::Empty.is_request());
+    assert!(Code::Valid.is_success() && Code::Valid.is_response());
+    assert!(Code::Conflict.is_client_error() && Code::Conflict.is_error());
+    assert!(Code::HopLimitReached.is_server_error());
+    assert!(Code::Ping.is_signaling() && !Code::Ping.is_response());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_msg_conditions() {
+    let mut msg = Message::new().with_code(Code::Put).with_if_none_match(true);
+    msg.add_if_match(b"v1").unwrap();
+    msg.add_if_match(b"").unwrap();
+    assert_eq!(msg.add_if_match(&[0; 9]), Err(Error::MessageFormat));
+
+    let parsed = Message::from_bytes(&msg.to_bytes().unwrap()).unwrap();
+    assert!(parsed.if_none_match());
+    assert_eq!(parsed.if_match(), Ok(vec![b"v1".to_vec(), vec![]]));
+
+    msg.set_if_none_match(false);
+    assert!(!msg.if_none_match());
+    assert_eq!(Message::new().if_match(), Ok(vec![]));
+}
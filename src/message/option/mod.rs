@@ -1,15 +1,22 @@
+#[cfg(feature = "std")]
 use std::collections::BTreeMap;
+#[cfg(feature = "std")]
 use std::borrow::Cow;
+use std::fmt;
 use std::str;
+#[cfg(feature = "std")]
+use std::sync::RwLock;
 use message::Error;
 
 use std::option::Option as StdOption;
 
-#[derive(PartialEq, Eq, Debug)]
+#[cfg(feature = "std")]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub struct Options {
     pub map: BTreeMap<u16, Vec<Vec<u8>>>,
 }
 
+#[cfg(feature = "std")]
 impl Options {
     pub fn new() -> Self {
         Options {
@@ -35,14 +42,32 @@ impl Options {
             .push(raw_value);
     }
 
-    pub fn get<T: Option>(&self) -> StdOption<Vec<T>> {
+    /// Every value of an option, each of which may be malformed.
+    pub fn get<T: Option>(&self) -> StdOption<Vec<Result<T, Error>>> {
         self.map
             .get(&<T as Option>::NUMBER)
             .map(|o| o.iter()
-                      .map(|v| <T as Option>::from_bytes(v.as_ref()).unwrap() )
+                      .map(|v| <T as Option>::from_bytes(v.as_ref()))
                       .collect())
     }
 
+    /// The first value of an option, `Err` if it is malformed.
+    pub fn get_first<T: Option>(&self) -> Result<StdOption<T>, Error> {
+        match self.map.get(&<T as Option>::NUMBER).and_then(|values| values.first()) {
+            Some(value) => <T as Option>::from_bytes(value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Replace any values of an option with a single one.
+    pub fn set<T: Option + Byteable>(&mut self, option: T) {
+        self.map.insert(option.number(), vec![option.to_bytes().into_owned()]);
+    }
+
+    pub fn remove<T: Option>(&mut self) {
+        self.map.remove(&<T as Option>::NUMBER);
+    }
+
     pub fn get_raw<T: Option>(&self) -> StdOption<Vec<Vec<u8>>> {
         self.map
             .get(&<T as Option>::NUMBER)
@@ -50,11 +75,13 @@ impl Options {
     }
 }
 
+#[cfg(feature = "std")]
 pub struct RawOptionsIterator<'a> {
     options: &'a Options,
     place: usize
 }
 
+#[cfg(feature = "std")]
 impl<'a> RawOptionsIterator<'a> {
     fn new(options: &'a Options) -> RawOptionsIterator<'a> {
         RawOptionsIterator {
@@ -64,6 +91,7 @@ impl<'a> RawOptionsIterator<'a> {
     }
 }
 
+#[cfg(feature = "std")]
 impl<'a> Iterator for RawOptionsIterator<'a> {
     type Item = (u16, &'a [u8]);
 
@@ -74,14 +102,42 @@ impl<'a> Iterator for RawOptionsIterator<'a> {
     }
 }
 
+/// Options are written one after another as `name: value`, e.g.
+/// `UriPath: "temp", ContentFormat: 0`, unknown ones by their number.
+#[cfg(feature = "std")]
+impl fmt::Display for Options {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, (number, value)) in self.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+
+            match info(number) {
+                Some(info) => {
+                    write!(f, "{}: ", info.name)?;
+                    info.fmt_value(value, f)?;
+                }
+                None => {
+                    write!(f, "{}: ", number)?;
+                    fmt_opaque(value, f)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
 pub trait Option: Sized {
     const NUMBER: u16;
     type Format;
 
     fn new(Self::Format) -> Self;
     fn from_bytes(bytes: &[u8]) -> Result<Self, Error>;
+    fn into_value(self) -> Self::Format;
 }
 
+#[cfg(feature = "std")]
 pub trait Byteable {
     fn number(&self) -> u16;
 
@@ -90,59 +146,122 @@ pub trait Byteable {
     // TODO: add as_bytes, into_bytes
 }
 
-pub fn build_header<'a>(number: u16, bytes: &[u8], last_option_number: &mut u16) -> Cow<'a, [u8]> {
-    let mut header = vec![0u8];
+/// RFC 7252: 3.1.  Option Format
+///
+/// Build the header of an option, options must be given in order of their
+/// number.
+#[cfg(feature = "std")]
+pub fn build_header<'a>(number: u16, bytes: &[u8], last_option_number: &mut u16) -> Result<Cow<'a, [u8]>, Error> {
+    let header = Header::new(number, bytes.len(), *last_option_number)?;
 
-    if number < *last_option_number {
-        panic!("bad order");
-    }
+    *last_option_number = number;
 
-    let delta = number - *last_option_number;
-    let base_delta = match delta {
-        0...12 => delta,
-        13...268 => {
-            header.push((delta - 13) as u8);
-            13
-        }
-        269...64999 => {
-            header.push(((delta - 269) >> 8) as u8);
-            header.push((delta - 269) as u8);
-            14
-        }
-        _ => unreachable!(),
-    } as u8;
-    let length = bytes.len();
-    let base_length = match length {
-        0...12 => length,
-        13...268 => {
-            header.push((length - 13) as u8);
-            13
-        }
-        269...64999 => {
-            header.push(((length - 269) >> 8) as u8);
-            header.push((length - 269) as u8);
-            14
+    Ok(Cow::Owned(header.as_bytes().to_vec()))
+}
+
+/// The largest option delta or length that can be encoded.
+pub const MAX_EXTENDED: usize = 269 + 0xFFFF;
+
+/// RFC 7252: 3.1.  Option Format
+///
+/// The header of an option, at most 5 bytes long.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    bytes: [u8; 5],
+    len: usize,
+}
+
+impl Header {
+    /// The header of an option with a value of `length` bytes, following an
+    /// option numbered `last_option_number`.
+    pub fn new(number: u16, length: usize, last_option_number: u16) -> Result<Header, Error> {
+        if number < last_option_number {
+            return Err(Error::InvalidOptionNumber);
         }
-        _ => panic!("option too big"),
-    } as u8;
 
-    header[0] = base_delta << 4 | base_length;
+        let mut header = Header { bytes: [0; 5], len: 1 };
+
+        let delta = header.push_extended((number - last_option_number) as usize).ok_or(Error::InvalidOptionNumber)?;
+        let length = header.push_extended(length).ok_or(Error::OptionTooLong(number))?;
 
-    *last_option_number = *last_option_number + delta;
+        header.bytes[0] = delta << 4 | length;
+
+        Ok(header)
+    }
 
-    Cow::Owned(header)
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    /// Push the extended bytes of an option delta or length, returning the
+    /// nibble to go into the first byte of the option.
+    fn push_extended(&mut self, value: usize) -> StdOption<u8> {
+        match value {
+            0..=12 => Some(value as u8),
+            13..=268 => {
+                self.bytes[self.len] = (value - 13) as u8;
+                self.len += 1;
+                Some(13)
+            }
+            269..=MAX_EXTENDED => {
+                self.bytes[self.len] = ((value - 269) >> 8) as u8;
+                self.bytes[self.len + 1] = (value - 269) as u8;
+                self.len += 2;
+                Some(14)
+            }
+            _ => None,
+        }
+    }
 }
 
 /// This builds the full type for each individual option.
-macro_rules! option {
+///
+/// It is exported so that other crates can define their own options, e.g.
+///
+/// ```
+/// #[macro_use]
+/// extern crate tokio_coap;
+///
+/// use tokio_coap::message::option::{self, Option, Options};
+///
+/// // (number, name, format, min length, max length, repeatable)
+/// coap_option!(65001, VendorMode, uint, 0, 1, false);
+///
+/// fn main() {
+///     option::register(VendorMode::INFO).unwrap();
+///
+///     let mut options = Options::new();
+///     options.push(VendorMode::new(1));
+///
+///     assert_eq!(options.get_first::<VendorMode>(), Ok(Some(VendorMode::new(1))));
+///     assert_eq!(options.to_string(), "VendorMode: 1");
+/// }
+/// ```
+#[macro_export]
+macro_rules! coap_option {
+    (@info $num: expr, $name: ident, $format: ident, $min: expr, $max: expr, $repeatable: expr) => {
+        impl $name {
+            pub const INFO: $crate::message::option::OptionInfo = $crate::message::option::OptionInfo {
+                number: $num,
+                name: stringify!($name),
+                format: $crate::value_format!($format),
+                min_len: $min,
+                max_len: $max,
+                repeatable: $repeatable,
+            };
+        }
+    };
+
     // Opaque Type Options
-    ($num: expr, $name: ident, opaque, $min: expr, $max: expr) => {
+    ($num: expr, $name: ident, opaque, $min: expr, $max: expr, $repeatable: expr) => {
+        $crate::coap_option!(@info $num, $name, opaque, $min, $max, $repeatable);
+
         #[derive(PartialEq, Eq, Debug)]
         pub struct $name {
             value: Vec<u8>
         }
 
-        impl Option for $name {
+        impl $crate::message::option::Option for $name {
             const NUMBER: u16 = $num;
             type Format = Vec<u8>;
 
@@ -150,22 +269,26 @@ macro_rules! option {
                 $name{value: value}
             }
 
-            fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+            fn from_bytes(bytes: &[u8]) -> Result<Self, $crate::message::Error> {
                 if bytes.len() >= $min as usize && bytes.len() <= $max as usize {
                     Ok(Self{value: bytes.to_vec()})
                 } else {
-                    Err(Error::MessageFormat)
+                    Err($crate::message::Error::InvalidOption($num))
                 }
             }
+
+            fn into_value(self) -> Self::Format {
+                self.value
+            }
         }
 
-        impl Byteable for $name {
+        impl $crate::message::option::Byteable for $name {
             fn number(&self) -> u16 {
                 $num
             }
 
-            fn to_bytes(&self) -> Cow<[u8]> {
-                Cow::Owned(self.value.clone())
+            fn to_bytes(&self) -> ::std::borrow::Cow<[u8]> {
+                ::std::borrow::Cow::Owned(self.value.clone())
             }
 
             fn bytes_len(&self) -> usize {
@@ -184,13 +307,15 @@ macro_rules! option {
     };
 
     // String Type Options
-    ($num: expr, $name: ident, string, $min: expr, $max: expr) => {
+    ($num: expr, $name: ident, string, $min: expr, $max: expr, $repeatable: expr) => {
+        $crate::coap_option!(@info $num, $name, string, $min, $max, $repeatable);
+
         #[derive(PartialEq, Eq, Debug)]
         pub struct $name {
             pub value: String
         }
 
-        impl Option for $name {
+        impl $crate::message::option::Option for $name {
             const NUMBER: u16 = $num;
             type Format = String;
 
@@ -198,23 +323,27 @@ macro_rules! option {
                 $name{value: value}
             }
 
-            fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+            fn from_bytes(bytes: &[u8]) -> Result<Self, $crate::message::Error> {
                 if bytes.len() >= $min as usize && bytes.len() <= $max as usize {
-                    Ok($name{value: str::from_utf8(bytes).or(Err(Error::MessageFormat))?.to_string()})
+                    Ok($name{value: ::std::str::from_utf8(bytes).or(Err($crate::message::Error::InvalidOption($num)))?.to_string()})
                 } else {
-                    Err(Error::MessageFormat)
+                    Err($crate::message::Error::InvalidOption($num))
                 }
             }
 
+            fn into_value(self) -> Self::Format {
+                self.value
+            }
+
         }
 
-        impl Byteable for $name {
+        impl $crate::message::option::Byteable for $name {
             fn number(&self) -> u16 {
                 $num
             }
 
-            fn to_bytes(&self) -> Cow<[u8]> {
-                Cow::Owned(self.value.clone().into_bytes())
+            fn to_bytes(&self) -> ::std::borrow::Cow<[u8]> {
+                ::std::borrow::Cow::Owned(self.value.clone().into_bytes())
             }
 
             fn bytes_len(&self) -> usize {
@@ -232,11 +361,13 @@ macro_rules! option {
     };
 
     // Empty Type Options
-    ($num: expr, $name: ident, empty, $min: expr, $max: expr) => {
+    ($num: expr, $name: ident, empty, $min: expr, $max: expr, $repeatable: expr) => {
+        $crate::coap_option!(@info $num, $name, empty, $min, $max, $repeatable);
+
         #[derive(PartialEq, Eq, Debug)]
         pub struct $name;
 
-        impl Option for $name {
+        impl $crate::message::option::Option for $name {
             const NUMBER: u16 = $num;
             type Format = ();
 
@@ -244,23 +375,26 @@ macro_rules! option {
                 $name
             }
 
-            fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
-                if bytes.len() != 0 {
+            fn from_bytes(bytes: &[u8]) -> Result<Self, $crate::message::Error> {
+                if bytes.is_empty() {
                     Ok($name)
                 } else {
-                    Err(Error::MessageFormat)
+                    Err($crate::message::Error::InvalidOption($num))
                 }
             }
 
+            fn into_value(self) -> Self::Format {
+            }
+
         }
 
-        impl Byteable for $name {
+        impl $crate::message::option::Byteable for $name {
             fn number(&self) -> u16 {
                 $num
             }
 
-            fn to_bytes(&self) -> Cow<[u8]> {
-                Cow::Borrowed(&[])
+            fn to_bytes(&self) -> ::std::borrow::Cow<[u8]> {
+                ::std::borrow::Cow::Borrowed(&[])
             }
 
             fn bytes_len(&self) -> usize {
@@ -272,13 +406,15 @@ macro_rules! option {
     };
 
     // UInt Type Options
-    ($num: expr, $name: ident, uint, $min: expr, $max: expr) => {
+    ($num: expr, $name: ident, uint, $min: expr, $max: expr, $repeatable: expr) => {
+        $crate::coap_option!(@info $num, $name, uint, $min, $max, $repeatable);
+
         #[derive(PartialEq, Eq, Debug)]
         pub struct $name {
             value: u64
         }
 
-        impl Option for $name {
+        impl $crate::message::option::Option for $name {
             const NUMBER: u16 = $num;
             type Format = u64;
 
@@ -286,24 +422,28 @@ macro_rules! option {
                 $name{value: value}
             }
 
-            fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+            fn from_bytes(bytes: &[u8]) -> Result<Self, $crate::message::Error> {
                 if bytes.len() >= $min as usize && bytes.len() <= $max as usize {
-                    Ok($name{value: bytes_to_value(bytes)})
+                    Ok($name{value: $crate::message::option::bytes_to_value(bytes)})
                 } else {
-                    Err(Error::MessageFormat)
+                    Err($crate::message::Error::InvalidOption($num))
                 }
             }
 
+            fn into_value(self) -> Self::Format {
+                self.value
+            }
+
         }
 
-        impl Byteable for $name {
+        impl $crate::message::option::Byteable for $name {
             fn number(&self) -> u16 {
                 $num
             }
 
-            fn to_bytes(&self) -> Cow<[u8]> {
+            fn to_bytes(&self) -> ::std::borrow::Cow<[u8]> {
 
-                Cow::Owned(value_to_bytes(self.value))
+                ::std::borrow::Cow::Owned($crate::message::option::value_to_bytes(self.value))
             }
 
             fn bytes_len(&self) -> usize {
@@ -322,7 +462,7 @@ macro_rules! option {
         impl<'a> From<&'a [u8]> for $name {
             fn from(bytes: &'a [u8]) -> Self {
                 Self {
-                    value: bytes_to_value(bytes)
+                    value: $crate::message::option::bytes_to_value(bytes)
                 }
             }
         }
@@ -332,7 +472,8 @@ macro_rules! option {
 // Helpers
 
 // TODO: Replace with something like byte order?
-fn bytes_to_value(bytes: &[u8]) -> u64 {
+#[doc(hidden)]
+pub fn bytes_to_value(bytes: &[u8]) -> u64 {
     let mut value = 0u64;
 
     for byte in bytes {
@@ -342,7 +483,9 @@ fn bytes_to_value(bytes: &[u8]) -> u64 {
     value
 }
 
-fn value_to_bytes(mut n: u64) -> Vec<u8> {
+#[doc(hidden)]
+#[cfg(feature = "std")]
+pub fn value_to_bytes(mut n: u64) -> Vec<u8> {
     let mut bytes = vec![];
     while n != 0 {
         bytes.push(n as u8);
@@ -354,32 +497,224 @@ fn value_to_bytes(mut n: u64) -> Vec<u8> {
 }
 
 
+/// RFC 7252: 5.4.1.  Critical/Elective
+pub fn is_critical(number: u16) -> bool {
+    number & 0x01 != 0
+}
+
+/// RFC 7252: 5.4.2.  Proxy Unsafe or Safe-to-Forward
+pub fn is_unsafe(number: u16) -> bool {
+    number & 0x02 != 0
+}
+
+/// RFC 7252: 5.4.6.  Option Numbers
+///
+/// Safe-to-forward options that are not part of the cache key.
+pub fn is_no_cache_key(number: u16) -> bool {
+    number & 0x1e == 0x1c
+}
+
+/// RFC 7252: 3.2.  Option Value Formats
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueFormat {
+    Empty,
+    Opaque,
+    Uint,
+    String,
+}
+
+/// What is known about an option number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OptionInfo {
+    pub number: u16,
+    pub name: &'static str,
+    pub format: ValueFormat,
+    /// the shortest valid value, in bytes
+    pub min_len: usize,
+    /// the longest valid value, in bytes
+    pub max_len: usize,
+    /// whether the option may occur more than once
+    pub repeatable: bool,
+}
+
+impl OptionInfo {
+    pub fn is_critical(&self) -> bool {
+        is_critical(self.number)
+    }
+
+    pub fn is_unsafe(&self) -> bool {
+        is_unsafe(self.number)
+    }
+
+    pub fn is_no_cache_key(&self) -> bool {
+        is_no_cache_key(self.number)
+    }
+}
+
+impl OptionInfo {
+    /// Check the length and format of a single value.
+    pub fn validate(&self, value: &[u8]) -> Result<(), Error> {
+        let well_formed = value.len() >= self.min_len && value.len() <= self.max_len && match self.format {
+            ValueFormat::Empty => value.is_empty(),
+            ValueFormat::Opaque => true,
+            ValueFormat::Uint => value.len() <= 8,
+            ValueFormat::String => str::from_utf8(value).is_ok(),
+        };
+
+        if well_formed {
+            Ok(())
+        } else {
+            Err(Error::InvalidOption(self.number))
+        }
+    }
+
+    /// Write a value in a human readable way, e.g. `"temp"`, `60` or `0x0a`.
+    pub fn fmt_value(&self, value: &[u8], f: &mut fmt::Formatter) -> fmt::Result {
+        match self.format {
+            ValueFormat::Empty if value.is_empty() => Ok(()),
+            ValueFormat::Uint if value.len() <= 8 => write!(f, "{}", bytes_to_value(value)),
+            ValueFormat::String => match str::from_utf8(value) {
+                Ok(string) => write!(f, "{:?}", string),
+                Err(_) => fmt_opaque(value, f),
+            },
+            _ => fmt_opaque(value, f),
+        }
+    }
+}
+
+fn fmt_opaque(value: &[u8], f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("0x")?;
+    for byte in value {
+        write!(f, "{:02x}", byte)?;
+    }
+    Ok(())
+}
+
+/// Options registered at runtime, in addition to `KNOWN_OPTIONS`.
+#[cfg(feature = "std")]
+static REGISTERED_OPTIONS: RwLock<Vec<OptionInfo>> = RwLock::new(Vec::new());
+
+/// Make an option known, e.g. a vendor specific one, so that its values are
+/// validated and printed like those of the options built into this library.
+///
+/// Registering the same option twice is fine, registering a different
+/// option under a number that is already known is not.
+#[cfg(feature = "std")]
+pub fn register(info: OptionInfo) -> Result<(), Error> {
+    let mut registered = REGISTERED_OPTIONS.write().unwrap();
+
+    let existing = KNOWN_OPTIONS.iter().chain(registered.iter()).find(|known| known.number == info.number);
+    match existing {
+        Some(known) if *known == info => Ok(()),
+        Some(_) => Err(Error::OptionAlreadyKnown(info.number)),
+        None => {
+            registered.push(info);
+            Ok(())
+        }
+    }
+}
+
+/// Forget a registered option again, so that tests leave the registry as
+/// they found it.
+#[cfg(all(test, feature = "std"))]
+pub(crate) fn unregister(number: u16) {
+    REGISTERED_OPTIONS.write().unwrap().retain(|info| info.number != number);
+}
+
+/// The registry entry of a known option number.
+pub fn info(number: u16) -> StdOption<OptionInfo> {
+    let known = KNOWN_OPTIONS.iter().find(|info| info.number == number).cloned();
+
+    #[cfg(feature = "std")]
+    let known = known.or_else(|| {
+        REGISTERED_OPTIONS.read().unwrap().iter().find(|info| info.number == number).cloned()
+    });
+
+    known
+}
+
+/// Check the length and format of a value, values of unknown options are
+/// always valid.
+pub fn validate(number: u16, value: &[u8]) -> Result<(), Error> {
+    match info(number) {
+        Some(info) => info.validate(value),
+        None => Ok(()),
+    }
+}
+
+/// Check the values of an option: an unknown option, a malformed value or a
+/// repeated value of a non-repeatable option make the option unrecognized.
+///
+/// RFC 7252: 5.4.5.  Repeatable Options
+pub fn check<V: AsRef<[u8]>>(number: u16, values: &[V]) -> Result<(), Error> {
+    let info = match info(number) {
+        Some(info) => info,
+        None => return Err(Error::UnrecognizedOption(number)),
+    };
+
+    if values.len() > 1 && !info.repeatable {
+        return Err(Error::InvalidOption(number));
+    }
+
+    values.iter().try_for_each(|value| info.validate(value.as_ref()))
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! value_format {
+    (empty) => { $crate::message::option::ValueFormat::Empty };
+    (opaque) => { $crate::message::option::ValueFormat::Opaque };
+    (uint) => { $crate::message::option::ValueFormat::Uint };
+    (string) => { $crate::message::option::ValueFormat::String };
+}
+
 /// This builds the type for each individual option.
 macro_rules! options {
-    ( $( ($num: expr, $name: ident, $format: ident, $min: expr, $max: expr), )+ ) => {
+    ( $( ($num: expr, $name: ident, $format: ident, $min: expr, $max: expr, $repeatable: expr), )+ ) => {
         $(
-            option!($num, $name, $format, $min, $max);
+            #[cfg(feature = "std")]
+            coap_option!($num, $name, $format, $min, $max, $repeatable);
         )+
+
+        /// Every option built into this library, by number.
+        pub const KNOWN_OPTIONS: &[OptionInfo] = &[
+            $(
+                OptionInfo {
+                    number: $num,
+                    name: stringify!($name),
+                    format: value_format!($format),
+                    min_len: $min,
+                    max_len: $max,
+                    repeatable: $repeatable,
+                },
+            )+
+        ];
     }
 }
 
+// (number, name, format, min length, max length, repeatable)
 options![
-    (1, IfMatch, opaque, 0, 8),
-    (3, UriHost, string, 1, 8),
-    (4, ETag, opaque, 0, 8),
-    (5, IfNoneMatch, empty, -1, -1), // TODO: fix macro to not need this
-    (6, Observe, uint, 0, 4),
-    (7, UriPort, uint, 0, 2),
-    (8, LocationPath, string, 0, 255),
-    (11, UriPath, string, 0, 255),
-    (12, ContentFormat, uint, 0, 2),
-    (14, MaxAge, uint, 0, 4),
-    (15, UriQuery, string, 0, 255),
-    (17, Accept, uint, 0, 2),
-    (20, LocationQuery, string, 0, 255),
-    (35, ProxyUri, string, 1, 1034),
-    (29, ProxyScheme, string, 1, 255),
-    (60, Size1, uint, 0, 4),
-    (284, NoResponse, uint, 0, 1),
+    (1, IfMatch, opaque, 0, 8, true),
+    (3, UriHost, string, 1, 255, false),
+    (4, ETag, opaque, 1, 8, true),
+    (5, IfNoneMatch, empty, 0, 0, false),
+    (6, Observe, uint, 0, 3, false),
+    (7, UriPort, uint, 0, 2, false),
+    (8, LocationPath, string, 0, 255, true),
+    (9, Oscore, opaque, 0, 255, false),
+    (11, UriPath, string, 0, 255, true),
+    (12, ContentFormat, uint, 0, 2, false),
+    (14, MaxAge, uint, 0, 4, false),
+    (15, UriQuery, string, 0, 255, true),
+    (17, Accept, uint, 0, 2, false),
+    (20, LocationQuery, string, 0, 255, true),
+    (23, Block2, uint, 0, 3, false),
+    (27, Block1, uint, 0, 3, false),
+    (28, Size2, uint, 0, 4, false),
+    (35, ProxyUri, string, 1, 1034, false),
+    (39, ProxyScheme, string, 1, 255, false),
+    (60, Size1, uint, 0, 4, false),
+    (252, Echo, opaque, 1, 40, false),
+    (258, NoResponse, uint, 0, 1, false),
+    (292, RequestTag, opaque, 0, 8, true),
 ];
-
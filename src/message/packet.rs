@@ -0,0 +1,187 @@
+//! A message that needs neither `std` nor an allocator.
+
+use super::{option, Code, Error, MessageRef, Mtype};
+
+use arrayvec::ArrayVec;
+
+/// How many options a `Packet` can hold.
+pub const MAX_OPTIONS: usize = 16;
+
+/// A message with fixed-capacity option storage, borrowing its token, option
+/// values and payload.
+///
+/// This is the message for targets without an allocator: decode it from a
+/// receive buffer, or build one and encode it into a send buffer.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Packet<'a> {
+    pub version: u8,
+    pub mtype: Mtype,
+    pub code: Code,
+    pub mid: u16,
+    pub token: &'a [u8],
+    /// kept in order of their number
+    options: ArrayVec<[(u16, &'a [u8]); MAX_OPTIONS]>,
+    pub payload: &'a [u8],
+}
+
+impl<'a> Packet<'a> {
+    pub fn new() -> Packet<'a> {
+        Packet {
+            version: 1,
+            mtype: Mtype::Confirmable,
+            code: Code::Get,
+            mid: 0,
+            token: &[],
+            options: ArrayVec::new(),
+            payload: &[],
+        }
+    }
+
+    /// RFC 7252: 3.  Message Format
+    pub fn decode(buf: &'a [u8]) -> Result<Packet<'a>, Error> {
+        Packet::from_ref(&MessageRef::new(buf)?)
+    }
+
+    pub fn from_ref(msg: &MessageRef<'a>) -> Result<Packet<'a>, Error> {
+        let mut options = ArrayVec::new();
+
+        for option in msg.options() {
+            options.try_push(option).map_err(|_| Error::TooManyOptions)?;
+        }
+
+        Ok(Packet {
+            version: msg.version(),
+            mtype: msg.mtype(),
+            code: msg.code(),
+            mid: msg.mid(),
+            token: msg.token(),
+            options,
+            payload: msg.payload(),
+        })
+    }
+
+    /// Add an option after any others with the same number.
+    pub fn push_option(&mut self, number: u16, value: &'a [u8]) -> Result<(), Error> {
+        let index = self.options.iter().take_while(|&&(n, _)| n <= number).count();
+
+        self.options.try_insert(index, (number, value)).map_err(|_| Error::TooManyOptions)
+    }
+
+    /// The options in order of their number, as `(number, value)`.
+    pub fn options(&self) -> &[(u16, &'a [u8])] {
+        &self.options
+    }
+
+    /// The first value of an option, if present.
+    pub fn option(&self, number: u16) -> Option<&'a [u8]> {
+        self.options.iter().find(|&&(n, _)| n == number).map(|&(_, value)| value)
+    }
+
+    /// The exact number of bytes `encode` writes.
+    pub fn encoded_len(&self) -> Result<usize, Error> {
+        let mut len = 4 + self.token.len();
+        let mut last_option_number = 0;
+
+        for &(number, value) in &self.options {
+            len += option::Header::new(number, value.len(), last_option_number)?.as_bytes().len() + value.len();
+            last_option_number = number;
+        }
+
+        if !self.payload.is_empty() {
+            len += 1 + self.payload.len();
+        }
+
+        Ok(len)
+    }
+
+    /// Write the packet to the start of `buf`, returning the number of bytes
+    /// written.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.token.len() > 8 {
+            return Err(Error::InvalidToken);
+        }
+
+        let len = self.encoded_len()?;
+        if buf.len() < len {
+            return Err(Error::MessageFormat);
+        }
+
+        buf[0] = (self.version << 6) | self.mtype.as_u8() << 4 | self.token.len() as u8;
+        buf[1] = self.code.as_u8();
+        buf[2] = (self.mid >> 8) as u8;
+        buf[3] = self.mid as u8;
+
+        let mut i = 4;
+        i = put(buf, i, self.token);
+
+        let mut last_option_number = 0;
+        for &(number, value) in &self.options {
+            i = put(buf, i, option::Header::new(number, value.len(), last_option_number)?.as_bytes());
+            i = put(buf, i, value);
+            last_option_number = number;
+        }
+
+        if !self.payload.is_empty() {
+            i = put(buf, i, &[0xFF]);
+            i = put(buf, i, self.payload);
+        }
+
+        Ok(i)
+    }
+}
+
+impl<'a> Default for Packet<'a> {
+    fn default() -> Packet<'a> {
+        Packet::new()
+    }
+}
+
+fn put(buf: &mut [u8], i: usize, bytes: &[u8]) -> usize {
+    buf[i..i + bytes.len()].copy_from_slice(bytes);
+    i + bytes.len()
+}
+
+#[test]
+fn test_packet_round_trip() {
+    // CON POST with token 0x99, Uri-Path "1a", "temp" and Uri-Query "a", payload
+    let bytes = [0x41, 0x02, 0x00, 0x37, 0x99, 0xb2, 0x31, 0x61, 0x04, 0x74, 0x65, 0x6d, 0x70,
+                 0x41, 0x61, 0xFF, 0x39, 0x39];
+    let packet = Packet::decode(&bytes).unwrap();
+
+    assert_eq!(packet.code, Code::Post);
+    assert_eq!(packet.token, &[0x99]);
+    assert_eq!(packet.options(), &[(11, &b"1a"[..]), (11, &b"temp"[..]), (15, &b"a"[..])]);
+    assert_eq!(packet.option(15), Some(&b"a"[..]));
+    assert_eq!(packet.payload, b"99");
+
+    let mut buf = [0; 32];
+    assert_eq!(packet.encoded_len(), Ok(bytes.len()));
+    assert_eq!(packet.encode(&mut buf), Ok(bytes.len()));
+    assert_eq!(&buf[..bytes.len()], &bytes[..]);
+    assert_eq!(packet.encode(&mut buf[..4]), Err(Error::MessageFormat));
+
+    let mut built = Packet::new();
+    built.mtype = Mtype::Confirmable;
+    built.code = Code::Post;
+    built.mid = 0x37;
+    built.token = &[0x99];
+    built.push_option(15, b"a").unwrap();
+    built.push_option(11, b"1a").unwrap();
+    built.push_option(11, b"temp").unwrap();
+    built.payload = b"99";
+    assert_eq!(built, packet);
+}
+
+#[test]
+fn test_packet_too_many_options() {
+    let mut packet = Packet::new();
+    for _ in 0..MAX_OPTIONS {
+        packet.push_option(11, b"a").unwrap();
+    }
+    assert_eq!(packet.push_option(11, b"a"), Err(Error::TooManyOptions));
+
+    let mut buf = [0; 64];
+    let len = packet.encode(&mut buf).unwrap();
+    buf[len..len + 2].copy_from_slice(&[0x01, 0x61]);
+    assert_eq!(Packet::decode(&buf[..len + 2]), Err(Error::TooManyOptions));
+}
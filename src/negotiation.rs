@@ -0,0 +1,195 @@
+//! Content negotiation with the Accept and Content-Format options (RFC 7252:
+//! 5.10.4 and 5.10.3).
+//!
+//! A resource offering several representations registers a handler for each
+//! Content-Format. The representation is picked by the Accept option of the
+//! request, while a request payload goes to the handler for its own
+//! Content-Format.
+
+use futures::prelude::*;
+use futures::future;
+
+use client::IoFuture;
+use message::{Code, ContentFormat, Message};
+use server::{Handler, Request};
+
+/// A `Handler` choosing between representations of one resource.
+///
+/// Without an Accept option the first representation is served. A request
+/// accepting none of them is answered with 4.06 Not Acceptable.
+///
+/// A request with a payload is handled by the input handler for its
+/// Content-Format, or else by the representation in that format. A payload
+/// in a format neither accepts is answered with 4.15 Unsupported
+/// Content-Format.
+#[derive(Default)]
+pub struct Negotiate {
+    representations: Vec<(ContentFormat, Box<dyn Handler>)>,
+    inputs: Vec<(ContentFormat, Box<dyn Handler>)>,
+}
+
+impl Negotiate {
+    pub fn new() -> Negotiate {
+        Negotiate::default()
+    }
+
+    /// Serve the representation in `format` with `handler`.
+    pub fn with_representation<H: Handler>(mut self, format: ContentFormat, handler: H) -> Self {
+        self.representations.push((format, Box::new(handler)));
+        self
+    }
+
+    /// Handle request payloads in `format` with `handler`, which answers in
+    /// whatever format it likes, the Accept option is left to it.
+    pub fn with_input<H: Handler>(mut self, format: ContentFormat, handler: H) -> Self {
+        self.inputs.push((format, Box::new(handler)));
+        self
+    }
+
+    fn representation(&self, format: ContentFormat) -> Option<&(ContentFormat, Box<dyn Handler>)> {
+        self.representations.iter().find(|&&(offered, _)| offered == format)
+    }
+
+    /// The handler for `request` and the format of the representation it
+    /// serves, or the code to answer with if there is none.
+    fn select(&self, request: &Message) -> Result<(&dyn Handler, Option<ContentFormat>), Code> {
+        let (content_format, accept) = match (request.content_format(), request.accept()) {
+            (Ok(content_format), Ok(accept)) => (content_format, accept),
+            _ => return Err(Code::BadRequest),
+        };
+
+        match content_format {
+            Some(format) if !request.payload.is_empty() => {
+                if let Some((_, handler)) = self.inputs.iter().find(|&&(input, _)| input == format) {
+                    return Ok((&**handler, None));
+                }
+
+                let &(format, ref handler) = self.representation(format).ok_or(Code::UnsupportedContentFormat)?;
+                match accept {
+                    Some(accept) if accept != format => Err(Code::NotAcceptable),
+                    _ => Ok((&**handler, Some(format))),
+                }
+            }
+            _ => {
+                let &(format, ref handler) = match accept {
+                    Some(accept) => self.representation(accept),
+                    None => self.representations.first(),
+                }.ok_or(Code::NotAcceptable)?;
+
+                Ok((&**handler, Some(format)))
+            }
+        }
+    }
+}
+
+impl Handler for Negotiate {
+    fn handle(&self, request: Request) -> IoFuture<Message> {
+        let (handler, format) = match self.select(&request.message) {
+            Ok(selected) => selected,
+            Err(code) => {
+                debug!("no representation for request from {}: {}", request.source, code);
+                return Box::new(future::ok(Message::new().with_code(code)));
+            }
+        };
+
+        let response = handler.handle(request).map(move |mut response| {
+            if let Some(format) = format {
+                if response.code.is_success() && response.content_format() == Ok(None) {
+                    response.set_content_format(format);
+                }
+            }
+            response
+        });
+
+        Box::new(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resource() -> Negotiate {
+        Negotiate::new()
+            .with_representation(ContentFormat::Json, |_: Request| {
+                Ok(Message::new().with_code(Code::Content).with_payload(b"{}".to_vec()))
+            })
+            .with_representation(ContentFormat::Cbor, |_: Request| {
+                Ok(Message::new().with_code(Code::Content).with_payload(vec![0xA0]))
+            })
+            .with_input(ContentFormat::LinkFormat, |request: Request| {
+                Ok(Message::new().with_code(Code::Changed).with_payload(request.message.payload))
+            })
+    }
+
+    fn handle(message: Message) -> Message {
+        let request = Request { message, source: "192.0.2.1:5683".parse().unwrap() };
+
+        resource().handle(request).wait().unwrap()
+    }
+
+    #[test]
+    fn picks_accepted_representation() {
+        let response = handle(Message::new().with_accept(ContentFormat::Cbor));
+        assert_eq!(response.content_format(), Ok(Some(ContentFormat::Cbor)));
+        assert_eq!(response.payload, vec![0xA0]);
+
+        let response = handle(Message::new());
+        assert_eq!(response.content_format(), Ok(Some(ContentFormat::Json)));
+        assert_eq!(response.payload, b"{}");
+    }
+
+    #[test]
+    fn not_acceptable() {
+        let request = Message::new().with_accept(ContentFormat::Xml);
+        assert!(!request.accepts(ContentFormat::Json).unwrap());
+
+        assert_eq!(handle(request).code, Code::NotAcceptable);
+    }
+
+    #[test]
+    fn unsupported_content_format() {
+        let request = Message::new()
+            .with_code(Code::Put)
+            .with_content_format(ContentFormat::TextPlain)
+            .with_payload(b"hi".to_vec());
+        assert_eq!(handle(request).code, Code::UnsupportedContentFormat);
+
+        let request = Message::new()
+            .with_code(Code::Put)
+            .with_accept(ContentFormat::Json)
+            .with_content_format(ContentFormat::TextPlain)
+            .with_payload(b"hi".to_vec());
+        assert_eq!(handle(request).code, Code::UnsupportedContentFormat);
+    }
+
+    #[test]
+    fn payload_routed_by_content_format() {
+        // not the first representation, without an Accept option
+        let request = Message::new()
+            .with_code(Code::Put)
+            .with_content_format(ContentFormat::Cbor)
+            .with_payload(vec![0xA0]);
+        let response = handle(request);
+        assert_eq!(response.code, Code::Content);
+        assert_eq!(response.content_format(), Ok(Some(ContentFormat::Cbor)));
+        assert_eq!(response.payload, vec![0xA0]);
+
+        let request = Message::new()
+            .with_code(Code::Put)
+            .with_accept(ContentFormat::Json)
+            .with_content_format(ContentFormat::Cbor)
+            .with_payload(vec![0xA0]);
+        assert_eq!(handle(request).code, Code::NotAcceptable);
+
+        let request = Message::new()
+            .with_code(Code::Post)
+            .with_accept(ContentFormat::Json)
+            .with_content_format(ContentFormat::LinkFormat)
+            .with_payload(b"</a>".to_vec());
+        let response = handle(request);
+        assert_eq!(response.code, Code::Changed);
+        assert_eq!(response.content_format(), Ok(None));
+        assert_eq!(response.payload, b"</a>");
+    }
+}
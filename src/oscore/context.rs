@@ -0,0 +1,260 @@
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use super::{cbor_bstr, Error, ALG_AES_CCM_16_64_128, KEY_LEN, NONCE_LEN};
+
+/// The largest sequence number that fits in a 5 byte Partial IV.
+const MAX_SEQUENCE_NUMBER: u64 = (1 << 40) - 1;
+
+/// How many sequence numbers below the highest one seen are tracked.
+const REPLAY_WINDOW_SIZE: u64 = 32;
+
+/// RFC 8613: 3.  The Security Context
+///
+/// Holds the keys derived for one pair of endpoints along with the state
+/// that changes as messages are exchanged: the sender sequence number and the
+/// recipient replay window.
+pub struct SecurityContext {
+    id_context: Option<Vec<u8>>,
+    common_iv: [u8; NONCE_LEN],
+
+    sender_id: Vec<u8>,
+    sender_key: [u8; KEY_LEN],
+    sender_sequence_number: u64,
+
+    recipient_id: Vec<u8>,
+    recipient_key: [u8; KEY_LEN],
+    replay_window: ReplayWindow,
+
+    /// the Echo value sent to recover the replay window, if any
+    pending_echo: Option<Vec<u8>>,
+}
+
+impl SecurityContext {
+    /// RFC 8613: 3.2.  Establishment of Security Context Parameters
+    ///
+    /// Derives the sender key, recipient key and common IV using
+    /// HKDF-SHA-256 and AES-CCM-16-64-128. Sender and recipient IDs may be at
+    /// most 7 bytes long.
+    pub fn new(
+        master_secret: &[u8],
+        master_salt: &[u8],
+        id_context: Option<&[u8]>,
+        sender_id: &[u8],
+        recipient_id: &[u8],
+    ) -> Result<SecurityContext, Error> {
+        if sender_id.len() > NONCE_LEN - 6 || recipient_id.len() > NONCE_LEN - 6 {
+            return Err(Error::InvalidId);
+        }
+
+        let hkdf = Hkdf::<Sha256>::new(Some(master_salt), master_secret);
+
+        let mut sender_key = [0u8; KEY_LEN];
+        let mut recipient_key = [0u8; KEY_LEN];
+        let mut common_iv = [0u8; NONCE_LEN];
+
+        hkdf.expand(&info(sender_id, id_context, "Key", KEY_LEN), &mut sender_key)
+            .map_err(|_| Error::KeyDerivation)?;
+        hkdf.expand(&info(recipient_id, id_context, "Key", KEY_LEN), &mut recipient_key)
+            .map_err(|_| Error::KeyDerivation)?;
+        hkdf.expand(&info(&[], id_context, "IV", NONCE_LEN), &mut common_iv)
+            .map_err(|_| Error::KeyDerivation)?;
+
+        Ok(SecurityContext {
+            id_context: id_context.map(|c| c.to_vec()),
+            common_iv,
+            sender_id: sender_id.to_vec(),
+            sender_key,
+            sender_sequence_number: 0,
+            recipient_id: recipient_id.to_vec(),
+            recipient_key,
+            replay_window: ReplayWindow::new(),
+            pending_echo: None,
+        })
+    }
+
+    /// Continue sending from a previously stored sequence number, e.g. after
+    /// a reboot.
+    pub fn with_sender_sequence_number(mut self, sequence_number: u64) -> Self {
+        self.sender_sequence_number = sequence_number;
+        self
+    }
+
+    /// RFC 8613: B.1.2.  Replay Window
+    ///
+    /// Mark the replay window as lost, e.g. after a reboot. Until a client
+    /// proves the freshness of a request by returning an Echo challenge, any
+    /// request is answered with 4.01 Unauthorized and a new challenge.
+    pub fn with_lost_replay_window(mut self) -> Self {
+        self.replay_window = ReplayWindow::lost();
+        self
+    }
+
+    pub fn id_context(&self) -> Option<&[u8]> {
+        self.id_context.as_deref()
+    }
+
+    pub fn common_iv(&self) -> &[u8] {
+        &self.common_iv
+    }
+
+    pub fn sender_id(&self) -> &[u8] {
+        &self.sender_id
+    }
+
+    pub fn sender_key(&self) -> &[u8] {
+        &self.sender_key
+    }
+
+    /// The sequence number the next protected message will use.
+    pub fn sender_sequence_number(&self) -> u64 {
+        self.sender_sequence_number
+    }
+
+    pub fn recipient_id(&self) -> &[u8] {
+        &self.recipient_id
+    }
+
+    pub fn recipient_key(&self) -> &[u8] {
+        &self.recipient_key
+    }
+
+    /// Take the next sender sequence number.
+    pub(crate) fn next_sequence_number(&mut self) -> Result<u64, Error> {
+        let sequence_number = self.sender_sequence_number;
+
+        if sequence_number > MAX_SEQUENCE_NUMBER {
+            return Err(Error::SequenceNumberExhausted);
+        }
+
+        self.sender_sequence_number += 1;
+
+        Ok(sequence_number)
+    }
+
+    pub(crate) fn replay_window(&mut self) -> &mut ReplayWindow {
+        &mut self.replay_window
+    }
+
+    pub(crate) fn pending_echo(&self) -> Option<&[u8]> {
+        self.pending_echo.as_deref()
+    }
+
+    pub(crate) fn set_pending_echo(&mut self, echo: Option<Vec<u8>>) {
+        self.pending_echo = echo;
+    }
+
+    /// RFC 8613: 5.2.  AEAD Nonce
+    pub(crate) fn nonce(&self, id_piv: &[u8], piv: &[u8]) -> [u8; NONCE_LEN] {
+        let mut nonce = [0u8; NONCE_LEN];
+
+        nonce[0] = id_piv.len() as u8;
+        nonce[NONCE_LEN - 5 - id_piv.len()..NONCE_LEN - 5].copy_from_slice(id_piv);
+        nonce[NONCE_LEN - piv.len()..].copy_from_slice(piv);
+
+        for (n, iv) in nonce.iter_mut().zip(self.common_iv.iter()) {
+            *n ^= *iv;
+        }
+
+        nonce
+    }
+}
+
+/// RFC 8613: 3.2.1.  Derivation of Sender Key, Recipient Key, and Common IV
+///
+/// info = [ id, id_context, alg_aead, type, L ]
+fn info(id: &[u8], id_context: Option<&[u8]>, kind: &str, len: usize) -> Vec<u8> {
+    let mut info = vec![0x85];
+
+    info.extend(cbor_bstr(id));
+    match id_context {
+        Some(context) => info.extend(cbor_bstr(context)),
+        None => info.push(0xF6),
+    }
+    info.push(ALG_AES_CCM_16_64_128);
+    info.push(0x60 | kind.len() as u8);
+    info.extend(kind.as_bytes());
+    info.push(len as u8);
+
+    info
+}
+
+/// RFC 8613: 7.4.  Replay Protection
+///
+/// A sliding window over the most recently received sequence numbers.
+pub(crate) struct ReplayWindow {
+    highest: Option<u64>,
+    /// bit `n` is set if `highest - n - 1` has been received
+    seen: u32,
+    valid: bool,
+}
+
+impl ReplayWindow {
+    fn new() -> ReplayWindow {
+        ReplayWindow {
+            highest: None,
+            seen: 0,
+            valid: true,
+        }
+    }
+
+    fn lost() -> ReplayWindow {
+        ReplayWindow {
+            valid: false,
+            ..ReplayWindow::new()
+        }
+    }
+
+    /// Whether the window can be trusted to detect replays.
+    pub fn is_valid(&self) -> bool {
+        self.valid
+    }
+
+    /// Whether a message with this sequence number has not been seen before.
+    pub fn is_fresh(&self, sequence_number: u64) -> bool {
+        let highest = match self.highest {
+            Some(highest) => highest,
+            None => return true,
+        };
+
+        if sequence_number > highest {
+            true
+        } else if sequence_number == highest || highest - sequence_number > REPLAY_WINDOW_SIZE {
+            false
+        } else {
+            self.seen & (1 << (highest - sequence_number - 1)) == 0
+        }
+    }
+
+    /// Record a sequence number as received.
+    pub fn accept(&mut self, sequence_number: u64) {
+        let highest = match self.highest {
+            Some(highest) => highest,
+            None => {
+                self.highest = Some(sequence_number);
+                return;
+            }
+        };
+
+        if sequence_number > highest {
+            let shift = sequence_number - highest;
+            self.seen = if shift > REPLAY_WINDOW_SIZE {
+                0
+            } else {
+                // the old highest becomes bit `shift - 1`
+                ((u64::from(self.seen) << shift) | (1 << (shift - 1))) as u32
+            };
+            self.highest = Some(sequence_number);
+        } else if sequence_number < highest {
+            self.seen |= 1 << (highest - sequence_number - 1);
+        }
+    }
+
+    /// RFC 8613: B.1.2.  Replay Window
+    ///
+    /// Start over with a known fresh sequence number.
+    pub fn recover(&mut self, sequence_number: u64) {
+        *self = ReplayWindow::new();
+        self.accept(sequence_number);
+    }
+}
@@ -0,0 +1,842 @@
+//! Object Security for Constrained RESTful Environments (OSCORE, RFC 8613).
+//!
+//! OSCORE protects requests and responses end-to-end, even through proxies
+//! that can read and rewrite the CoAP message around them. The code, the
+//! payload and every option a proxy has no need to see are encrypted into the
+//! payload of an outer message, which only carries the options needed to
+//! route it along with an OSCORE option identifying the security context.
+//!
+//! Only the mandatory to implement algorithms are supported: AES-CCM-16-64-128
+//! for encryption and HKDF-SHA-256 for key derivation.
+//!
+//! On the client side a shared `SecurityContext` is given to
+//! `Client::with_oscore`, on the server side a handler is wrapped in a
+//! `Protected` handler.
+
+mod context;
+
+pub use self::context::SecurityContext;
+
+use std::borrow::Cow;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use aes::Aes128;
+use ccm::{AeadInPlace, KeyInit, Ccm};
+use ccm::consts::{U13, U8};
+
+use futures::prelude::*;
+use futures::future;
+
+use percent_encoding::percent_decode;
+use url::Url;
+
+use client::{self, IoFuture, Transport};
+use error::Error as CrateError;
+use message::{self, Code, Message};
+use message::option::{Echo, Options, Oscore, ProxyScheme, ProxyUri, UriHost, UriPath, UriPort, UriQuery};
+use message::option::Option as CoapOption;
+use server::{Handler, Request};
+use util::random_u64;
+
+/// The COSE algorithm identifier of AES-CCM-16-64-128.
+const ALG_AES_CCM_16_64_128: u8 = 10;
+
+const KEY_LEN: usize = 16;
+const NONCE_LEN: usize = 13;
+const TAG_LEN: usize = 8;
+
+/// The length of the Echo values used for replay window recovery.
+const ECHO_LEN: usize = 8;
+
+type Aead = Ccm<Aes128, U8, U13>;
+
+/// Errors in protecting or verifying a message.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// HKDF could not produce the requested key material
+    KeyDerivation,
+    /// A sender or recipient ID is too long for the AEAD nonce
+    InvalidId,
+    /// Every sender sequence number has been used, a new context is needed
+    SequenceNumberExhausted,
+    /// The message carries no OSCORE option
+    NotProtected,
+    /// An error response to a protected request came back unprotected, as
+    /// errors of the OSCORE layer itself do. Nothing about it is
+    /// authenticated, it may as well come from an attacker.
+    UnprotectedError(Code),
+    /// The OSCORE option could not be parsed
+    MalformedOption,
+    /// The message was protected with a security context we don't have
+    UnknownContext,
+    /// The message has been received before
+    Replay,
+    /// The message failed to decrypt or authenticate
+    Decryption,
+    /// The encrypted content was not a valid CoAP message
+    Message(message::Error),
+}
+
+impl From<message::Error> for Error {
+    fn from(e: message::Error) -> Error {
+        Error::Message(e)
+    }
+}
+
+/// What a response needs to know about the request it answers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestInfo {
+    kid: Vec<u8>,
+    piv: Vec<u8>,
+    nonce: [u8; NONCE_LEN],
+}
+
+impl RequestInfo {
+    /// The sequence number of the request, from its Partial IV.
+    pub fn sequence_number(&self) -> u64 {
+        self.piv.iter().fold(0, |n, b| (n << 8) | u64::from(*b))
+    }
+}
+
+/// RFC 8613: 6.1.  OSCORE Option Value
+#[derive(Debug, Default, PartialEq)]
+struct OptionValue {
+    piv: Option<Vec<u8>>,
+    kid_context: Option<Vec<u8>>,
+    kid: Option<Vec<u8>>,
+}
+
+impl OptionValue {
+    fn from_bytes(bytes: &[u8]) -> Result<OptionValue, Error> {
+        let mut value = OptionValue::default();
+
+        let flags = match bytes.first() {
+            Some(flags) => *flags,
+            None => return Ok(value),
+        };
+
+        // reserved bits and Partial IV lengths
+        if flags & 0xE0 != 0 || flags & 0x07 > 5 {
+            return Err(Error::MalformedOption);
+        }
+
+        let mut rest = &bytes[1..];
+
+        let piv_len = (flags & 0x07) as usize;
+        if piv_len > 0 {
+            if rest.len() < piv_len {
+                return Err(Error::MalformedOption);
+            }
+            value.piv = Some(rest[..piv_len].to_vec());
+            rest = &rest[piv_len..];
+        }
+
+        if flags & 0x10 != 0 {
+            let len = *rest.first().ok_or(Error::MalformedOption)? as usize;
+            if rest.len() < 1 + len {
+                return Err(Error::MalformedOption);
+            }
+            value.kid_context = Some(rest[1..1 + len].to_vec());
+            rest = &rest[1 + len..];
+        }
+
+        if flags & 0x08 != 0 {
+            value.kid = Some(rest.to_vec());
+        } else if !rest.is_empty() {
+            return Err(Error::MalformedOption);
+        }
+
+        Ok(value)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut flags = 0u8;
+        let mut bytes = vec![0];
+
+        if let Some(ref piv) = self.piv {
+            flags |= piv.len() as u8;
+            bytes.extend(piv);
+        }
+
+        if let Some(ref kid_context) = self.kid_context {
+            flags |= 0x10;
+            bytes.push(kid_context.len() as u8);
+            bytes.extend(kid_context);
+        }
+
+        if let Some(ref kid) = self.kid {
+            flags |= 0x08;
+            bytes.extend(kid);
+        }
+
+        if flags == 0 {
+            return vec![];
+        }
+
+        bytes[0] = flags;
+        bytes
+    }
+}
+
+/// RFC 8613: 8.1.  Protecting the Request
+pub fn protect_request(context: &mut SecurityContext, request: &Message)
+    -> Result<(Message, RequestInfo), Error>
+{
+    let piv = partial_iv(context.next_sequence_number()?);
+    let kid = context.sender_id().to_vec();
+    let nonce = context.nonce(&kid, &piv);
+
+    let option = OptionValue {
+        piv: Some(piv.clone()),
+        kid_context: context.id_context().map(|c| c.to_vec()),
+        kid: Some(kid.clone()),
+    };
+
+    // RFC 8613: 4.2.  The Outer Code
+    let outer_code = if request.options.map.contains_key(&OBSERVE) {
+        Code::Fetch
+    } else {
+        Code::Post
+    };
+
+    let request = decompose_proxy_uri(request)?;
+    let protected = protect(&request, context.sender_key(), &nonce, &aad(&kid, &piv), &option, outer_code);
+
+    Ok((protected, RequestInfo { kid, piv, nonce }))
+}
+
+/// RFC 8613: 8.2.  Verifying the Request
+///
+/// On success the request is returned as it was before protection, along
+/// with what is needed to protect the response. If the replay window is
+/// valid the request is checked against it, otherwise that's left to the
+/// caller, see `SecurityContext::with_lost_replay_window`.
+pub fn unprotect_request(context: &mut SecurityContext, request: &Message)
+    -> Result<(Message, RequestInfo), Error>
+{
+    let option = oscore_option(request)?;
+
+    let (piv, kid) = match (option.piv, option.kid) {
+        (Some(piv), Some(kid)) => (piv, kid),
+        _ => return Err(Error::MalformedOption),
+    };
+
+    if kid != context.recipient_id() {
+        return Err(Error::UnknownContext);
+    }
+
+    if let Some(ref kid_context) = option.kid_context {
+        if Some(kid_context.as_slice()) != context.id_context() {
+            return Err(Error::UnknownContext);
+        }
+    }
+
+    let nonce = context.nonce(&kid, &piv);
+    let info = RequestInfo { kid, piv, nonce };
+    let sequence_number = info.sequence_number();
+
+    let window_valid = context.replay_window().is_valid();
+    if window_valid && !context.replay_window().is_fresh(sequence_number) {
+        return Err(Error::Replay);
+    }
+
+    let unprotected = unprotect(request, context.recipient_key(), &nonce, &aad(&info.kid, &info.piv))?;
+
+    if window_valid {
+        context.replay_window().accept(sequence_number);
+    }
+
+    Ok((unprotected, info))
+}
+
+/// RFC 8613: 8.3.  Protecting the Response
+///
+/// With `fresh_partial_iv` the response uses a nonce from the sender's own
+/// sequence number instead of reusing the one from the request, as is
+/// required for notifications and replay window recovery.
+pub fn protect_response(
+    context: &mut SecurityContext,
+    response: &Message,
+    request: &RequestInfo,
+    fresh_partial_iv: bool,
+) -> Result<Message, Error> {
+    let mut option = OptionValue::default();
+
+    let nonce = if fresh_partial_iv {
+        let piv = partial_iv(context.next_sequence_number()?);
+        let nonce = context.nonce(context.sender_id(), &piv);
+        option.piv = Some(piv);
+        nonce
+    } else {
+        request.nonce
+    };
+
+    let outer_code = if response.options.map.contains_key(&OBSERVE) {
+        Code::Content
+    } else {
+        Code::Changed
+    };
+
+    Ok(protect(response, context.sender_key(), &nonce, &aad(&request.kid, &request.piv), &option, outer_code))
+}
+
+/// RFC 8613: 8.4.  Verifying the Response
+pub fn unprotect_response(context: &mut SecurityContext, response: &Message, request: &RequestInfo)
+    -> Result<Message, Error>
+{
+    let option = oscore_option(response)?;
+
+    let nonce = match option.piv {
+        Some(ref piv) => context.nonce(context.recipient_id(), piv),
+        None => request.nonce,
+    };
+
+    unprotect(response, context.recipient_key(), &nonce, &aad(&request.kid, &request.piv))
+}
+
+/// Option numbers that are never encrypted (class U).
+///
+/// Uri-Host, Uri-Port, OSCORE, Proxy-Uri and Proxy-Scheme
+const CLASS_U: [u16; 5] = [3, 7, 9, 35, 39];
+
+/// Observe is both an inner and outer option.
+const OBSERVE: u16 = 6;
+
+/// RFC 8613: 4.1.3.3.  Proxy-Uri
+///
+/// A Proxy-Uri would reveal the path and query to every proxy, so it is
+/// taken apart into Proxy-Scheme, Uri-Host and Uri-Port, which are sent as
+/// they are, and Uri-Path and Uri-Query, which are encrypted.
+fn decompose_proxy_uri(request: &Message) -> Result<Cow<'_, Message>, Error> {
+    let proxy_uri = match request.options.get_first::<ProxyUri>() {
+        Ok(Some(proxy_uri)) => proxy_uri.into_value(),
+        Ok(None) => return Ok(Cow::Borrowed(request)),
+        Err(_) => return Err(Error::MalformedOption),
+    };
+
+    let url = Url::parse(&proxy_uri).map_err(|_| Error::MalformedOption)?;
+    let host = url.host_str().ok_or(Error::MalformedOption)?;
+    let depercent = |s: &str| {
+        percent_decode(s.as_bytes()).decode_utf8().map(|s| s.into_owned()).map_err(|_| Error::MalformedOption)
+    };
+
+    let mut decomposed = request.clone();
+    decomposed.options.remove::<ProxyUri>();
+    decomposed.options.push(ProxyScheme::new(url.scheme().to_string()));
+    decomposed.options.push(UriHost::new(host.to_string()));
+    if let Some(port) = url.port() {
+        decomposed.options.push(UriPort::new(u64::from(port)));
+    }
+
+    // RFC 7252: 6.4.  Decomposing URIs into Options
+    if url.path() != "" && url.path() != "/" {
+        for segment in url.path_segments().ok_or(Error::MalformedOption)? {
+            decomposed.options.push(UriPath::new(depercent(segment)?));
+        }
+    }
+    if let Some(query) = url.query().filter(|query| !query.is_empty()) {
+        for argument in query.split('&') {
+            decomposed.options.push(UriQuery::new(depercent(argument)?));
+        }
+    }
+
+    Ok(Cow::Owned(decomposed))
+}
+
+fn protect(
+    msg: &Message,
+    key: &[u8],
+    nonce: &[u8; NONCE_LEN],
+    aad: &[u8],
+    option: &OptionValue,
+    outer_code: Code,
+) -> Message {
+    let mut inner = Options::new();
+    let mut outer = Options::new();
+
+    for (number, value) in msg.options.iter() {
+        if number == OBSERVE || CLASS_U.contains(&number) {
+            outer.push_raw(number, value.to_vec());
+        }
+        if !CLASS_U.contains(&number) {
+            inner.push_raw(number, value.to_vec());
+        }
+    }
+
+    outer.push_raw(Oscore::NUMBER, option.to_bytes());
+
+    let mut ciphertext = encode_inner(msg.code, inner, &msg.payload);
+    let tag = Aead::new_from_slice(key)
+        .expect("key has the right length")
+        .encrypt_in_place_detached(nonce.into(), aad, &mut ciphertext)
+        .expect("plaintext fits in a single CCM message");
+    ciphertext.extend(tag);
+
+    Message {
+        version: msg.version,
+        mtype: msg.mtype,
+        code: outer_code,
+        mid: msg.mid,
+        token: msg.token.clone(),
+        options: outer,
+        payload: ciphertext,
+    }
+}
+
+fn unprotect(msg: &Message, key: &[u8], nonce: &[u8; NONCE_LEN], aad: &[u8]) -> Result<Message, Error> {
+    if msg.payload.len() < TAG_LEN {
+        return Err(Error::Decryption);
+    }
+
+    let (ciphertext, tag) = msg.payload.split_at(msg.payload.len() - TAG_LEN);
+    let mut plaintext = ciphertext.to_vec();
+
+    Aead::new_from_slice(key)
+        .expect("key has the right length")
+        .decrypt_in_place_detached(nonce.into(), aad, &mut plaintext, tag.into())
+        .map_err(|_| Error::Decryption)?;
+
+    let inner = decode_inner(&plaintext)?;
+
+    // inner options take precedence, the outer ones left are class U
+    let mut options = inner.options;
+    for (number, value) in msg.options.iter() {
+        if CLASS_U.contains(&number) && number != Oscore::NUMBER {
+            options.push_raw(number, value.to_vec());
+        }
+    }
+
+    Ok(Message {
+        version: msg.version,
+        mtype: msg.mtype,
+        code: inner.code,
+        mid: msg.mid,
+        token: msg.token.clone(),
+        options,
+        payload: inner.payload,
+    })
+}
+
+fn oscore_option(msg: &Message) -> Result<OptionValue, Error> {
+    match msg.options.map.get(&Oscore::NUMBER).map(|v| v.as_slice()) {
+        Some([value]) => OptionValue::from_bytes(value),
+        Some(_) => Err(Error::MalformedOption),
+        None => Err(Error::NotProtected),
+    }
+}
+
+/// RFC 8613: 5.1.  Plaintext
+///
+/// The code, then the inner options and payload encoded as in a message.
+fn encode_inner(code: Code, options: Options, payload: &[u8]) -> Vec<u8> {
+    let mut plain = Message::new().with_code(code).with_payload(payload.to_vec());
+    plain.options = options;
+
+    // an empty token leaves a four byte header, only the code is kept
+    let bytes = plain.to_bytes().expect("inner message is always encodable");
+    let mut plaintext = vec![bytes[1]];
+    plaintext.extend(&bytes[4..]);
+    plaintext
+}
+
+fn decode_inner(plaintext: &[u8]) -> Result<Message, Error> {
+    let code = *plaintext.first().ok_or(Error::Message(message::Error::MessageFormat))?;
+
+    let mut bytes = vec![0x40, code, 0, 0];
+    bytes.extend(&plaintext[1..]);
+
+    Ok(Message::from_bytes(&bytes)?)
+}
+
+/// RFC 8613: 5.4.  Additional Authenticated Data
+///
+/// Enc_structure = [ "Encrypt0", h'', external_aad ]
+/// external_aad = bstr .cbor [ 1, [ alg_aead ], request_kid, request_piv, h'' ]
+fn aad(request_kid: &[u8], request_piv: &[u8]) -> Vec<u8> {
+    let mut external_aad = vec![0x85, 0x01, 0x81, ALG_AES_CCM_16_64_128];
+    external_aad.extend(cbor_bstr(request_kid));
+    external_aad.extend(cbor_bstr(request_piv));
+    external_aad.push(0x40);
+
+    let mut aad = vec![0x83];
+    aad.push(0x68);
+    aad.extend(b"Encrypt0");
+    aad.push(0x40);
+    aad.extend(cbor_bstr(&external_aad));
+    aad
+}
+
+/// Encode a byte string in CBOR.
+fn cbor_bstr(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = match bytes.len() {
+        len @ 0..=23 => vec![0x40 | len as u8],
+        len @ 24..=255 => vec![0x58, len as u8],
+        len => vec![0x59, (len >> 8) as u8, len as u8],
+    };
+    encoded.extend(bytes);
+    encoded
+}
+
+/// RFC 8613: 6.1.  The Partial IV is the sequence number in as few bytes as
+/// possible, but at least one.
+fn partial_iv(sequence_number: u64) -> Vec<u8> {
+    let bytes = sequence_number.to_be_bytes();
+    let skip = bytes.iter().take(7).take_while(|b| **b == 0).count();
+    bytes[skip..].to_vec()
+}
+
+/// Send a request protected with OSCORE and verify the response.
+///
+/// A server that has lost its replay window challenges the request with an
+/// Echo option inside the protected response, the client repeats the request
+/// with it like any other freshness challenge.
+pub(crate) fn exchange<T: Transport>(
+    transport: T,
+    request: Message,
+    remote_addr: SocketAddr,
+    context: Arc<Mutex<SecurityContext>>,
+) -> IoFuture<(Message, T)> {
+    let (protected, info) = match protect_request(&mut context.lock().unwrap(), &request) {
+        Ok(protected) => protected,
+        Err(e) => return Box::new(future::err(e.into())),
+    };
+
+    Box::new(client::exchange(transport, protected, remote_addr).and_then(move |(response, transport)| {
+        // RFC 8613: 8.4.  Verifying the Response
+        //
+        // only errors from the OSCORE layer itself come back unprotected
+        if !response.options.map.contains_key(&Oscore::NUMBER) {
+            return Err(match response.code.is_error() {
+                true => Error::UnprotectedError(response.code),
+                false => Error::NotProtected,
+            }.into());
+        }
+
+        let response = unprotect_response(&mut context.lock().unwrap(), &response, &info)?;
+        Ok((response, transport))
+    }))
+}
+
+/// A `Handler` only accepting requests protected with OSCORE, and protecting
+/// its responses in turn.
+///
+/// Requests that can't be verified are answered without protection, as
+/// described in RFC 8613 section 8.2.
+pub struct Protected<H> {
+    context: Arc<Mutex<SecurityContext>>,
+    handler: Arc<H>,
+}
+
+impl<H: Handler> Protected<H> {
+    pub fn new(context: Arc<Mutex<SecurityContext>>, handler: H) -> Protected<H> {
+        Protected {
+            context,
+            handler: Arc::new(handler),
+        }
+    }
+}
+
+impl<H: Handler> Handler for Protected<H> {
+    fn handle(&self, request: Request) -> IoFuture<Message> {
+        let Request { message, source } = request;
+        let mut context = self.context.lock().unwrap();
+
+        let (inner, info) = match unprotect_request(&mut context, &message) {
+            Ok(unprotected) => unprotected,
+            Err(e) => {
+                warn!("rejecting request that failed verification: {:?}", e);
+                return Box::new(future::ok(verification_failure(&e)));
+            }
+        };
+
+        if !context.replay_window().is_valid() {
+            let echo = inner.options.map.get(&Echo::NUMBER).and_then(|v| v.first());
+
+            if echo.is_some() && echo.map(|e| e.as_slice()) == context.pending_echo() {
+                debug!("replay window recovered");
+                context.set_pending_echo(None);
+                context.replay_window().recover(info.sequence_number());
+            } else {
+                return Box::new(future::result(challenge(&mut context, &info)));
+            }
+        }
+
+        drop(context);
+
+        let context = self.context.clone();
+        let response = self.handler
+            .handle(Request { message: inner, source })
+            .and_then(move |response| {
+                Ok(protect_response(&mut context.lock().unwrap(), &response, &info, false)?)
+            });
+
+        Box::new(response)
+    }
+}
+
+/// RFC 8613: B.1.2.  Replay Window
+///
+/// Answer with a 4.01 Unauthorized carrying a new Echo value, protected with
+/// a fresh Partial IV since the request may well be a replay.
+fn challenge(context: &mut SecurityContext, info: &RequestInfo) -> Result<Message, CrateError> {
+    let mut echo = Vec::with_capacity(ECHO_LEN);
+    echo.extend(&random_u64().to_be_bytes()[..ECHO_LEN]);
+    context.set_pending_echo(Some(echo.clone()));
+
+    let mut response = Message::new().with_code(Code::Unauthorized);
+    response.options.push_raw(Echo::NUMBER, echo);
+
+    Ok(protect_response(context, &response, info, true)?)
+}
+
+/// RFC 8613: 8.2.  Verifying the Request, the error responses
+fn verification_failure(error: &Error) -> Message {
+    let (code, diagnostic) = match *error {
+        Error::NotProtected => (Code::Unauthorized, "OSCORE required"),
+        Error::UnknownContext => (Code::Unauthorized, "Security context not found"),
+        Error::Replay => (Code::Unauthorized, "Replay detected"),
+        Error::Decryption => (Code::BadRequest, "Decryption failed"),
+        _ => (Code::BadOption, "Bad OSCORE option"),
+    };
+
+    Message::new()
+        .with_code(code)
+        .with_payload(diagnostic.as_bytes().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use client::Client;
+    use endpoint::Endpoint;
+    use server::Server;
+    use sim::{Config, Network, Simulation};
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    // RFC 8613: C.1.1.  Client
+    fn client_context() -> SecurityContext {
+        SecurityContext::new(&hex("0102030405060708090a0b0c0d0e0f10"), &hex("9e7ca92223786340"), None, &[], &[0x01])
+            .unwrap()
+    }
+
+    // RFC 8613: C.1.2.  Server
+    fn server_context() -> SecurityContext {
+        SecurityContext::new(&hex("0102030405060708090a0b0c0d0e0f10"), &hex("9e7ca92223786340"), None, &[0x01], &[])
+            .unwrap()
+    }
+
+    #[test]
+    fn derive_context() {
+        let context = client_context();
+
+        assert_eq!(context.sender_key(), hex("f0910ed7295e6ad4b54fc793154302ff").as_slice());
+        assert_eq!(context.recipient_key(), hex("ffb14e093c94c9cac9471648b4f98710").as_slice());
+        assert_eq!(context.common_iv(), hex("4622d4dd6d944168eefb54987c").as_slice());
+    }
+
+    #[test]
+    fn reject_long_ids() {
+        assert_eq!(SecurityContext::new(&[1; 16], &[], None, &[0; 8], &[]).err(), Some(Error::InvalidId));
+    }
+
+    // RFC 8613: C.4.  Test Vector 4: OSCORE Request, Client
+    #[test]
+    fn protect_request_test_vector() {
+        let request = Message::from_bytes(&hex("44015d1f00003974396c6f63616c686f737483747631")).unwrap();
+        let mut context = client_context().with_sender_sequence_number(20);
+
+        let (protected, _) = protect_request(&mut context, &request).unwrap();
+
+        assert_eq!(protected.to_bytes().unwrap(),
+                   hex("44025d1f00003974396c6f63616c686f7374620914ff612f1092f1776f1c1668b3825e"));
+        assert_eq!(context.sender_sequence_number(), 21);
+    }
+
+    // RFC 8613: C.7.  Test Vector 7: OSCORE Response, Server
+    #[test]
+    fn protect_response_test_vector() {
+        let protected = Message::from_bytes(
+            &hex("44025d1f00003974396c6f63616c686f7374620914ff612f1092f1776f1c1668b3825e")).unwrap();
+        let mut context = server_context();
+
+        let (request, info) = unprotect_request(&mut context, &protected).unwrap();
+        assert_eq!(request, Message::from_bytes(&hex("44015d1f00003974396c6f63616c686f737483747631")).unwrap());
+        assert_eq!(info.sequence_number(), 20);
+
+        let response = Message::from_bytes(&hex("64455d1f00003974ff48656c6c6f20576f726c6421")).unwrap();
+        let protected = protect_response(&mut context, &response, &info, false).unwrap();
+
+        assert_eq!(protected.to_bytes().unwrap(),
+                   hex("64445d1f0000397490ffdbaad1e9a7e7b2a813d3c31524378303cdafae119106"));
+
+        let mut client = client_context().with_sender_sequence_number(20);
+        let (_, info) = protect_request(&mut client, &request).unwrap();
+        assert_eq!(unprotect_response(&mut client, &protected, &info).unwrap(), response);
+    }
+
+    #[test]
+    fn proxy_uri_decomposed() {
+        let request = Message::new()
+            .with_code(Code::Get)
+            .with_option(ProxyUri::new("coap://sensor.example:5684/private%20room/temp?unit=C".to_string()));
+        let (protected, _) = protect_request(&mut client_context(), &request).unwrap();
+
+        let outer: Vec<u16> = protected.options.iter().map(|(number, _)| number).collect();
+        assert_eq!(outer, vec![UriHost::NUMBER, UriPort::NUMBER, Oscore::NUMBER, ProxyScheme::NUMBER]);
+        for (_, value) in protected.options.iter() {
+            assert!(!value.windows(4).any(|w| w == b"room" || w == b"temp" || w == b"unit"));
+        }
+
+        let (request, _) = unprotect_request(&mut server_context(), &protected).unwrap();
+        assert_eq!(request.options.get_first::<ProxyScheme>(), Ok(Some(ProxyScheme::new("coap".to_string()))));
+        assert_eq!(request.options.get_first::<UriHost>(), Ok(Some(UriHost::new("sensor.example".to_string()))));
+        assert_eq!(request.options.get_first::<UriPort>(), Ok(Some(UriPort::new(5684))));
+        assert_eq!(request.options.get_raw::<UriPath>(), Some(vec![b"private room".to_vec(), b"temp".to_vec()]));
+        assert_eq!(request.options.get_first::<UriQuery>(), Ok(Some(UriQuery::new("unit=C".to_string()))));
+    }
+
+    #[test]
+    fn detect_replay() {
+        let request = Message::new().with_code(Code::Get).with_payload(b"on".to_vec());
+        let (protected, _) = protect_request(&mut client_context(), &request).unwrap();
+        let mut context = server_context();
+
+        assert!(unprotect_request(&mut context, &protected).is_ok());
+        assert_eq!(unprotect_request(&mut context, &protected).err(), Some(Error::Replay));
+    }
+
+    #[test]
+    fn detect_tampering() {
+        let request = Message::new().with_code(Code::Put).with_payload(b"on".to_vec());
+        let (mut protected, _) = protect_request(&mut client_context(), &request).unwrap();
+        protected.payload[0] ^= 0x01;
+
+        assert_eq!(unprotect_request(&mut server_context(), &protected).err(), Some(Error::Decryption));
+    }
+
+    #[test]
+    fn option_value_round_trip() {
+        let value = OptionValue {
+            piv: Some(vec![0x05]),
+            kid_context: Some(vec![0x37, 0xcb, 0xf3, 0x21, 0x00, 0x17, 0xa2, 0xd3]),
+            kid: Some(vec![]),
+        };
+
+        // RFC 8613: C.6.  Test Vector 6: OSCORE Request, Client, with ID Context
+        assert_eq!(value.to_bytes(), hex("19050837cbf3210017a2d3"));
+        assert_eq!(OptionValue::from_bytes(&value.to_bytes()), Ok(value));
+        assert_eq!(OptionValue::from_bytes(&[]), Ok(OptionValue::default()));
+        assert_eq!(OptionValue::from_bytes(&[0x06]), Err(Error::MalformedOption));
+    }
+
+    #[test]
+    fn replay_window_slides() {
+        let mut context = server_context();
+        let window = context.replay_window();
+
+        window.accept(5);
+        window.accept(3);
+        assert!(!window.is_fresh(5));
+        assert!(!window.is_fresh(3));
+        assert!(window.is_fresh(4));
+        assert!(window.is_fresh(6));
+
+        window.accept(40);
+        assert!(!window.is_fresh(5));
+        assert!(window.is_fresh(39));
+        assert!(window.is_fresh(8));
+        assert!(!window.is_fresh(7));
+    }
+
+    fn serve(context: SecurityContext, calls: Arc<AtomicUsize>) -> (Simulation, Endpoint, ::sim::Socket) {
+        let net = Network::new(Config::new());
+        let server_socket = net.bind_any().unwrap();
+        let endpoint = Endpoint::Resolved(server_socket.local_addr());
+        let client_socket = net.bind_any().unwrap();
+
+        let handler = Protected::new(Arc::new(Mutex::new(context)), move |req: Request| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Ok(req.message.new_reply()
+               .with_code(Code::Content)
+               .with_payload(b"secret".to_vec()))
+        });
+
+        let mut sim = Simulation::new(net);
+        sim.spawn(Server::new(handler).serve(server_socket).map_err(|_| ()));
+
+        (sim, endpoint, client_socket)
+    }
+
+    #[test]
+    fn client_and_server() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let (mut sim, endpoint, socket) = serve(server_context(), calls.clone());
+
+        let client = Client::new().with_endpoint(endpoint).with_oscore(Arc::new(Mutex::new(client_context())));
+        let response = sim.run(client.send_over(socket)).unwrap();
+
+        assert_eq!(response.code, Code::Content);
+        assert_eq!(response.payload, b"secret");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn unprotected_request_rejected() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let (mut sim, endpoint, socket) = serve(server_context(), calls.clone());
+
+        let response = sim.run(Client::new().with_endpoint(endpoint).send_over(socket)).unwrap();
+
+        assert_eq!(response.code, Code::Unauthorized);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    /// Send a protected request to a server answering with `code`, without
+    /// protection, as an attacker would.
+    fn forged(code: Code) -> Result<Message, CrateError> {
+        let net = Network::new(Config::new());
+        let server_socket = net.bind_any().unwrap();
+        let endpoint = Endpoint::Resolved(server_socket.local_addr());
+        let client_socket = net.bind_any().unwrap();
+
+        let handler = move |req: Request| Ok(req.message.new_reply().with_code(code).with_payload(b"forged".to_vec()));
+        let mut sim = Simulation::new(net);
+        sim.spawn(Server::new(handler).serve(server_socket).map_err(|_| ()));
+
+        let client = Client::new().with_endpoint(endpoint).with_oscore(Arc::new(Mutex::new(client_context())));
+        sim.run(client.send_over(client_socket))
+    }
+
+    #[test]
+    fn unprotected_response_rejected() {
+        match forged(Code::Content) {
+            Err(CrateError::Oscore(Error::NotProtected)) => {}
+            other => panic!("expected the forged response to be rejected, got {:?}", other),
+        }
+
+        match forged(Code::Unauthorized) {
+            Err(CrateError::Oscore(Error::UnprotectedError(Code::Unauthorized))) => {}
+            other => panic!("expected an unauthenticated error, got {:?}", other),
+        }
+    }
+
+    // RFC 8613: B.1.2.  Replay Window
+    #[test]
+    fn replay_window_recovery_with_echo() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let (mut sim, endpoint, socket) = serve(server_context().with_lost_replay_window(), calls.clone());
+
+        let client = Client::new().with_endpoint(endpoint).with_oscore(Arc::new(Mutex::new(client_context())));
+        let response = sim.run(client.send_over(socket)).unwrap();
+
+        assert_eq!(response.code, Code::Content);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+use std::option::Option as StdOption;
+use std::str;
+use std::sync::{Arc, Mutex};
+
+use futures::prelude::*;
+
+use sha2::{Digest, Sha256};
+use url::{Position, Url};
+
+use client::IoFuture;
+use http::{self, OCTET_STREAM};
+use message::{Code, Message};
+use message::option::ETag;
+
+use super::HttpSend;
+
+/// RFC 7252: 4.6.  Message Size
+///
+/// The largest response body relayed, as responses aren't transferred
+/// block-wise.
+const MAX_PAYLOAD_SIZE: usize = 1024;
+
+/// How many hashed entity-tags are remembered to translate them back.
+const HASHED_ETAGS: usize = 1024;
+
+/// RFC 7252: 10.1.  CoAP-HTTP Proxying
+///
+/// Perform `request` on the HTTP origin server at `url` with `send`, or
+/// give the code to answer with if it can't be.
+pub(super) fn forward(send: &HttpSend, etags: &Arc<Etags>, request: &Message, url: Url) -> Result<IoFuture<Message>, Code> {
+    let translated = translate_request(etags, request, &url)?;
+    let method = request.code;
+    let etags = etags.clone();
+
+    Ok(Box::new(send(&url, translated).map(move |response| translate_response(&etags, method, response))))
+}
+
+/// Translates HTTP entity-tags to ETag options and back.
+///
+/// A strong entity-tag whose opaque value fits in the option is carried as
+/// that value, any other by the first 8 bytes of its SHA-256 hash, which
+/// is remembered to translate it back.
+#[derive(Default)]
+pub(super) struct Etags {
+    hashed: Mutex<HashMap<Vec<u8>, String>>,
+}
+
+impl Etags {
+    fn to_coap(&self, etag: &str) -> Vec<u8> {
+        let etag = etag.trim();
+        let opaque = etag.strip_prefix('"').and_then(|etag| etag.strip_suffix('"'));
+        if let Some(opaque) = opaque.filter(|opaque| !opaque.is_empty() && opaque.len() <= 8) {
+            return opaque.as_bytes().to_vec();
+        }
+
+        let hash = Sha256::digest(etag.as_bytes())[..8].to_vec();
+
+        let mut hashed = self.hashed.lock().unwrap();
+        if hashed.len() >= HASHED_ETAGS && !hashed.contains_key(&hash) {
+            let evicted = hashed.keys().next().cloned();
+            if let Some(evicted) = evicted {
+                hashed.remove(&evicted);
+            }
+        }
+        hashed.insert(hash.clone(), etag.to_string());
+
+        hash
+    }
+
+    /// The entity-tag `etag` was translated from, or else `etag` as an
+    /// opaque value if it can be one, and in hexadecimal if not.
+    fn to_http(&self, etag: &[u8]) -> String {
+        if let Some(original) = self.hashed.lock().unwrap().get(etag) {
+            return original.clone();
+        }
+
+        // RFC 7232: 2.3.  ETag
+        match str::from_utf8(etag) {
+            Ok(opaque) if opaque.bytes().all(|b| b == 0x21 || (0x23..=0x7e).contains(&b)) => format!("\"{}\"", opaque),
+            _ => http::format_etag(etag),
+        }
+    }
+}
+
+fn translate_request(etags: &Etags, request: &Message, url: &Url) -> Result<http::Request, Code> {
+    // FETCH and iPATCH have no counterpart
+    let method = match request.code {
+        Code::Get => "GET",
+        Code::Post => "POST",
+        Code::Put => "PUT",
+        Code::Delete => "DELETE",
+        Code::Patch => "PATCH",
+        _ => return Err(Code::NotImplemented),
+    };
+
+    let mut translated = http::Request::new(method, &url[Position::BeforePath..Position::AfterQuery]);
+
+    if !request.payload.is_empty() {
+        let content_type = match request.content_format().map_err(|_| Code::BadOption)? {
+            Some(format) => format.media_type().unwrap_or(OCTET_STREAM),
+            None => OCTET_STREAM,
+        };
+        translated = translated
+            .with_header("Content-Type", content_type)
+            .with_body(request.payload.clone());
+    }
+
+    if let Some(format) = request.accept().map_err(|_| Code::BadOption)? {
+        translated = translated.with_header("Accept", format.media_type().ok_or(Code::NotAcceptable)?);
+    }
+
+    let if_match = request.if_match().map_err(|_| Code::BadOption)?;
+    if !if_match.is_empty() {
+        let if_match: Vec<String> = if_match.iter().map(|etag| match etag.len() {
+            0 => "*".to_string(),
+            _ => etags.to_http(etag),
+        }).collect();
+        translated = translated.with_header("If-Match", &if_match.join(", "));
+    }
+
+    // RFC 7252: 5.10.6.2.  ETag as a Request Option
+    let stored = request.options.get_raw::<ETag>().unwrap_or_default();
+    if request.if_none_match() {
+        translated = translated.with_header("If-None-Match", "*");
+    } else if request.code == Code::Get && !stored.is_empty() {
+        let stored: Vec<String> = stored.iter().map(|etag| etags.to_http(etag)).collect();
+        translated = translated.with_header("If-None-Match", &stored.join(", "));
+    }
+
+    Ok(translated)
+}
+
+fn translate_response(etags: &Etags, method: Code, response: http::Response) -> Message {
+    if response.body.len() > MAX_PAYLOAD_SIZE {
+        warn!("not relaying a response body of {} bytes", response.body.len());
+        return Message::new().with_code(Code::BadGateway);
+    }
+
+    let code = http::code(response.status, method);
+    let mut translated = Message::new().with_code(code);
+
+    if let Some(etag) = response.header("ETag") {
+        translated.set_etag(&etags.to_coap(etag)).ok();
+    }
+
+    if let Some(max_age) = response.header("Cache-Control").and_then(max_age) {
+        translated.set_max_age(max_age);
+    }
+
+    if code == Code::Valid || response.body.is_empty() {
+        return translated;
+    }
+
+    if let Some(format) = response.header("Content-Type").and_then(http::content_format) {
+        translated.set_content_format(format);
+    }
+
+    translated.with_payload(response.body)
+}
+
+/// The Max-Age a response with the Cache-Control header `cache_control`
+/// may be cached for, if it says.
+fn max_age(cache_control: &str) -> StdOption<u32> {
+    let mut max_age = None;
+
+    for directive in cache_control.split(',').map(str::trim) {
+        if directive.eq_ignore_ascii_case("no-store") || directive.eq_ignore_ascii_case("no-cache") {
+            return Some(0);
+        }
+        if let Some(seconds) = directive.strip_prefix("max-age=") {
+            max_age = seconds.parse::<u64>().ok().map(|seconds| seconds.min(u64::from(u32::MAX)) as u32);
+        }
+    }
+
+    max_age
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    use futures::future;
+
+    use tokio;
+    use tokio::net::TcpListener;
+    use tokio::runtime::Runtime;
+    use tokio_io::codec::Decoder;
+
+    use message::ContentFormat;
+    use message::option::{Option, ProxyScheme, ProxyUri, UriHost, UriPort};
+    use proxy::ForwardProxy;
+    use server::{Handler, Request};
+    use super::*;
+
+    fn request(message: Message) -> Request {
+        Request { message, source: "192.0.2.1:5683".parse().unwrap() }
+    }
+
+    fn proxy_uri(code: Code, uri: &str) -> Message {
+        Message::new().with_code(code).with_option(ProxyUri::new(uri.to_string()))
+    }
+
+    /// Answer the requests on each connection accepted on the loopback
+    /// interface with `handler`.
+    fn stand_in<H>(runtime: &mut Runtime, handler: H) -> SocketAddr
+        where H: Fn(http::Request) -> http::Response + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handler = Arc::new(handler);
+
+        let server = listener.incoming().map_err(|_| ()).for_each(move |stream| {
+            let handler = handler.clone();
+            let (responses, requests) = http::ServerCodec::new().framed(stream).split();
+            let connection = requests.map(move |request| handler(request)).forward(responses);
+            tokio::spawn(connection.map(|_| ()).map_err(|_| ()));
+            Ok(())
+        });
+        runtime.spawn(server);
+
+        addr
+    }
+
+    #[test]
+    fn over_loopback() {
+        let mut runtime = Runtime::new().unwrap();
+        let origin_addr = stand_in(&mut runtime, |request| match (request.method.as_str(), request.target.as_str()) {
+            ("GET", "/api/temp?unit=C") if request.header("Accept") == Some("application/json") => {
+                http::Response::new(200)
+                    .with_header("Content-Type", "application/json")
+                    .with_header("Cache-Control", "public, max-age=10")
+                    .with_header("ETag", "\"0102\"")
+                    .with_body(b"{\"t\":22.5}".to_vec())
+            }
+            ("PUT", "/api/mode") if request.header("Content-Type") == Some("text/plain; charset=utf-8") => {
+                http::Response::new(204)
+            }
+            _ => http::Response::new(400),
+        });
+        let proxy = ForwardProxy::new().with_http();
+
+        let get = proxy_uri(Code::Get, &format!("http://{}/api/temp?unit=C", origin_addr))
+            .with_accept(ContentFormat::Json);
+        let response = runtime.block_on(proxy.handle(request(get))).unwrap();
+
+        assert_eq!(response.code, Code::Content);
+        assert_eq!(response.content_format(), Ok(Some(ContentFormat::Json)));
+        assert_eq!(response.max_age(), Ok(10));
+        assert_eq!(response.etag(), Ok(Some(b"0102".to_vec())));
+        assert_eq!(response.payload, b"{\"t\":22.5}");
+
+        let mut put = Message::new()
+            .with_code(Code::Put)
+            .with_option(ProxyScheme::new("http".to_string()))
+            .with_option(UriHost::new(origin_addr.ip().to_string()))
+            .with_option(UriPort::new(u64::from(origin_addr.port())))
+            .with_content_format(ContentFormat::TextPlain)
+            .with_payload(b"eco".to_vec());
+        put.set_uri_path("/api/mode").unwrap();
+        let response = runtime.block_on(proxy.handle(request(put))).unwrap();
+
+        assert_eq!(response.code, Code::Changed);
+
+        runtime.shutdown_now().wait().unwrap();
+    }
+
+    #[test]
+    fn conditional_requests() {
+        let proxy = ForwardProxy::new().with_http_fn(|_: &Url, request: http::Request| -> IoFuture<http::Response> {
+            let status = match (request.header("If-Match"), request.header("If-None-Match")) {
+                (Some("\"01\", *"), None) => 412,
+                (None, Some("\"0a0b\"")) => 304,
+                _ => 400,
+            };
+            Box::new(future::ok(http::Response::new(status).with_header("ETag", "\"0a0b\"")))
+        });
+
+        let mut post = proxy_uri(Code::Post, "http://example.com/");
+        post.add_if_match(&[1]).unwrap();
+        post.add_if_match(&[]).unwrap();
+        assert_eq!(proxy.handle(request(post)).wait().unwrap().code, Code::PreconditionFailed);
+
+        let get = proxy_uri(Code::Get, "http://example.com/").with_option(ETag::new(vec![0x0a, 0x0b]));
+        let response = proxy.handle(request(get)).wait().unwrap();
+        assert_eq!(response.code, Code::Valid);
+        assert_eq!(response.etag(), Ok(Some(b"0a0b".to_vec())));
+    }
+
+    #[test]
+    fn http_etags() {
+        let etags = Etags::default();
+
+        assert_eq!(etags.to_coap("\"v1\""), b"v1");
+        assert_eq!(etags.to_http(b"v1"), "\"v1\"");
+
+        let long = "\"33a64df551425fcc55e4d42a148795d9f25f89d4\"";
+        let hashed = etags.to_coap(long);
+        assert_eq!(hashed.len(), 8);
+        assert_eq!(etags.to_http(&hashed), long);
+
+        let weak = etags.to_coap("W/\"v1\"");
+        assert_eq!(weak.len(), 8);
+        assert_eq!(etags.to_http(&weak), "W/\"v1\"");
+    }
+
+    #[test]
+    fn revalidate_hashed_etag() {
+        let long = "\"33a64df551425fcc55e4d42a148795d9f25f89d4\"";
+        let proxy = ForwardProxy::new().with_http_fn(move |_: &Url, request: http::Request| -> IoFuture<http::Response> {
+            let status = match request.header("If-None-Match") {
+                None => 200,
+                Some(etag) if etag == long => 304,
+                Some(_) => 400,
+            };
+            Box::new(future::ok(http::Response::new(status).with_header("ETag", long)))
+        });
+
+        let response = proxy.handle(request(proxy_uri(Code::Get, "http://example.com/"))).wait().unwrap();
+        assert_eq!(response.code, Code::Content);
+        let etag = response.etag().unwrap().unwrap();
+
+        let get = proxy_uri(Code::Get, "http://example.com/").with_option(ETag::new(etag.clone()));
+        let response = proxy.handle(request(get)).wait().unwrap();
+        assert_eq!(response.code, Code::Valid);
+        assert_eq!(response.etag(), Ok(Some(etag)));
+    }
+
+    #[test]
+    fn large_body() {
+        let proxy = ForwardProxy::new().with_http_fn(|request: &Url, _: http::Request| -> IoFuture<http::Response> {
+            let size = if request.path() == "/large" { MAX_PAYLOAD_SIZE + 1 } else { MAX_PAYLOAD_SIZE };
+            Box::new(future::ok(http::Response::new(200).with_body(vec![0; size])))
+        });
+
+        let response = proxy.handle(request(proxy_uri(Code::Get, "http://example.com/"))).wait().unwrap();
+        assert_eq!(response.code, Code::Content);
+        assert_eq!(response.payload.len(), MAX_PAYLOAD_SIZE);
+
+        let response = proxy.handle(request(proxy_uri(Code::Get, "http://example.com/large"))).wait().unwrap();
+        assert_eq!(response.code, Code::BadGateway);
+    }
+
+    #[test]
+    fn https_with_own_sender() {
+        let proxy = ForwardProxy::new().with_http_fn(|url: &Url, _: http::Request| -> IoFuture<http::Response> {
+            let status = if url.scheme() == "https" { 204 } else { 400 };
+            Box::new(future::ok(http::Response::new(status)))
+        });
+
+        let https = proxy_uri(Code::Post, "https://example.com/");
+        assert_eq!(proxy.handle(request(https)).wait().unwrap().code, Code::Changed);
+    }
+
+    #[test]
+    fn untranslatable_requests() {
+        let proxy = ForwardProxy::new().with_http();
+
+        let fetch = proxy_uri(Code::Fetch, "http://example.com/");
+        assert_eq!(proxy.handle(request(fetch)).wait().unwrap().code, Code::NotImplemented);
+
+        let https = proxy_uri(Code::Get, "https://example.com/");
+        assert_eq!(proxy.handle(request(https)).wait().unwrap().code, Code::ProxyingNotSupported);
+
+        assert_eq!(max_age("no-cache, max-age=10"), Some(0));
+        assert_eq!(max_age("private"), None);
+    }
+}
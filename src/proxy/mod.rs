@@ -0,0 +1,363 @@
+//! A CoAP-to-CoAP forward proxy (RFC 7252: 5.7).
+//!
+//! A client asks a forward proxy to perform a request on its behalf by
+//! giving the target either as a whole in Proxy-Uri, or as a Proxy-Scheme
+//! along with the usual Uri-Host, Uri-Port, Uri-Path and Uri-Query options.
+//! The proxy sends the request on from its own endpoint and relays the
+//! response, or answers with a 5.xx code of its own if it can't. Targets
+//! with an `http` scheme can be reached as well, by translating requests to
+//! HTTP and the responses back (RFC 7252: 10.1).
+//!
+//! A reverse proxy instead stands in for a set of backend servers, picking
+//! one by the path of each request.
+
+use std::option::Option as StdOption;
+use std::sync::{Arc, Mutex};
+
+use futures::prelude::*;
+use futures::future;
+
+use url::Url;
+
+use cache::Cache;
+use client::{Client, IoFuture};
+use endpoint::Endpoint;
+use error::{Error, UrlError};
+use http;
+use message::{Code, Message, Mtype};
+use message::option::{self, Option, ProxyScheme, ProxyUri, UriHost, UriPort};
+use server::{Handler, Request};
+use uri;
+
+mod cross;
+mod reverse;
+
+pub use self::reverse::ReverseProxy;
+
+/// The options describing the target of a request.
+const TARGET_OPTIONS: [u16; 6] = [3, 7, 11, 15, 35, 39];
+
+/// Something performing an HTTP request on the origin server of a URL.
+pub type HttpSend = Box<dyn Fn(&Url, http::Request) -> IoFuture<http::Response> + Send + Sync>;
+
+/// A `Handler` forwarding requests to the target given in their Proxy-Uri or
+/// Proxy-Scheme option.
+///
+/// Requests without either are answered with 4.04 Not Found. Targets with a
+/// scheme other than `coap`, or `http` once enabled by `with_http`, are
+/// answered with 5.05 Proxying Not Supported, requests that time out with
+/// 5.04 Gateway Timeout, and any other failure to get a response with 5.02
+/// Bad Gateway.
+pub struct ForwardProxy<F> {
+    send: F,
+    cache: StdOption<Arc<Mutex<Cache>>>,
+    http: StdOption<HttpSend>,
+    /// whether `http` can reach `https` targets
+    https: bool,
+    etags: Arc<cross::Etags>,
+}
+
+impl ForwardProxy<fn(Client) -> IoFuture<Message>> {
+    /// Forward requests over UDP, from a new socket for each.
+    pub fn new() -> Self {
+        ForwardProxy::from_fn(Client::send)
+    }
+}
+
+impl Default for ForwardProxy<fn(Client) -> IoFuture<Message>> {
+    fn default() -> Self {
+        ForwardProxy::new()
+    }
+}
+
+impl<F> ForwardProxy<F>
+    where F: Fn(Client) -> IoFuture<Message> + Send + Sync + 'static,
+{
+    /// Forward requests by sending the client made for each with `send`,
+    /// e.g. over a simulated socket.
+    pub fn from_fn(send: F) -> ForwardProxy<F> {
+        ForwardProxy {
+            send,
+            cache: None,
+            http: None,
+            https: false,
+            etags: Arc::new(cross::Etags::default()),
+        }
+    }
+
+    /// Serve fresh responses from `cache`, and store new ones in it.
+    pub fn with_cache(mut self, cache: Arc<Mutex<Cache>>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Also forward requests to `http` targets, over TCP with `http::send`.
+    ///
+    /// There is no support for TLS, `https` targets are still answered with
+    /// 5.05 Proxying Not Supported.
+    pub fn with_http(mut self) -> Self {
+        self.http = Some(Box::new(http::send));
+        self.https = false;
+        self
+    }
+
+    /// Also forward requests to `http` and `https` targets, by performing
+    /// the translated requests with `send`, which has to bring its own TLS.
+    pub fn with_http_fn<G>(mut self, send: G) -> Self
+        where G: Fn(&Url, http::Request) -> IoFuture<http::Response> + Send + Sync + 'static,
+    {
+        self.http = Some(Box::new(send));
+        self.https = true;
+        self
+    }
+
+    /// The client performing `request` on behalf of its sender.
+    fn forward(&self, request: &Message, url: &Url) -> Result<Client, Code> {
+        let (endpoint, options) = uri::decompose(url).map_err(|e| match e {
+            UrlError::UnsupportedScheme(_) => Code::ProxyingNotSupported,
+            _ => Code::BadRequest,
+        })?;
+
+        let mut msg = request.clone()
+            .with_mtype(Mtype::Confirmable)
+            .with_token(&[]);
+        msg.options.map.retain(|n, _| !TARGET_OPTIONS.contains(n));
+        msg.options.map.extend(options.map);
+
+        let client = Client::from_message(endpoint, msg);
+
+        Ok(match self.cache {
+            Some(ref cache) => client.with_cache(cache.clone()),
+            None => client,
+        })
+    }
+}
+
+impl<F> Handler for ForwardProxy<F>
+    where F: Fn(Client) -> IoFuture<Message> + Send + Sync + 'static,
+{
+    fn handle(&self, request: Request) -> IoFuture<Message> {
+        let response = check_options(&request.message)
+            .and_then(|()| target(&request.message))
+            .and_then(|url| url.ok_or(Code::NotFound))
+            .and_then(|url| match (url.scheme(), &self.http) {
+                ("http", Some(send)) => cross::forward(send, &self.etags, &request.message, url),
+                ("https", Some(send)) if self.https => cross::forward(send, &self.etags, &request.message, url),
+                _ => self.forward(&request.message, &url).map(|client| (self.send)(client)),
+            });
+
+        match response {
+            Ok(response) => Box::new(response.or_else(|e| Ok(failure(e)))),
+            Err(code) => {
+                debug!("not forwarding request from {}: {}", request.source, code);
+                Box::new(future::ok(Message::new().with_code(code)))
+            }
+        }
+    }
+
+    fn forwards_options(&self) -> bool {
+        true
+    }
+}
+
+/// RFC 7252: 5.7.1.  Proxy Operation
+///
+/// Unrecognized options are forwarded as they are if they are safe to
+/// forward, if not the request can't be forwarded at all and is answered
+/// with 5.02 Bad Gateway. Recognized critical options have to be well
+/// formed, as at any endpoint.
+pub(crate) fn check_options(request: &Message) -> Result<(), Code> {
+    for (&number, values) in &request.options.map {
+        match option::info(number) {
+            None if option::is_unsafe(number) => return Err(Code::BadGateway),
+            Some(_) if option::is_critical(number) && option::check(number, values).is_err() => {
+                return Err(Code::BadOption);
+            }
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+/// The response to relay when forwarding a request failed.
+fn failure(e: Error) -> Message {
+    warn!("forwarding request failed: {:?}", e);
+
+    let code = match e {
+        Error::Timeout => Code::GatewayTimeout,
+        Error::Url(UrlError::UnsupportedScheme(_)) => Code::ProxyingNotSupported,
+        _ => Code::BadGateway,
+    };
+
+    Message::new().with_code(code)
+}
+
+/// RFC 7252: 5.10.2.  Proxy-Uri and Proxy-Scheme
+///
+/// The absolute URI of the target of a proxy request, if it is one.
+fn target(request: &Message) -> Result<StdOption<Url>, Code> {
+    let options = &request.options;
+
+    if let Some(proxy_uri) = options.get_first::<ProxyUri>().map_err(|_| Code::BadOption)? {
+        return Url::parse(&proxy_uri.into_value()).map(Some).map_err(|_| Code::BadRequest);
+    }
+
+    let scheme = match options.get_first::<ProxyScheme>().map_err(|_| Code::BadOption)? {
+        Some(scheme) => scheme.into_value(),
+        None => return Ok(None),
+    };
+
+    // without a Uri-Host there is nowhere to forward to but this proxy
+    if options.get_raw::<UriHost>().is_none() {
+        return Err(Code::BadRequest);
+    }
+
+    uri::compose_with_scheme(&scheme, options, None).map(Some).map_err(|_| Code::BadRequest)
+}
+
+/// Send `msg`, meant for `endpoint`, through the forward proxy at `proxy`
+/// instead.
+pub(crate) fn via(proxy: Endpoint, endpoint: Endpoint, mut msg: Message) -> (Endpoint, Message) {
+    match endpoint {
+        Endpoint::Resolved(addr) => {
+            msg.options.set(UriHost::new(addr.ip().to_string()));
+            if addr.port() != 5683 {
+                msg.options.set(UriPort::new(u64::from(addr.port())));
+            }
+        }
+        Endpoint::Unresolved(_, port) if port != 5683 => {
+            msg.options.set(UriPort::new(u64::from(port)));
+        }
+        _ => (),
+    }
+    msg.options.set(ProxyScheme::new("coap".to_string()));
+
+    (proxy, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::SocketAddr;
+
+    use sim::{Config, Network, Simulation};
+    use server::Server;
+
+    /// An origin server answering with the path and query it was asked for,
+    /// and a proxy in front of it.
+    fn setup() -> (Simulation, Network, SocketAddr, SocketAddr) {
+        let net = Network::new(Config::new());
+        let origin_socket = net.bind_any().unwrap();
+        let origin_addr = origin_socket.local_addr();
+        let proxy_socket = net.bind_any().unwrap();
+        let proxy_addr = proxy_socket.local_addr();
+
+        let origin = |request: Request| {
+            let mut target = request.message.uri_path()?;
+            if let Some(query) = request.message.options.map.get(&15) {
+                target.push('?');
+                target.push_str(&String::from_utf8_lossy(&query[0]));
+            }
+            Ok(Message::new().with_code(Code::Content).with_payload(target.into_bytes()))
+        };
+
+        let proxy_net = net.clone();
+        let proxy = ForwardProxy::from_fn(move |client: Client| client.send_over(proxy_net.bind_any().unwrap()));
+
+        let mut sim = Simulation::new(net.clone());
+        sim.spawn(Server::new(origin).serve(origin_socket).map_err(|_| ()));
+        sim.spawn(Server::new(proxy).serve(proxy_socket).map_err(|_| ()));
+
+        (sim, net, origin_addr, proxy_addr)
+    }
+
+    #[test]
+    fn client_through_proxy() {
+        let (mut sim, net, origin_addr, proxy_addr) = setup();
+
+        let client = Client::get(&format!("coap://{}/sensors/temp?unit=C", origin_addr)).unwrap()
+            .with_proxy(Endpoint::Resolved(proxy_addr));
+        let response = sim.run(client.send_over(net.bind_any().unwrap())).unwrap();
+
+        assert_eq!(response.code, Code::Content);
+        assert_eq!(response.payload, b"/sensors/temp?unit=C");
+    }
+
+    #[test]
+    fn proxy_uri() {
+        let (mut sim, net, origin_addr, _) = setup();
+        let proxy = ForwardProxy::from_fn(move |client: Client| client.send_over(net.bind_any().unwrap()));
+
+        let mut message = Message::new();
+        message.options.push(ProxyUri::new(format!("coap://{}/a%2Fb", origin_addr)));
+        let request = Request { message, source: "192.0.2.1:5683".parse().unwrap() };
+
+        let response = sim.run(proxy.handle(request)).unwrap();
+        assert_eq!(response.code, Code::Content);
+        assert_eq!(response.payload, b"/a/b");
+    }
+
+    #[test]
+    fn proxy_scheme() {
+        let (mut sim, net, origin_addr, _) = setup();
+        let proxy = ForwardProxy::from_fn(move |client: Client| client.send_over(net.bind_any().unwrap()));
+
+        // a slash and an ampersand within a segment and a query parameter
+        let mut message = Message::new().with_code(Code::Get);
+        message.options.push(ProxyScheme::new("coap".to_string()));
+        message.options.push(UriHost::new(origin_addr.ip().to_string()));
+        message.options.push(UriPort::new(u64::from(origin_addr.port())));
+        message.options.push_raw(11, b"a/b".to_vec());
+        message.options.push_raw(15, b"x&y".to_vec());
+
+        let url = target(&message).unwrap().unwrap();
+        assert_eq!(url.as_str(), format!("coap://{}/a%2Fb?x%26y", origin_addr));
+
+        let request = Request { message, source: "192.0.2.1:5683".parse().unwrap() };
+        let response = sim.run(proxy.handle(request)).unwrap();
+        assert_eq!(response.code, Code::Content);
+        assert_eq!(response.payload, b"/a/b?x&y");
+    }
+
+    #[test]
+    fn unrecognized_options() {
+        let (mut sim, net, origin_addr, proxy_addr) = setup();
+        let mut send = |number: u16| {
+            let mut msg = Message::new().with_code(Code::Get);
+            msg.options.push_raw(number, b"x".to_vec());
+            let client = Client::from_message(Endpoint::Resolved(origin_addr), msg)
+                .with_proxy(Endpoint::Resolved(proxy_addr));
+            sim.run(client.send_over(net.bind_any().unwrap())).unwrap().code
+        };
+
+        // safe to forward, elective and critical, the latter rejected by
+        // the origin rather than the proxy
+        assert_eq!(send(2048), Code::Content);
+        assert_eq!(send(2049), Code::BadOption);
+        // unsafe
+        assert_eq!(send(2054), Code::BadGateway);
+        assert_eq!(send(2055), Code::BadGateway);
+    }
+
+    #[test]
+    fn unsupported_targets() {
+        let proxy = ForwardProxy::new();
+        let request = |option: StdOption<ProxyUri>| {
+            let mut message = Message::new();
+            if let Some(option) = option {
+                message.options.push(option);
+            }
+            Request { message, source: "192.0.2.1:5683".parse().unwrap() }
+        };
+
+        let http = request(Some(ProxyUri::new("http://example.com/".to_string())));
+        assert_eq!(proxy.handle(http).wait().unwrap().code, Code::ProxyingNotSupported);
+
+        let relative = request(Some(ProxyUri::new("/a".to_string())));
+        assert_eq!(proxy.handle(relative).wait().unwrap().code, Code::BadRequest);
+
+        assert_eq!(proxy.handle(request(None)).wait().unwrap().code, Code::NotFound);
+    }
+}
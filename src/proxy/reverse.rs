@@ -0,0 +1,623 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::option::Option as StdOption;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures::prelude::*;
+use futures::future;
+use futures::sync::oneshot;
+use futures::task::{self, Task};
+
+use tokio::net::{UdpFramed, UdpSocket};
+
+use cache::Cache;
+use client::{self, Client, IoFuture, Transport};
+use codec::CoapCodec;
+use endpoint::Endpoint;
+use error::Error;
+use message::{option, Code, Message, Mtype};
+use message::option::{Observe, Option, UriHost, UriPath, UriPort};
+use server::{Handler, Notifications, Request};
+use util::random_u64;
+
+use super::{check_options, failure};
+
+/// A downstream client observing a resource, by address and token.
+type Observer = (SocketAddr, Vec<u8>);
+
+/// An upstream resource, by backend and the options of the request for it.
+type Key = (SocketAddr, Vec<(u16, Vec<u8>)>);
+
+/// A `Handler` standing in for a set of backend servers.
+///
+/// Each request is forwarded to the backend whose route is the longest
+/// prefix of its Uri-Path, with that prefix removed. Requests matching no
+/// route are answered with 4.04 Not Found. Responses are cached for as long
+/// as their Max-Age allows. Requests to the backends are retransmitted as a
+/// client's are, and those going unanswered for the timeout are answered
+/// with 5.04 Gateway Timeout.
+///
+/// Observers of the same resource share a single registration with the
+/// backend, whose notifications are relayed to each of them. These are sent
+/// by the stream returned from `notifications`, which must be given to the
+/// `Server` running the proxy. An observer leaves by deregistering or by
+/// rejecting a notification with a Reset, and once the last one has left the
+/// registration with the backend is cancelled.
+pub struct ReverseProxy<B> {
+    /// path prefixes and the backends they map to, longest first
+    routes: Vec<(Vec<String>, SocketAddr)>,
+    bind: B,
+    cache: Arc<Mutex<Cache>>,
+    relay: Arc<Mutex<RelayState>>,
+    /// how long to wait for a backend to respond
+    timeout: Duration,
+}
+
+/// A UDP socket to reach `backend` from, on any address of its family.
+fn bind_udp(backend: SocketAddr) -> Result<UdpFramed<CoapCodec>, Error> {
+    let sock = UdpSocket::bind(&client::unspecified(&backend))?;
+
+    Ok(UdpFramed::new(sock, CoapCodec))
+}
+
+impl ReverseProxy<fn(SocketAddr) -> Result<UdpFramed<CoapCodec>, Error>> {
+    /// Talk to backends over UDP.
+    pub fn new() -> Self {
+        ReverseProxy::from_fn(bind_udp)
+    }
+}
+
+impl Default for ReverseProxy<fn(SocketAddr) -> Result<UdpFramed<CoapCodec>, Error>> {
+    fn default() -> Self {
+        ReverseProxy::new()
+    }
+}
+
+impl<B, T> ReverseProxy<B>
+    where B: Fn(SocketAddr) -> Result<T, Error> + Send + Sync + 'static,
+          T: Transport,
+{
+    /// Talk to backends over the transports made by `bind` for the address
+    /// of a backend, one for each request and for each resource observed.
+    pub fn from_fn(bind: B) -> ReverseProxy<B> {
+        ReverseProxy {
+            routes: Vec::new(),
+            bind,
+            cache: Arc::new(Mutex::new(Cache::new())),
+            relay: Arc::new(Mutex::new(RelayState::default())),
+            timeout: client::DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Forward requests for paths starting with `prefix` to `backend`.
+    pub fn with_route(mut self, prefix: &str, backend: SocketAddr) -> Self {
+        let prefix = prefix.split('/').filter(|s| !s.is_empty()).map(str::to_string).collect();
+
+        self.routes.push((prefix, backend));
+        self.routes.sort_by_key(|route| Reverse(route.0.len()));
+        self
+    }
+
+    /// Cache responses in `cache` rather than a cache of the proxy's own.
+    pub fn with_cache(mut self, cache: Arc<Mutex<Cache>>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Give up on a backend that hasn't responded after `timeout`. For an
+    /// observation only the response to the registration has to arrive in
+    /// time.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The notifications to relay to observers, to be sent by the server.
+    pub fn notifications(&self) -> Notifications {
+        Box::new(Relay { state: self.relay.clone() })
+    }
+
+    /// The backend for `request` and the request to send it.
+    fn route(&self, request: &Message) -> StdOption<(SocketAddr, Message)> {
+        let path = request.options.map.get(&UriPath::NUMBER).cloned().unwrap_or_default();
+
+        let (prefix, backend) = self.routes.iter().find(|(prefix, _)| {
+            prefix.len() <= path.len() && prefix.iter().zip(&path).all(|(p, s)| p.as_bytes() == s.as_slice())
+        })?;
+
+        let mut msg = request.clone()
+            .with_mtype(Mtype::Confirmable)
+            .with_token(&[]);
+        msg.options.remove::<UriHost>();
+        msg.options.remove::<UriPort>();
+        msg.options.remove::<UriPath>();
+        if path.len() > prefix.len() {
+            msg.options.map.insert(UriPath::NUMBER, path[prefix.len()..].to_vec());
+        }
+
+        Some((*backend, msg))
+    }
+
+    /// Add `observer` to the observers of the resource `msg` asks for,
+    /// registering with the backend if it is the first.
+    fn register(&self, backend: SocketAddr, msg: Message, observer: Observer) -> IoFuture<Message> {
+        let key = observation_key(backend, &msg);
+        let mut relay = self.relay.lock().unwrap();
+
+        if !relay.registrations.contains_key(&key) {
+            let transport = match (self.bind)(backend) {
+                Ok(transport) => transport,
+                Err(e) => return Box::new(future::ok(failure(e))),
+            };
+
+            debug!("registering with {} for a new observation", backend);
+            let (cancel, cancelled) = oneshot::channel();
+            let id = relay.next_id;
+            relay.next_id += 1;
+            relay.upstreams.push((key.clone(), id, client::observe_until(transport, msg, backend, self.timeout, cancelled.map_err(|_| ()))));
+            relay.registrations.insert(key.clone(), Registration::new(id, cancel));
+            if let Some(task) = relay.task.take() {
+                task.notify();
+            }
+        }
+
+        let registration = relay.registrations.get_mut(&key).unwrap();
+        registration.observers.retain(|o| *o != observer);
+
+        if let Some(ref latest) = registration.latest {
+            registration.observers.push(observer);
+            return Box::new(future::ok(latest.clone()));
+        }
+
+        let (tx, rx) = oneshot::channel();
+        registration.waiting.push((observer, tx));
+
+        Box::new(rx.or_else(|_| Ok(Message::new().with_code(Code::BadGateway))))
+    }
+
+    /// Remove `observer` from the observers of the resource `msg` asks for.
+    fn deregister(&self, backend: SocketAddr, msg: &Message, observer: &Observer) {
+        let key = observation_key(backend, msg);
+
+        self.relay.lock().unwrap().leave(&key, observer);
+    }
+}
+
+impl<B, T> Handler for ReverseProxy<B>
+    where B: Fn(SocketAddr) -> Result<T, Error> + Send + Sync + 'static,
+          T: Transport,
+{
+    fn handle(&self, request: Request) -> IoFuture<Message> {
+        if let Err(code) = check_options(&request.message) {
+            return Box::new(future::ok(Message::new().with_code(code)));
+        }
+
+        let (backend, mut msg) = match self.route(&request.message) {
+            Some(route) => route,
+            None => return Box::new(future::ok(Message::new().with_code(Code::NotFound))),
+        };
+
+        // RFC 7641: 3.1.  Registration and 3.6.  Cancellation
+        let observer = (request.source, request.message.token.to_vec());
+        match (request.message.code, request.message.observe()) {
+            (Code::Get, Ok(Some(0))) => return self.register(backend, msg, observer),
+            (_, Ok(Some(_))) => {
+                self.deregister(backend, &msg, &observer);
+                msg.options.remove::<Observe>();
+            }
+            _ => (),
+        }
+
+        let transport = match (self.bind)(backend) {
+            Ok(transport) => transport,
+            Err(e) => return Box::new(future::ok(failure(e))),
+        };
+
+        let response = Client::from_message(Endpoint::Resolved(backend), msg)
+            .with_cache(self.cache.clone())
+            .with_timeout(self.timeout)
+            .send_over(transport)
+            .or_else(|e| Ok(failure(e)));
+
+        Box::new(response)
+    }
+
+    fn forwards_options(&self) -> bool {
+        true
+    }
+
+    /// RFC 7641: 3.6.  Cancellation
+    ///
+    /// An observer rejecting a notification with a Reset is removed.
+    fn reset(&self, source: SocketAddr, mid: u16) {
+        let mut relay = self.relay.lock().unwrap();
+
+        let rejected = relay.registrations.iter().find_map(|(key, registration)| {
+            registration.relayed
+                .iter()
+                .find(|&(observer, &relayed)| observer.0 == source && relayed == mid)
+                .map(|(observer, _)| (key.clone(), observer.clone()))
+        });
+
+        if let Some((key, observer)) = rejected {
+            debug!("{} rejected a notification", source);
+            relay.leave(&key, &observer);
+        }
+    }
+}
+
+fn observation_key(backend: SocketAddr, msg: &Message) -> Key {
+    let options = msg.options.map
+        .iter()
+        .filter(|&(&n, _)| n != Observe::NUMBER && !option::is_no_cache_key(n))
+        .flat_map(|(&n, values)| values.iter().map(move |v| (n, v.clone())))
+        .collect();
+
+    (backend, options)
+}
+
+struct Registration {
+    /// tells the notifications of this registration from those of an
+    /// earlier one for the same resource, still being cancelled
+    id: u64,
+    /// the observers receiving notifications
+    observers: Vec<Observer>,
+    /// the message ID of the latest notification relayed to each observer,
+    /// to recognize a Reset rejecting it
+    relayed: HashMap<Observer, u16>,
+    /// the most recent notification, once there is one
+    latest: StdOption<Message>,
+    /// observers waiting for the first notification, to get it as the
+    /// response to their registration
+    waiting: Vec<(Observer, oneshot::Sender<Message>)>,
+    /// dropped along with the registration, which cancels it with the
+    /// backend
+    _cancel: oneshot::Sender<()>,
+}
+
+impl Registration {
+    fn new(id: u64, cancel: oneshot::Sender<()>) -> Registration {
+        Registration {
+            id,
+            observers: Vec::new(),
+            relayed: HashMap::new(),
+            latest: None,
+            waiting: Vec::new(),
+            _cancel: cancel,
+        }
+    }
+}
+
+#[derive(Default)]
+struct RelayState {
+    registrations: HashMap<Key, Registration>,
+    next_id: u64,
+    /// the notifications of each registration with a backend, by resource
+    /// and registration
+    upstreams: Vec<(Key, u64, client::IoStream<Message>)>,
+    /// notifications ready to be sent to observers
+    outgoing: VecDeque<(Message, SocketAddr)>,
+    /// the task to wake when a new registration is made
+    task: StdOption<Task>,
+}
+
+impl RelayState {
+    /// Poll the registrations with backends until one makes progress,
+    /// returning whether one did.
+    ///
+    /// The registrations that were cancelled are polled until they have
+    /// told their backend, their notifications are dropped meanwhile.
+    fn poll_upstreams(&mut self) -> bool {
+        for i in 0..self.upstreams.len() {
+            let (key, id) = (self.upstreams[i].0.clone(), self.upstreams[i].1);
+
+            match self.upstreams[i].2.poll() {
+                Ok(Async::NotReady) => continue,
+                Ok(Async::Ready(Some(notification))) => self.deliver(&key, id, notification),
+                Ok(Async::Ready(None)) => {
+                    drop(self.upstreams.swap_remove(i));
+                    if self.registrations.get(&key).is_some_and(|registration| registration.id == id) {
+                        self.registrations.remove(&key);
+                    }
+                }
+                Err(e) => {
+                    drop(self.upstreams.swap_remove(i));
+                    self.deliver(&key, id, failure(e));
+                }
+            }
+
+            return true;
+        }
+
+        false
+    }
+
+    /// Hand a message from a backend to the observers of its resource. A
+    /// message that isn't a notification ends the observation.
+    fn deliver(&mut self, key: &Key, id: u64, msg: Message) {
+        let notification = client::is_notification(&msg);
+
+        let registration = match self.registrations.get_mut(key) {
+            Some(registration) if registration.id == id => registration,
+            _ => return,
+        };
+
+        for observer in &registration.observers {
+            let relayed = Message {
+                mtype: Mtype::NonConfirmable,
+                mid: random_u64() as u16,
+                ..msg.clone()
+            };
+            registration.relayed.insert(observer.clone(), relayed.mid);
+            self.outgoing.push_back((relayed.with_token(&observer.1), observer.0));
+        }
+
+        for (observer, tx) in registration.waiting.drain(..) {
+            let _ = tx.send(msg.clone());
+            if notification {
+                registration.observers.push(observer);
+            }
+        }
+
+        if notification {
+            registration.latest = Some(msg);
+        } else {
+            self.registrations.remove(key);
+        }
+    }
+
+    /// Remove `observer` from the observers of the resource `key`, cancelling
+    /// the registration with the backend if it was the last.
+    fn leave(&mut self, key: &Key, observer: &Observer) {
+        let empty = match self.registrations.get_mut(key) {
+            Some(registration) => {
+                registration.observers.retain(|o| o != observer);
+                registration.relayed.remove(observer);
+                registration.observers.is_empty() && registration.waiting.is_empty()
+            }
+            None => false,
+        };
+
+        if empty {
+            debug!("last observer of a resource on {} left", key.0);
+            self.registrations.remove(key);
+        }
+    }
+}
+
+/// The stream of notifications relayed by a `ReverseProxy`.
+struct Relay {
+    state: Arc<Mutex<RelayState>>,
+}
+
+impl Stream for Relay {
+    type Item = (Message, SocketAddr);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<StdOption<Self::Item>, Error> {
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            if let Some(next) = state.outgoing.pop_front() {
+                return Ok(Async::Ready(Some(next)));
+            }
+
+            if !state.poll_upstreams() {
+                state.task = Some(task::current());
+                return Ok(Async::NotReady);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use futures::future::Loop;
+
+    use sim::{Config, Network, Simulation, Socket};
+    use server::Server;
+
+    type State = (Socket, StdOption<Observer>, u32);
+
+    /// A backend with one observable resource, whose value changes with
+    /// every PUT. Each request is logged as `method path`.
+    fn backend(socket: Socket, log: Arc<Mutex<Vec<String>>>) -> IoFuture<()> {
+        Box::new(future::loop_fn((socket, None, 1), move |(socket, observer, version): State| {
+            let log = log.clone();
+
+            socket.into_future().map_err(|(e, _)| e).and_then(move |(received, socket)| -> IoFuture<Loop<(), State>> {
+                let (request, addr) = match received {
+                    Some(received) => received,
+                    None => return Box::new(future::ok(Loop::Break(()))),
+                };
+
+                let path = request.uri_path().unwrap();
+                let reply = request.new_reply();
+                let response = |version: u32| {
+                    Message::new().with_code(Code::Content).with_observe(version).with_payload(format!("v{}", version).into_bytes())
+                };
+
+                match (request.code, request.observe()) {
+                    (Code::Get, Ok(Some(0))) => {
+                        log.lock().unwrap().push(format!("observe {}", path));
+                        let observer = Some((addr, request.token.to_vec()));
+                        let reply = Message { mtype: reply.mtype, mid: reply.mid, token: reply.token, ..response(version) };
+
+                        Box::new(socket.send((reply, addr)).map(move |socket| Loop::Continue((socket, observer, version))))
+                    }
+                    (Code::Get, Ok(Some(1))) => {
+                        log.lock().unwrap().push(format!("deregister {}", path));
+                        let reply = reply.with_code(Code::Content).with_payload(path.into_bytes());
+
+                        Box::new(socket.send((reply, addr)).map(move |socket| Loop::Continue((socket, None, version))))
+                    }
+                    (Code::Get, _) => {
+                        log.lock().unwrap().push(format!("get {}", path));
+                        let reply = reply.with_code(Code::Content).with_payload(path.into_bytes());
+
+                        Box::new(socket.send((reply, addr)).map(move |socket| Loop::Continue((socket, observer, version))))
+                    }
+                    (Code::Put, _) => {
+                        log.lock().unwrap().push(format!("put {}", path));
+                        let version = version + 1;
+                        let changed = socket.send((reply.with_code(Code::Changed), addr));
+
+                        Box::new(changed.and_then(move |socket| -> IoFuture<Loop<(), State>> {
+                            match observer.clone() {
+                                Some((observer_addr, token)) => {
+                                    let notification = response(version)
+                                        .with_mtype(Mtype::NonConfirmable)
+                                        .with_mid(random_u64() as u16)
+                                        .with_token(&token);
+                                    let sent = socket.send((notification, observer_addr));
+
+                                    Box::new(sent.map(move |socket| Loop::Continue((socket, observer, version))))
+                                }
+                                None => Box::new(future::ok(Loop::Continue((socket, observer, version)))),
+                            }
+                        }))
+                    }
+                    _ => Box::new(future::ok(Loop::Continue((socket, observer, version)))),
+                }
+            })
+        }))
+    }
+
+    fn setup() -> (Simulation, Network, Arc<Mutex<Vec<String>>>, SocketAddr, SocketAddr) {
+        let net = Network::new(Config::new());
+        let backend_socket = net.bind_any().unwrap();
+        let backend_addr = backend_socket.local_addr();
+        let proxy_socket = net.bind_any().unwrap();
+        let proxy_addr = proxy_socket.local_addr();
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let proxy_net = net.clone();
+        let proxy = ReverseProxy::from_fn(move |_| proxy_net.bind_any())
+            .with_route("/dev", "192.0.2.1:5683".parse().unwrap())
+            .with_route("/dev/1", backend_addr);
+        let notifications = proxy.notifications();
+
+        let mut sim = Simulation::new(net.clone());
+        sim.spawn(backend(backend_socket, log.clone()).map_err(|_| ()));
+        sim.spawn(Server::new(proxy).with_notifications(notifications).serve(proxy_socket).map_err(|_| ()));
+
+        (sim, net, log, backend_addr, proxy_addr)
+    }
+
+    fn get(path: &str) -> Message {
+        let mut msg = Message::new();
+        msg.set_uri_path(path).unwrap();
+        msg
+    }
+
+    #[test]
+    fn routes_and_caches() {
+        let (mut sim, net, log, _, proxy_addr) = setup();
+
+        for _ in 0..2 {
+            let (response, _) = sim.run(client::exchange(net.bind_any().unwrap(), get("/dev/1/temp"), proxy_addr)).unwrap();
+            assert_eq!(response.code, Code::Content);
+            assert_eq!(response.payload, b"/temp");
+        }
+
+        let (response, _) = sim.run(client::exchange(net.bind_any().unwrap(), get("/other"), proxy_addr)).unwrap();
+        assert_eq!(response.code, Code::NotFound);
+
+        assert_eq!(*log.lock().unwrap(), vec!["get /temp"]);
+    }
+
+    #[test]
+    fn unresponsive_backend_times_out() {
+        let net = Network::new(Config::new());
+        let proxy_socket = net.bind_any().unwrap();
+        let proxy_addr = proxy_socket.local_addr();
+
+        // nothing is listening on the backend's address
+        let proxy_net = net.clone();
+        let proxy = ReverseProxy::from_fn(move |_| proxy_net.bind_any())
+            .with_route("/dev", "192.0.2.1:5683".parse().unwrap())
+            .with_timeout(Duration::from_secs(5));
+        let notifications = proxy.notifications();
+
+        let mut sim = Simulation::new(net.clone());
+        sim.spawn(Server::new(proxy).with_notifications(notifications).serve(proxy_socket).map_err(|_| ()));
+
+        let start = net.now();
+        let (response, _) = sim.run(client::exchange(net.bind_any().unwrap(), get("/dev/temp"), proxy_addr)).unwrap();
+        assert_eq!(response.code, Code::GatewayTimeout);
+        assert_eq!(net.now() - start, Duration::from_secs(5));
+
+        let observe = get("/dev/humidity").with_observe(0);
+        let (response, _) = sim.run(client::exchange(net.bind_any().unwrap(), observe, proxy_addr)).unwrap();
+        assert_eq!(response.code, Code::GatewayTimeout);
+    }
+
+    #[test]
+    fn binds_family_of_backend() {
+        let v4 = bind_udp("192.0.2.1:5683".parse().unwrap()).unwrap();
+        let v6 = bind_udp("[2001:db8::1]:5683".parse().unwrap()).unwrap();
+
+        assert!(v4.get_ref().local_addr().unwrap().is_ipv4());
+        assert!(v6.get_ref().local_addr().unwrap().is_ipv6());
+    }
+
+    #[test]
+    fn observers_share_registration() {
+        let (mut sim, net, log, backend_addr, proxy_addr) = setup();
+
+        let observe = get("/dev/1/temp").with_observe(0);
+        let (first, observer_a) = sim.run(client::exchange(net.bind_any().unwrap(), observe.clone(), proxy_addr)).unwrap();
+        let (second, observer_b) = sim.run(client::exchange(net.bind_any().unwrap(), observe, proxy_addr)).unwrap();
+
+        assert_eq!(first.payload, b"v1");
+        assert_eq!(second.payload, b"v1");
+        assert_eq!(second.observe(), Ok(Some(1)));
+
+        let put = get("/temp").with_code(Code::Put);
+        sim.run(client::exchange(net.bind_any().unwrap(), put, backend_addr)).unwrap();
+
+        for (observer, registration) in [(observer_a, first), (observer_b, second)] {
+            let (notification, _) = sim.run(observer.into_future().map_err(|(e, _)| e)).unwrap();
+            let (notification, _) = notification.unwrap();
+
+            assert_eq!(notification.token, registration.token);
+            assert_eq!(notification.observe(), Ok(Some(2)));
+            assert_eq!(notification.payload, b"v2");
+        }
+
+        assert_eq!(*log.lock().unwrap(), vec!["observe /temp", "put /temp"]);
+    }
+
+    #[test]
+    fn reset_cancels_registration() {
+        let (mut sim, net, log, backend_addr, proxy_addr) = setup();
+
+        let observe = get("/dev/1/temp").with_observe(0);
+        let (_, observer) = sim.run(client::exchange(net.bind_any().unwrap(), observe, proxy_addr)).unwrap();
+
+        let put = get("/temp").with_code(Code::Put);
+        sim.run(client::exchange(net.bind_any().unwrap(), put.clone(), backend_addr)).unwrap();
+
+        let (notification, observer) = sim.run(observer.into_future().map_err(|(e, _)| e)).unwrap();
+        let (notification, _) = notification.unwrap();
+        assert_eq!(notification.payload, b"v2");
+
+        // the only observer rejects the notification, so the proxy cancels
+        // its registration with the backend
+        let reset = Message::new().with_mtype(Mtype::Reset).with_code(Code::Empty).with_mid(notification.mid);
+        sim.run(observer.send((reset, proxy_addr))).unwrap();
+        sim.run(net.sleep(Duration::from_secs(1))).unwrap();
+
+        // and the backend has nobody left to notify
+        sim.run(client::exchange(net.bind_any().unwrap(), put, backend_addr)).unwrap();
+
+        assert_eq!(*log.lock().unwrap(), vec!["observe /temp", "put /temp", "deregister /temp", "put /temp"]);
+    }
+}
@@ -0,0 +1,164 @@
+//! Turning host names into addresses.
+//!
+//! `Endpoint::Unresolved` names a host that has to be looked up before a
+//! request can be sent. How that happens is up to a `Resolver`: by default
+//! the system resolver is asked, with answers cached for a minute, but tests
+//! can use a fixed map of hosts instead.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use futures::prelude::*;
+use futures::future;
+
+use tokio_dns;
+
+use client::IoFuture;
+use error::Error;
+
+/// How long resolved addresses are used for unless told otherwise.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+/// Something that can look up the addresses of a host.
+pub trait Resolver: Send + Sync + 'static {
+    /// Every address of `host`, most preferred first.
+    fn resolve(&self, host: &str) -> IoFuture<Vec<IpAddr>>;
+}
+
+impl<R: Resolver + ?Sized> Resolver for Arc<R> {
+    fn resolve(&self, host: &str) -> IoFuture<Vec<IpAddr>> {
+        (**self).resolve(host)
+    }
+}
+
+/// The resolver used unless told otherwise: the system resolver, cached.
+pub fn default_resolver() -> Arc<dyn Resolver> {
+    static DEFAULT: OnceLock<Arc<dyn Resolver>> = OnceLock::new();
+
+    DEFAULT.get_or_init(|| Arc::new(CachingResolver::new(DnsResolver))).clone()
+}
+
+/// Looks hosts up with the system resolver, on a thread pool.
+pub struct DnsResolver;
+
+impl Resolver for DnsResolver {
+    fn resolve(&self, host: &str) -> IoFuture<Vec<IpAddr>> {
+        Box::new(tokio_dns::resolve::<&str>(host).map_err(Error::Io))
+    }
+}
+
+/// Looks hosts up in a fixed map, e.g. to give names to test servers.
+#[derive(Default)]
+pub struct StaticResolver {
+    hosts: HashMap<String, Vec<IpAddr>>,
+}
+
+impl StaticResolver {
+    pub fn new() -> StaticResolver {
+        StaticResolver::default()
+    }
+
+    /// Resolve `host` to `addrs`, in that order.
+    pub fn with_host(mut self, host: &str, addrs: Vec<IpAddr>) -> Self {
+        self.hosts.insert(host.to_lowercase(), addrs);
+        self
+    }
+}
+
+impl Resolver for StaticResolver {
+    fn resolve(&self, host: &str) -> IoFuture<Vec<IpAddr>> {
+        match self.hosts.get(&host.to_lowercase()) {
+            Some(addrs) => Box::new(future::ok(addrs.clone())),
+            None => Box::new(future::err(Error::Io(io::Error::new(io::ErrorKind::NotFound, "unknown host")))),
+        }
+    }
+}
+
+/// When each cached host was resolved, and to what.
+type Entries = HashMap<String, (Instant, Vec<IpAddr>)>;
+
+/// Remembers the addresses found by another resolver for a while.
+///
+/// Failed lookups and hosts without addresses aren't remembered.
+pub struct CachingResolver<R> {
+    resolver: R,
+    ttl: Duration,
+    entries: Arc<Mutex<Entries>>,
+}
+
+impl<R: Resolver> CachingResolver<R> {
+    pub fn new(resolver: R) -> CachingResolver<R> {
+        CachingResolver {
+            resolver,
+            ttl: DEFAULT_TTL,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Use resolved addresses for `ttl`.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+impl<R: Resolver> Resolver for CachingResolver<R> {
+    fn resolve(&self, host: &str) -> IoFuture<Vec<IpAddr>> {
+        let host = host.to_lowercase();
+
+        if let Some(&(resolved, ref addrs)) = self.entries.lock().unwrap().get(&host) {
+            if resolved.elapsed() < self.ttl {
+                return Box::new(future::ok(addrs.clone()));
+            }
+        }
+
+        let entries = self.entries.clone();
+        Box::new(self.resolver.resolve(&host).map(move |addrs| {
+            if !addrs.is_empty() {
+                entries.lock().unwrap().insert(host, (Instant::now(), addrs.clone()));
+            }
+            addrs
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Counts how often it's asked.
+    struct Counting(Arc<AtomicUsize>, StaticResolver);
+
+    impl Resolver for Counting {
+        fn resolve(&self, host: &str) -> IoFuture<Vec<IpAddr>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            self.1.resolve(host)
+        }
+    }
+
+    #[test]
+    fn caching() {
+        let lookups = Arc::new(AtomicUsize::new(0));
+        let hosts = StaticResolver::new().with_host("Sensor.example", vec!["192.0.2.7".parse().unwrap()]);
+        let resolver = CachingResolver::new(Counting(lookups.clone(), hosts));
+
+        assert_eq!(resolver.resolve("sensor.example").wait().unwrap(), vec!["192.0.2.7".parse::<IpAddr>().unwrap()]);
+        assert_eq!(resolver.resolve("SENSOR.example").wait().unwrap().len(), 1);
+        assert!(resolver.resolve("other.example").wait().is_err());
+        assert!(resolver.resolve("other.example").wait().is_err());
+        assert_eq!(lookups.load(Ordering::SeqCst), 3);
+
+        let resolver = resolver.with_ttl(Duration::from_secs(0));
+        resolver.resolve("sensor.example").wait().unwrap();
+        assert_eq!(lookups.load(Ordering::SeqCst), 4);
+    }
+}
@@ -0,0 +1,318 @@
+//! A CoAP server, answering requests with a `Handler`.
+//!
+//! The server takes care of the message layer: it matches the type and
+//! message ID of each reply to its request, answers pings, and recognises
+//! retransmitted or duplicated requests so that handlers only ever see each
+//! request once.
+
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use futures::prelude::*;
+use futures::future;
+
+use tokio::net::{UdpFramed, UdpSocket};
+
+use client::IoFuture;
+use codec::CoapCodec;
+use error::Error;
+use message::{Code, Message, Mtype};
+use util::random_u64;
+
+/// How many requests may be handled at the same time.
+const CONCURRENT_REQUESTS: usize = 64;
+
+/// How many recent exchanges are remembered to detect duplicates.
+const EXCHANGE_CACHE_SIZE: usize = 512;
+
+/// A request received by a server.
+#[derive(Debug)]
+pub struct Request {
+    /// the received message
+    pub message: Message,
+    /// the address the message came from
+    pub source: SocketAddr,
+}
+
+/// Something that can produce a response to a request.
+///
+/// The returned message only needs a code, options and a payload, the
+/// server fills in the type, message ID and token. Any closure taking a
+/// `Request` and returning something that can be turned into a future of a
+/// `Message` is a `Handler`.
+pub trait Handler: Send + Sync + 'static {
+    fn handle(&self, request: Request) -> IoFuture<Message>;
+
+    /// Whether requests are forwarded along with the options that aren't
+    /// recognized, as by a proxy (RFC 7252: 5.7.1). Unless they are, the
+    /// server rejects requests with unrecognized critical options itself.
+    fn forwards_options(&self) -> bool {
+        false
+    }
+
+    /// Called with each Reset message received, by the message ID it
+    /// rejects, such as that of a notification the client no longer wants
+    /// (RFC 7641: 3.6).
+    fn reset(&self, _source: SocketAddr, _mid: u16) {}
+}
+
+impl<F, R> Handler for F
+    where F: Fn(Request) -> R + Send + Sync + 'static,
+          R: IntoFuture<Item = Message, Error = Error>,
+          R::Future: Send + 'static,
+{
+    fn handle(&self, request: Request) -> IoFuture<Message> {
+        Box::new(self(request).into_future())
+    }
+}
+
+/// Messages sent by a server on its own accord, such as Observe
+/// notifications, along with where to send them.
+pub type Notifications = Box<dyn Stream<Item = (Message, SocketAddr), Error = Error> + Send>;
+
+pub struct Server<H> {
+    handler: Arc<H>,
+    notifications: Option<Notifications>,
+}
+
+impl<H: Handler> Server<H> {
+    pub fn new(handler: H) -> Server<H> {
+        Server {
+            handler: Arc::new(handler),
+            notifications: None,
+        }
+    }
+
+    /// Also send the messages produced by `notifications`, which is polled
+    /// for as long as the server runs.
+    pub fn with_notifications(mut self, notifications: Notifications) -> Self {
+        self.notifications = Some(notifications);
+        self
+    }
+
+    /// Serve requests on a UDP socket bound to `addr`.
+    pub fn bind(self, addr: &SocketAddr) -> Result<IoFuture<()>, Error> {
+        let sock = UdpSocket::bind(addr)?;
+
+        Ok(self.serve(UdpFramed::new(sock, CoapCodec)))
+    }
+
+    /// Serve requests arriving on `transport` until it closes.
+    pub fn serve<T>(self, transport: T) -> IoFuture<()>
+        where T: Stream<Item = (Message, SocketAddr), Error = Error>,
+              T: Sink<SinkItem = (Message, SocketAddr), SinkError = Error>,
+              T: Send + 'static,
+    {
+        let handler = self.handler;
+        let exchanges = Arc::new(Mutex::new(Exchanges::new()));
+
+        let (sink, stream) = transport.split();
+
+        let replies = stream
+            .then(|received| match received {
+                Ok(received) => Ok(Some(received)),
+                Err(e) => {
+                    warn!("dropping undecodable message: {:?}", e);
+                    Ok(None)
+                }
+            })
+            .filter_map(|received| received)
+            .map(move |(msg, addr)| process(&handler, &exchanges, msg, addr))
+            .buffer_unordered(CONCURRENT_REQUESTS)
+            .filter_map(|reply| reply);
+
+        let outgoing: Notifications = match self.notifications {
+            Some(notifications) => Box::new(replies.select(notifications)),
+            None => Box::new(replies),
+        };
+
+        Box::new(sink.send_all(outgoing).map(|_| ()))
+    }
+}
+
+fn process<H: Handler>(
+    handler: &Arc<H>,
+    exchanges: &Arc<Mutex<Exchanges>>,
+    msg: Message,
+    addr: SocketAddr,
+) -> IoFuture<Option<(Message, SocketAddr)>> {
+    info!("--> {:?}", msg);
+
+    match msg.mtype {
+        Mtype::Acknowledgement => return Box::new(future::ok(None)),
+        Mtype::Reset => {
+            handler.reset(addr, msg.mid);
+            return Box::new(future::ok(None));
+        }
+        Mtype::Confirmable | Mtype::NonConfirmable => (),
+    }
+
+    // Empty CON messages are pings, anything that isn't a request has no
+    // business arriving at a server.
+    if msg.code == Code::Empty || msg.code.class() != 0 {
+        let reply = match msg.mtype {
+            Mtype::Confirmable => Some((reset(&msg), addr)),
+            _ => None,
+        };
+        return Box::new(future::ok(reply));
+    }
+
+    // RFC 7252: 5.4.1.  Critical/Elective
+    //
+    // proxies check the options of the requests they forward themselves
+    let checked = match handler.forwards_options() {
+        true => Ok(()),
+        false => msg.check_options(),
+    };
+    if let Err(e) = checked {
+        debug!("rejecting request: {:?}", e);
+        let reply = match msg.mtype {
+            Mtype::Confirmable => {
+                let response = Message::new().with_code(Code::BadOption);
+                Some((complete_reply(msg.new_reply(), msg.mtype, response), addr))
+            }
+            _ => None,
+        };
+        return Box::new(future::ok(reply));
+    }
+
+    let key = (addr, msg.mid);
+    match exchanges.lock().unwrap().begin(key) {
+        Seen::New => (),
+        Seen::InProgress => {
+            debug!("ignoring duplicate of request still in progress");
+            return Box::new(future::ok(None));
+        }
+        Seen::Answered(reply) => {
+            debug!("repeating reply to duplicate request");
+            return Box::new(future::ok(Some((reply, addr))));
+        }
+    }
+
+    let request = Request {
+        message: msg,
+        source: addr,
+    };
+    let template = request.message.new_reply();
+    let mtype = request.message.mtype;
+    let exchanges = exchanges.clone();
+
+    let reply = handler.handle(request)
+        .or_else(|e| {
+            warn!("handler failed: {:?}", e);
+            Ok(Message::new().with_code(Code::InternalServerError))
+        })
+        .map(move |response| {
+            let reply = complete_reply(template, mtype, response);
+            info!("<-- {:?}", reply);
+            exchanges.lock().unwrap().finish(key, reply.clone());
+            Some((reply, addr))
+        });
+
+    Box::new(reply)
+}
+
+/// Fill in the message layer details of a handler's response.
+fn complete_reply(template: Message, request_mtype: Mtype, response: Message) -> Message {
+    let (mtype, mid) = match request_mtype {
+        Mtype::Confirmable => (Mtype::Acknowledgement, template.mid),
+        _ => (Mtype::NonConfirmable, random_u64() as u16),
+    };
+
+    Message {
+        mtype,
+        mid,
+        token: template.token,
+        ..response
+    }
+}
+
+fn reset(msg: &Message) -> Message {
+    Message::new()
+        .with_mtype(Mtype::Reset)
+        .with_code(Code::Empty)
+        .with_mid(msg.mid)
+}
+
+enum Seen {
+    New,
+    InProgress,
+    Answered(Message),
+}
+
+/// The most recent exchanges, used for message deduplication.
+struct Exchanges {
+    order: VecDeque<(SocketAddr, u16)>,
+    replies: HashMap<(SocketAddr, u16), Option<Message>>,
+}
+
+impl Exchanges {
+    fn new() -> Exchanges {
+        Exchanges {
+            order: VecDeque::new(),
+            replies: HashMap::new(),
+        }
+    }
+
+    fn begin(&mut self, key: (SocketAddr, u16)) -> Seen {
+        if let Some(reply) = self.replies.get(&key) {
+            return match *reply {
+                Some(ref reply) => Seen::Answered(reply.clone()),
+                None => Seen::InProgress,
+            };
+        }
+
+        if self.order.len() >= EXCHANGE_CACHE_SIZE {
+            if let Some(oldest) = self.order.pop_front() {
+                self.replies.remove(&oldest);
+            }
+        }
+
+        self.order.push_back(key);
+        self.replies.insert(key, None);
+
+        Seen::New
+    }
+
+    fn finish(&mut self, key: (SocketAddr, u16), reply: Message) {
+        if let Some(slot) = self.replies.get_mut(&key) {
+            *slot = Some(reply);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use client::exchange;
+    use sim::{Config, Network, Simulation};
+
+    #[test]
+    fn unrecognized_critical_option_rejected() {
+        let net = Network::new(Config::new());
+        let server_socket = net.bind_any().unwrap();
+        let server_addr = server_socket.local_addr();
+        let client_socket = net.bind_any().unwrap();
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler_calls = calls.clone();
+        let server = Server::new(move |_: Request| {
+            handler_calls.fetch_add(1, Ordering::SeqCst);
+            Ok(Message::new().with_code(Code::Content))
+        });
+
+        let mut sim = Simulation::new(net);
+        sim.spawn(server.serve(server_socket).map_err(|_| ()));
+
+        let mut request = Message::new();
+        request.options.push_raw(2049, vec![1]);
+        let (response, _) = sim.run(exchange(client_socket, request, server_addr)).unwrap();
+
+        assert_eq!(response.code, Code::BadOption);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+}
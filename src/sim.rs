@@ -0,0 +1,780 @@
+//! An in-memory network for deterministic protocol tests.
+//!
+//! A `Network` connects any number of simulated `Socket`s. Datagrams sent
+//! through it can be lost, duplicated, delayed, reordered or dropped for
+//! exceeding the MTU, all driven by a seeded random number generator and a
+//! virtual clock, so every run of a test sees exactly the same traffic.
+//!
+//! A `Socket` is a `Stream` and `Sink` of `(Message, SocketAddr)` just like a
+//! `UdpFramed<CoapCodec>`, so anything written against a real socket can be
+//! pointed at a simulated one instead.
+//!
+//! Time only moves when asked to, either explicitly with `Network::advance`
+//! or by a `Simulation`, which polls a set of futures and jumps the clock
+//! forward to the next scheduled event whenever they are all waiting.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::prelude::*;
+use futures::executor::{self, Notify, Spawn};
+use futures::task::{self, Task};
+
+use client::{IoFuture, Transport};
+use error::Error;
+use message::Message;
+
+/// The behaviour of the links in a simulated `Network`.
+#[derive(Clone, Debug)]
+pub struct Config {
+    loss: f64,
+    duplication: f64,
+    min_delay: Duration,
+    max_delay: Duration,
+    reordering: f64,
+    reordering_delay: Duration,
+    mtu: usize,
+    seed: u64,
+}
+
+impl Config {
+    /// A perfect network: nothing is lost, duplicated or delayed.
+    pub fn new() -> Config {
+        Config {
+            loss: 0.0,
+            duplication: 0.0,
+            min_delay: Duration::from_millis(0),
+            max_delay: Duration::from_millis(0),
+            reordering: 0.0,
+            reordering_delay: Duration::from_millis(0),
+            mtu: 1280,
+            seed: 0x5eed,
+        }
+    }
+
+    /// The probability, from 0 to 1, that any datagram is dropped.
+    pub fn with_loss(mut self, probability: f64) -> Self {
+        self.loss = probability;
+        self
+    }
+
+    /// The probability, from 0 to 1, that any datagram is delivered twice.
+    pub fn with_duplication(mut self, probability: f64) -> Self {
+        self.duplication = probability;
+        self
+    }
+
+    /// Delay every datagram by a uniformly chosen time between `min` and `max`.
+    pub fn with_delay(mut self, min: Duration, max: Duration) -> Self {
+        self.min_delay = min;
+        self.max_delay = if max < min { min } else { max };
+        self
+    }
+
+    /// Hold back a datagram by an additional `delay` with the given
+    /// probability, letting later datagrams overtake it.
+    pub fn with_reordering(mut self, probability: f64, delay: Duration) -> Self {
+        self.reordering = probability;
+        self.reordering_delay = delay;
+        self
+    }
+
+    /// Drop any datagram larger than `mtu` bytes.
+    pub fn with_mtu(mut self, mtu: usize) -> Self {
+        self.mtu = mtu;
+        self
+    }
+
+    /// Seed the random number generator deciding the fate of each datagram.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config::new()
+    }
+}
+
+/// Counters of what happened to the datagrams sent through a `Network`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub sent: usize,
+    pub delivered: usize,
+    pub lost: usize,
+    pub duplicated: usize,
+    pub reordered: usize,
+    pub oversized: usize,
+}
+
+/// A handle to a simulated network, cheap to clone.
+#[derive(Clone)]
+pub struct Network {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    config: Config,
+    rng: XorShift,
+    now: Instant,
+    seq: u64,
+    next_port: u16,
+    in_flight: Vec<Datagram>,
+    sockets: HashMap<SocketAddr, Mailbox>,
+    next_timer: u64,
+    /// the deadline of each waiting `Sleep`, by its id
+    timers: BTreeMap<u64, (Instant, Task)>,
+    stats: Stats,
+}
+
+struct Datagram {
+    deliver_at: Instant,
+    seq: u64,
+    from: SocketAddr,
+    to: SocketAddr,
+    bytes: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Mailbox {
+    queue: VecDeque<(Vec<u8>, SocketAddr)>,
+    task: Option<Task>,
+}
+
+impl Network {
+    pub fn new(config: Config) -> Network {
+        let rng = XorShift::new(config.seed);
+
+        Network {
+            inner: Arc::new(Mutex::new(Inner {
+                config,
+                rng,
+                now: Instant::now(),
+                seq: 0,
+                next_port: 49152,
+                in_flight: Vec::new(),
+                sockets: HashMap::new(),
+                next_timer: 0,
+                timers: BTreeMap::new(),
+                stats: Stats::default(),
+            })),
+        }
+    }
+
+    /// Attach a socket to the network at `addr`. A port of zero picks an
+    /// unused one.
+    pub fn bind(&self, addr: &SocketAddr) -> Result<Socket, Error> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut addr = *addr;
+
+        if addr.port() == 0 {
+            loop {
+                let port = inner.next_port;
+                inner.next_port = inner.next_port.checked_add(1).unwrap_or(49152);
+                addr.set_port(port);
+
+                if !inner.sockets.contains_key(&addr) {
+                    break;
+                }
+            }
+        } else if inner.sockets.contains_key(&addr) {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::AddrInUse, "address in use")));
+        }
+
+        inner.sockets.insert(addr, Mailbox::default());
+
+        Ok(Socket {
+            network: self.clone(),
+            addr,
+        })
+    }
+
+    /// Attach a socket on the loopback address with an unused port.
+    pub fn bind_any(&self) -> Result<Socket, Error> {
+        self.bind(&SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0))
+    }
+
+    /// The current time on the virtual clock.
+    pub fn now(&self) -> Instant {
+        self.inner.lock().unwrap().now
+    }
+
+    /// Move the virtual clock forward, delivering every datagram and firing
+    /// every `Sleep` that falls due.
+    pub fn advance(&self, duration: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        let now = inner.now + duration;
+        inner.advance_to(now);
+    }
+
+    /// The time of the next datagram delivery or `Sleep` expiry, if any.
+    pub fn next_event(&self) -> Option<Instant> {
+        let inner = self.inner.lock().unwrap();
+        let datagrams = inner.in_flight.iter().map(|d| d.deliver_at);
+        let timers = inner.timers.values().map(|t| t.0);
+
+        datagrams.chain(timers).min()
+    }
+
+    /// A future that completes once the virtual clock has moved `duration`
+    /// past the current time.
+    pub fn sleep(&self, duration: Duration) -> Sleep {
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_timer;
+        inner.next_timer += 1;
+
+        Sleep {
+            network: self.clone(),
+            id,
+            deadline: inner.now + duration,
+        }
+    }
+
+    /// The number of datagrams sent but not yet delivered.
+    pub fn in_flight(&self) -> usize {
+        self.inner.lock().unwrap().in_flight.len()
+    }
+
+    pub fn stats(&self) -> Stats {
+        self.inner.lock().unwrap().stats.clone()
+    }
+}
+
+impl Inner {
+    fn send(&mut self, from: SocketAddr, to: SocketAddr, bytes: Vec<u8>) {
+        self.stats.sent += 1;
+
+        if bytes.len() > self.config.mtu {
+            debug!("sim: dropping {} byte datagram over mtu", bytes.len());
+            self.stats.oversized += 1;
+            return;
+        }
+
+        let copies = if self.rng.chance(self.config.duplication) {
+            self.stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+
+        for _ in 0..copies {
+            if self.rng.chance(self.config.loss) {
+                debug!("sim: losing datagram {} -> {}", from, to);
+                self.stats.lost += 1;
+                continue;
+            }
+
+            let mut delay = self.rng.between(self.config.min_delay, self.config.max_delay);
+
+            if self.rng.chance(self.config.reordering) {
+                self.stats.reordered += 1;
+                delay += self.config.reordering_delay;
+            }
+
+            self.seq += 1;
+            self.in_flight.push(Datagram {
+                deliver_at: self.now + delay,
+                seq: self.seq,
+                from,
+                to,
+                bytes: bytes.clone(),
+            });
+        }
+
+        let now = self.now;
+        self.advance_to(now);
+    }
+
+    fn advance_to(&mut self, now: Instant) {
+        self.now = now;
+
+        let mut due: Vec<Datagram> = Vec::new();
+        let mut i = 0;
+        while i < self.in_flight.len() {
+            if self.in_flight[i].deliver_at <= now {
+                due.push(self.in_flight.swap_remove(i));
+            } else {
+                i += 1;
+            }
+        }
+        due.sort_by_key(|d| (d.deliver_at, d.seq));
+
+        for datagram in due {
+            match self.sockets.get_mut(&datagram.to) {
+                Some(mailbox) => {
+                    self.stats.delivered += 1;
+                    mailbox.queue.push_back((datagram.bytes, datagram.from));
+                    if let Some(task) = mailbox.task.take() {
+                        task.notify();
+                    }
+                }
+                None => debug!("sim: no socket at {}, dropping datagram", datagram.to),
+            }
+        }
+
+        let fired: Vec<u64> = self.timers.iter().filter(|t| (t.1).0 <= now).map(|t| *t.0).collect();
+        for id in fired {
+            if let Some((_, task)) = self.timers.remove(&id) {
+                task.notify();
+            }
+        }
+    }
+}
+
+/// A socket attached to a simulated `Network`.
+pub struct Socket {
+    network: Network,
+    addr: SocketAddr,
+}
+
+impl Socket {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn network(&self) -> &Network {
+        &self.network
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.network.inner.lock() {
+            inner.sockets.remove(&self.addr);
+        }
+    }
+}
+
+impl Transport for Socket {
+    /// Sleep on the virtual clock of the network.
+    fn sleep(&self, duration: Duration) -> IoFuture<()> {
+        Box::new(self.network.sleep(duration))
+    }
+}
+
+impl Stream for Socket {
+    type Item = (Message, SocketAddr);
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let mut inner = self.network.inner.lock().unwrap();
+        let mailbox = inner.sockets.get_mut(&self.addr).expect("socket outlived its mailbox");
+
+        match mailbox.queue.pop_front() {
+            Some((bytes, from)) => {
+                let msg = Message::from_bytes(&bytes)?;
+                Ok(Async::Ready(Some((msg, from))))
+            }
+            None => {
+                mailbox.task = Some(task::current());
+                Ok(Async::NotReady)
+            }
+        }
+    }
+}
+
+impl Sink for Socket {
+    type SinkItem = (Message, SocketAddr);
+    type SinkError = Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        let (msg, to) = item;
+        let bytes = msg.to_bytes()?;
+
+        self.network.inner.lock().unwrap().send(self.addr, to, bytes);
+
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        Ok(Async::Ready(()))
+    }
+}
+
+/// A future that completes at a point in time on the virtual clock.
+///
+/// A `Sleep` waits on at most one timer, which goes away once it is dropped.
+pub struct Sleep {
+    network: Network,
+    id: u64,
+    deadline: Instant,
+}
+
+impl Future for Sleep {
+    type Item = ();
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<(), Error> {
+        let mut inner = self.network.inner.lock().unwrap();
+
+        if inner.now >= self.deadline {
+            Ok(Async::Ready(()))
+        } else {
+            inner.timers.insert(self.id, (self.deadline, task::current()));
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.network.inner.lock() {
+            inner.timers.remove(&self.id);
+        }
+    }
+}
+
+/// Drives futures over a simulated `Network` as a discrete event simulation.
+///
+/// Futures are polled until none of them can make progress, then the clock
+/// jumps straight to the next scheduled datagram delivery or `Sleep`, so a
+/// test covering minutes of retransmission timeouts runs instantly.
+pub struct Simulation {
+    network: Network,
+    background: Vec<Spawn<Box<dyn Future<Item = (), Error = ()>>>>,
+    woken: Arc<Woken>,
+}
+
+struct Woken(AtomicBool);
+
+impl Notify for Woken {
+    fn notify(&self, _id: usize) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+impl Simulation {
+    pub fn new(network: Network) -> Simulation {
+        Simulation {
+            network,
+            background: Vec::new(),
+            woken: Arc::new(Woken(AtomicBool::new(false))),
+        }
+    }
+
+    pub fn network(&self) -> &Network {
+        &self.network
+    }
+
+    /// Run `future` alongside everything else in the simulation, for example
+    /// a server.
+    pub fn spawn<F>(&mut self, future: F)
+        where F: Future<Item = (), Error = ()> + 'static
+    {
+        self.background.push(executor::spawn(Box::new(future)));
+    }
+
+    /// Run the simulation until `future` completes.
+    ///
+    /// Returns `Error::Timeout` if `future` is still waiting once there is
+    /// nothing left that could ever wake it.
+    pub fn run<F: Future<Error = Error>>(&mut self, future: F) -> Result<F::Item, Error> {
+        let mut future = executor::spawn(future);
+
+        loop {
+            self.woken.0.store(false, Ordering::SeqCst);
+
+            if let Async::Ready(item) = future.poll_future_notify(&self.woken, 0)? {
+                return Ok(item);
+            }
+
+            let woken = &self.woken;
+            let mut i = 0;
+            while i < self.background.len() {
+                match self.background[i].poll_future_notify(woken, 0) {
+                    Ok(Async::NotReady) => i += 1,
+                    Ok(Async::Ready(())) | Err(()) => {
+                        self.background.remove(i);
+                    }
+                }
+            }
+
+            if self.woken.0.load(Ordering::SeqCst) {
+                continue;
+            }
+
+            match self.network.next_event() {
+                Some(at) => {
+                    let mut inner = self.network.inner.lock().unwrap();
+                    inner.advance_to(at);
+                }
+                None => return Err(Error::Timeout),
+            }
+        }
+    }
+}
+
+/// A small, seedable pseudo random number generator (xorshift64*).
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> XorShift {
+        XorShift(if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed })
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// A uniformly distributed value in `[0, 1)`.
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.unit() < probability
+    }
+
+    fn between(&mut self, min: Duration, max: Duration) -> Duration {
+        if max <= min {
+            return min;
+        }
+
+        let span = max - min;
+        let nanos = span.as_secs() * 1_000_000_000 + span.subsec_nanos() as u64;
+
+        min + Duration::from_nanos(self.next() % (nanos + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Network, Simulation, Socket, Woken};
+
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use futures::prelude::*;
+    use futures::executor;
+
+    use client::Client;
+    use endpoint::Endpoint;
+    use error::Error;
+    use message::{Code, Message};
+    use server::{Request, Server};
+
+    fn recv(sim: &mut Simulation, socket: Socket) -> Result<(Message, Socket), Error> {
+        sim.run(socket.into_future().map_err(|(e, _)| e))
+            .map(|(msg, socket)| (msg.expect("socket closed").0, socket))
+    }
+
+    fn send(socket: Socket, to: &Socket, mid: u16) -> Socket {
+        socket.send((Message::new().with_mid(mid), to.local_addr())).wait().unwrap()
+    }
+
+    #[test]
+    fn perfect_network_delivers_immediately() {
+        let net = Network::new(Config::new());
+        let a = net.bind_any().unwrap();
+        let b = net.bind_any().unwrap();
+
+        let _a = send(a, &b, 7);
+        assert_eq!(net.in_flight(), 0);
+
+        let mut sim = Simulation::new(net.clone());
+        let (msg, _b) = recv(&mut sim, b).unwrap();
+        assert_eq!(msg.mid, 7);
+    }
+
+    #[test]
+    fn bind_rejects_address_in_use() {
+        let net = Network::new(Config::new());
+        let a = net.bind_any().unwrap();
+
+        assert!(net.bind(&a.local_addr()).is_err());
+    }
+
+    #[test]
+    fn delay_waits_for_clock() {
+        let delay = Duration::from_millis(100);
+        let net = Network::new(Config::new().with_delay(delay, delay));
+        let a = net.bind_any().unwrap();
+        let b = net.bind_any().unwrap();
+        let start = net.now();
+
+        let _a = send(a, &b, 1);
+        assert_eq!(net.in_flight(), 1);
+
+        net.advance(Duration::from_millis(99));
+        assert_eq!(net.in_flight(), 1);
+
+        let mut sim = Simulation::new(net.clone());
+        let (msg, _b) = recv(&mut sim, b).unwrap();
+        assert_eq!(msg.mid, 1);
+        assert_eq!(net.now() - start, delay);
+    }
+
+    #[test]
+    fn loss_drops_everything() {
+        let net = Network::new(Config::new().with_loss(1.0));
+        let a = net.bind_any().unwrap();
+        let b = net.bind_any().unwrap();
+
+        let _a = send(a, &b, 1);
+
+        let mut sim = Simulation::new(net.clone());
+        assert!(recv(&mut sim, b).is_err());
+        assert_eq!(net.stats().lost, 1);
+    }
+
+    #[test]
+    fn duplication_delivers_twice() {
+        let net = Network::new(Config::new().with_duplication(1.0));
+        let a = net.bind_any().unwrap();
+        let b = net.bind_any().unwrap();
+
+        let _a = send(a, &b, 3);
+
+        let mut sim = Simulation::new(net.clone());
+        let (first, b) = recv(&mut sim, b).unwrap();
+        let (second, b) = recv(&mut sim, b).unwrap();
+        assert_eq!(first, second);
+        assert!(recv(&mut sim, b).is_err());
+    }
+
+    #[test]
+    fn reordering_lets_later_datagrams_overtake() {
+        let net = Network::new(Config::new()
+                               .with_delay(Duration::from_millis(10), Duration::from_millis(10))
+                               .with_reordering(0.5, Duration::from_millis(50)));
+        let mut a = net.bind_any().unwrap();
+        let mut b = net.bind_any().unwrap();
+
+        for mid in 0..20 {
+            a = send(a, &b, mid);
+        }
+
+        let mut sim = Simulation::new(net.clone());
+        let mut received = Vec::new();
+        for _ in 0..20 {
+            let (msg, socket) = recv(&mut sim, b).unwrap();
+            received.push(msg.mid);
+            b = socket;
+        }
+
+        assert!(net.stats().reordered > 0);
+        assert!(received.windows(2).any(|w| w[0] > w[1]));
+        received.sort();
+        assert_eq!(received, (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn mtu_drops_oversized_datagrams() {
+        let net = Network::new(Config::new().with_mtu(16));
+        let a = net.bind_any().unwrap();
+        let b = net.bind_any().unwrap();
+
+        let msg = Message::new().with_payload(vec![0; 32]);
+        let _a = a.send((msg, b.local_addr())).wait().unwrap();
+
+        assert_eq!(net.stats().oversized, 1);
+        assert_eq!(net.in_flight(), 0);
+    }
+
+    #[test]
+    fn same_seed_same_fate() {
+        let fates = |seed| {
+            let net = Network::new(Config::new().with_loss(0.5).with_seed(seed));
+            let mut a = net.bind_any().unwrap();
+            let b = net.bind_any().unwrap();
+            for mid in 0..32 {
+                a = send(a, &b, mid);
+            }
+            net.stats()
+        };
+
+        assert_eq!(fates(1), fates(1));
+    }
+
+    #[test]
+    fn sleep_follows_virtual_clock() {
+        let net = Network::new(Config::new());
+        let start = net.now();
+        let mut sim = Simulation::new(net.clone());
+
+        sim.run(net.sleep(Duration::from_secs(300))).unwrap();
+
+        assert_eq!(net.now() - start, Duration::from_secs(300));
+    }
+
+    #[test]
+    fn sleep_registers_one_timer() {
+        let net = Network::new(Config::new());
+        let woken = Arc::new(Woken(AtomicBool::new(false)));
+        let mut sleep = executor::spawn(net.sleep(Duration::from_secs(1)));
+
+        for _ in 0..3 {
+            assert!(sleep.poll_future_notify(&woken, 0).unwrap().is_not_ready());
+        }
+        assert_eq!(net.inner.lock().unwrap().timers.len(), 1);
+
+        drop(sleep);
+        assert_eq!(net.next_event(), None);
+    }
+
+    #[test]
+    fn server_answers_duplicated_request_once() {
+        let net = Network::new(Config::new()
+                               .with_duplication(1.0)
+                               .with_delay(Duration::from_millis(5), Duration::from_millis(20)));
+        let server_socket = net.bind_any().unwrap();
+        let server_addr = server_socket.local_addr();
+        let client_socket = net.bind_any().unwrap();
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler_calls = calls.clone();
+        let server = Server::new(move |req: Request| {
+            handler_calls.fetch_add(1, Ordering::SeqCst);
+            Ok(req.message.new_reply()
+               .with_code(Code::Content)
+               .with_payload(b"hello".to_vec()))
+        });
+
+        let mut sim = Simulation::new(net.clone());
+        sim.spawn(server.serve(server_socket).map_err(|_| ()));
+
+        let response = sim.run(Client::new()
+                               .with_endpoint(Endpoint::Resolved(server_addr))
+                               .send_over(client_socket))
+            .unwrap();
+
+        assert_eq!(response.code, Code::Content);
+        assert_eq!(response.payload, b"hello");
+
+        // let the duplicates play out
+        sim.run(net.sleep(Duration::from_secs(1))).unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn lost_request_times_out() {
+        let net = Network::new(Config::new().with_loss(1.0));
+        let server_socket = net.bind_any().unwrap();
+        let server_addr = server_socket.local_addr();
+        let client_socket = net.bind_any().unwrap();
+
+        let server = Server::new(|req: Request| Ok(req.message.new_reply().with_code(Code::Content)));
+
+        let mut sim = Simulation::new(net.clone());
+        sim.spawn(server.serve(server_socket).map_err(|_| ()));
+
+        let response = sim.run(Client::new()
+                               .with_endpoint(Endpoint::Resolved(server_addr))
+                               .send_over(client_socket));
+
+        match response {
+            Err(Error::Timeout) => (),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+    }
+}
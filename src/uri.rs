@@ -0,0 +1,537 @@
+//! CoAP URIs, and how they map to the options of a message.
+//!
+//! `CoapUri` is a URI with one of the CoAP schemes, which can be taken apart
+//! into the endpoint and options of a request (RFC 7252: 6.4). `compose` is
+//! the inverse (RFC 7252: 6.5): the options are percent-encoded back into a
+//! URI, with the address a request was sent to filling in whatever they
+//! leave out. The Location-Path and Location-Query options of a 2.01
+//! Created response are resolved against the request URI the same way.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::option::Option as StdOption;
+use std::str::FromStr;
+
+use percent_encoding::percent_decode;
+use url::{Host, Url};
+
+use endpoint::Endpoint;
+use error::{Error, UrlError};
+use message::Message;
+use message::option::{LocationPath, LocationQuery, Option, Options, UriHost, UriPath, UriPort, UriQuery};
+
+/// RFC 3986: 2.3.  Unreserved Characters
+fn is_unreserved(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"-._~".contains(&c)
+}
+
+/// RFC 3986: 2.2.  Reserved Characters
+fn is_sub_delim(c: u8) -> bool {
+    b"!$&'()*+,;=".contains(&c)
+}
+
+fn is_host_char(c: u8) -> bool {
+    is_unreserved(c) || is_sub_delim(c)
+}
+
+fn is_path_char(c: u8) -> bool {
+    is_unreserved(c) || is_sub_delim(c) || c == b':' || c == b'@'
+}
+
+/// `&` separates the arguments of a query, so it must be encoded inside one.
+fn is_query_char(c: u8) -> bool {
+    (is_path_char(c) || c == b'/' || c == b'?') && c != b'&'
+}
+
+/// Append `value` to `uri`, percent-encoding every byte `allowed` rejects.
+fn encode(uri: &mut String, value: &[u8], allowed: fn(u8) -> bool) {
+    for &c in value {
+        if allowed(c) {
+            uri.push(c as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", c));
+        }
+    }
+}
+
+/// The port a scheme is reached at unless told otherwise.
+fn default_port(scheme: &str) -> StdOption<u16> {
+    match scheme {
+        "http" => Some(80),
+        "https" => Some(443),
+        scheme => scheme.parse::<Scheme>().ok().map(Scheme::default_port),
+    }
+}
+
+fn depercent(s: &str) -> Result<String, UrlError> {
+    percent_decode(s.as_bytes())
+        .decode_utf8()
+        .map(Cow::into_owned)
+        .map_err(UrlError::NonUtf8)
+}
+
+/// The URI schemes of CoAP over its different transports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Scheme {
+    /// over UDP (RFC 7252)
+    Coap,
+    /// over DTLS (RFC 7252)
+    Coaps,
+    /// over TCP (RFC 8323)
+    CoapTcp,
+    /// over TLS (RFC 8323)
+    CoapsTcp,
+    /// over WebSockets (RFC 8323)
+    CoapWs,
+    /// over secure WebSockets (RFC 8323)
+    CoapsWs,
+}
+
+impl Scheme {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Scheme::Coap => "coap",
+            Scheme::Coaps => "coaps",
+            Scheme::CoapTcp => "coap+tcp",
+            Scheme::CoapsTcp => "coaps+tcp",
+            Scheme::CoapWs => "coap+ws",
+            Scheme::CoapsWs => "coaps+ws",
+        }
+    }
+
+    /// The port used when a URI doesn't give one.
+    pub fn default_port(self) -> u16 {
+        match self {
+            Scheme::Coap | Scheme::CoapTcp => 5683,
+            Scheme::Coaps | Scheme::CoapsTcp => 5684,
+            Scheme::CoapWs => 80,
+            Scheme::CoapsWs => 443,
+        }
+    }
+
+    pub fn is_secure(&self) -> bool {
+        matches!(*self, Scheme::Coaps | Scheme::CoapsTcp | Scheme::CoapsWs)
+    }
+}
+
+impl FromStr for Scheme {
+    type Err = UrlError;
+
+    fn from_str(scheme: &str) -> Result<Scheme, UrlError> {
+        match scheme {
+            "coap" => Ok(Scheme::Coap),
+            "coaps" => Ok(Scheme::Coaps),
+            "coap+tcp" => Ok(Scheme::CoapTcp),
+            "coaps+tcp" => Ok(Scheme::CoapsTcp),
+            "coap+ws" => Ok(Scheme::CoapWs),
+            "coaps+ws" => Ok(Scheme::CoapsWs),
+            other => Err(UrlError::UnsupportedScheme(other.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An absolute URI with one of the CoAP schemes, e.g.
+/// `coap://example.net/.well-known/core`.
+///
+/// It has a host, no fragment, and its path and query are UTF-8 once
+/// percent-decoded, so it can always be taken apart into options.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CoapUri {
+    scheme: Scheme,
+    url: Url,
+}
+
+impl CoapUri {
+    pub fn parse(uri: &str) -> Result<CoapUri, UrlError> {
+        CoapUri::from_url(Url::parse(uri).map_err(UrlError::Parse)?)
+    }
+
+    pub fn from_url(url: Url) -> Result<CoapUri, UrlError> {
+        // Step 3
+        let scheme = url.scheme().parse()?;
+
+        // Step 4
+        if url.fragment().is_some() {
+            return Err(UrlError::FragmentSpecified);
+        }
+
+        if url.host().is_none() {
+            return Err(UrlError::NonAbsolutePath);
+        }
+        if url.path() != "" && url.path() != "/" {
+            for segment in url.path_segments().ok_or(UrlError::NonAbsolutePath)? {
+                depercent(segment)?;
+            }
+        }
+        for argument in url.query().unwrap_or("").split('&') {
+            depercent(argument)?;
+        }
+
+        Ok(CoapUri { scheme, url })
+    }
+
+    /// The URI of a request with `options` sent to `endpoint`.
+    pub fn from_parts(scheme: Scheme, endpoint: &Endpoint, options: &Options) -> Result<CoapUri, Error> {
+        let url = match *endpoint {
+            Endpoint::Resolved(ref addr) => compose_with_scheme(scheme.as_str(), options, Some(addr))?,
+            Endpoint::Unresolved(ref host, port) => {
+                let mut options = options.clone();
+                if !options.map.contains_key(&UriHost::NUMBER) {
+                    options.push(UriHost::new(host.clone()));
+                }
+                if !options.map.contains_key(&UriPort::NUMBER) {
+                    options.push(UriPort::new(u64::from(port)));
+                }
+                compose_with_scheme(scheme.as_str(), &options, None)?
+            }
+            Endpoint::Unset => return Err(UrlError::NonAbsolutePath.into()),
+        };
+
+        Ok(CoapUri::from_url(url)?)
+    }
+
+    pub fn scheme(&self) -> Scheme {
+        self.scheme
+    }
+
+    /// The host as written in the URI, IPv6 addresses in brackets.
+    pub fn host(&self) -> &str {
+        self.url.host_str().unwrap_or("")
+    }
+
+    /// The port given in the URI, or the default port of the scheme.
+    pub fn port(&self) -> u16 {
+        self.url.port().unwrap_or_else(|| self.scheme.default_port())
+    }
+
+    /// The percent-decoded segments of the path, none for `/`.
+    pub fn path_segments(&self) -> Vec<String> {
+        match self.url.path() {
+            "" | "/" => Vec::new(),
+            _ => self.url.path_segments()
+                .map_or_else(Vec::new, |segments| segments.filter_map(|s| depercent(s).ok()).collect()),
+        }
+    }
+
+    /// The percent-decoded arguments of the query, split at `&`.
+    pub fn query(&self) -> Vec<String> {
+        match self.url.query() {
+            None | Some("") => Vec::new(),
+            Some(query) => query.split('&').filter_map(|s| depercent(s).ok()).collect(),
+        }
+    }
+
+    /// Resolve the URI reference `reference`, e.g. `../temp?unit=C`, with
+    /// this URI as its base.
+    pub fn join(&self, reference: &str) -> Result<CoapUri, UrlError> {
+        CoapUri::from_url(self.url.join(reference).map_err(UrlError::Parse)?)
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.url.as_str()
+    }
+
+    pub fn as_url(&self) -> &Url {
+        &self.url
+    }
+
+    /// The endpoint to send a request for this URI to.
+    pub fn endpoint(&self) -> Endpoint {
+        self.decompose().0
+    }
+
+    /// The options of a request for this URI.
+    pub fn options(&self) -> Options {
+        self.decompose().1
+    }
+
+    /// RFC 7252: 6.4.  Decomposing URIs into Options
+    ///
+    /// The endpoint and options of a request for this URI.
+    pub fn decompose(&self) -> (Endpoint, Options) {
+        let url = &self.url;
+        let mut options = Options::new();
+
+        // Step 6
+        let port = self.port();
+
+        // Step 5
+        let endpoint = match url.host().expect("checked when parsed") {
+            Host::Domain(domain) => {
+                // ! gross hack warning !
+                // The URL standard from whatwg (which the url crate follows) specifies that you try to
+                // parse an IPv6 address no matter whatm but you only try to parse an IPv4 address from
+                // a set of "special" url schemes that it defines. *Shockingly* coap isn't one of them.
+                // See https://url.spec.whatwg.org/#host-parsing
+                // and https://url.spec.whatwg.org/#url-miscellaneous
+                //
+                // This forces us to try to parse any domain name as an IPv4 address here to comply
+                // with the coap spec.
+
+                if let Ok(ip) = domain.parse::<Ipv4Addr>() {
+                    Endpoint::Resolved((ip, port).into())
+                } else {
+                    let host = domain.to_lowercase();
+                    options.push(UriHost::new(host.clone()));
+                    Endpoint::Unresolved(host, port)
+                }
+            },
+            Host::Ipv4(ip) => Endpoint::Resolved((ip, port).into()),
+            Host::Ipv6(ip) => Endpoint::Resolved((ip, port).into()),
+        };
+
+        // Step 8
+        for segment in self.path_segments() {
+            options.push(UriPath::new(segment));
+        }
+
+        // Step 9
+        for argument in self.query() {
+            options.push(UriQuery::new(argument));
+        }
+
+        (endpoint, options)
+    }
+}
+
+impl FromStr for CoapUri {
+    type Err = UrlError;
+
+    fn from_str(uri: &str) -> Result<CoapUri, UrlError> {
+        CoapUri::parse(uri)
+    }
+}
+
+impl fmt::Display for CoapUri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.url.as_str())
+    }
+}
+
+/// Take `url` apart into the endpoint and options of a request, as long as
+/// it's a `coap` URI, the only kind there is a transport for.
+pub(crate) fn decompose(url: &Url) -> Result<(Endpoint, Options), UrlError> {
+    let uri = CoapUri::from_url(url.clone())?;
+
+    match uri.scheme() {
+        Scheme::Coap => Ok(uri.decompose()),
+        other => Err(UrlError::UnsupportedScheme(other.to_string())),
+    }
+}
+
+fn parse(uri: &str) -> Result<Url, Error> {
+    Ok(Url::parse(uri).map_err(UrlError::Parse)?)
+}
+
+/// Append the path and query given by the options numbered `path` and
+/// `query` to `uri`.
+fn encode_path_and_query(uri: &mut String, options: &Options, path: u16, query: u16) {
+    // Step 8
+    match options.map.get(&path) {
+        Some(segments) => for segment in segments {
+            uri.push('/');
+            encode(uri, segment, is_path_char);
+        },
+        None => uri.push('/'),
+    }
+
+    // Step 9
+    if let Some(arguments) = options.map.get(&query) {
+        for (i, argument) in arguments.iter().enumerate() {
+            uri.push(if i == 0 { '?' } else { '&' });
+            encode(uri, argument, is_query_char);
+        }
+    }
+}
+
+/// The URI with `scheme` of a request with `options`. Without Uri-Host and
+/// Uri-Port the address the request was sent to is used, if given, or the
+/// default port of the scheme.
+pub(crate) fn compose_with_scheme(
+    scheme: &str,
+    options: &Options,
+    destination: StdOption<&SocketAddr>,
+) -> Result<Url, Error> {
+    // Step 1, 2
+    let mut uri = format!("{}://", scheme);
+
+    // Step 3, 4
+    let host = options.get_raw::<UriHost>().and_then(|hosts| hosts.into_iter().next());
+    match host {
+        Some(host) => match String::from_utf8_lossy(&host).parse::<IpAddr>() {
+            Ok(IpAddr::V6(ip)) => uri.push_str(&format!("[{}]", ip)),
+            _ => encode(&mut uri, &host, is_host_char),
+        },
+        None => match destination.map(SocketAddr::ip) {
+            Some(IpAddr::V6(ip)) => uri.push_str(&format!("[{}]", ip)),
+            Some(IpAddr::V4(ip)) => uri.push_str(&ip.to_string()),
+            None => return Err(UrlError::NonAbsolutePath.into()),
+        },
+    }
+
+    // Step 5, 6
+    let port = options.get_first::<UriPort>()?
+        .map(|port| port.into_value() as u16)
+        .or_else(|| destination.map(SocketAddr::port));
+    if let Some(port) = port {
+        if Some(port) != default_port(scheme) {
+            uri.push_str(&format!(":{}", port));
+        }
+    }
+
+    encode_path_and_query(&mut uri, options, UriPath::NUMBER, UriQuery::NUMBER);
+
+    parse(&uri)
+}
+
+/// RFC 7252: 6.5.  Composing URIs from Options
+///
+/// The `coap` URI of a request with `options` that was sent to
+/// `destination`, e.g. the local address of the server that received it.
+pub fn compose(options: &Options, destination: &SocketAddr) -> Result<Url, Error> {
+    compose_with_scheme("coap", options, Some(destination))
+}
+
+/// RFC 7252: 5.10.7.  Location-Path and Location-Query
+///
+/// The absolute URI of the resource created by the request for
+/// `request_uri`, as given by the Location-Path and Location-Query options
+/// of `response`, if it gives one.
+pub fn location(request_uri: &Url, response: &Message) -> Result<StdOption<Url>, Error> {
+    let options = &response.options;
+    let has_path = options.map.contains_key(&LocationPath::NUMBER);
+    let has_query = options.map.contains_key(&LocationQuery::NUMBER);
+
+    if !has_path && !has_query {
+        return Ok(None);
+    }
+
+    let mut reference = String::new();
+    encode_path_and_query(&mut reference, options, LocationPath::NUMBER, LocationQuery::NUMBER);
+
+    // a query alone replaces only the query of the request URI
+    if !has_path {
+        reference.remove(0);
+    }
+
+    Ok(Some(request_uri.join(&reference).map_err(UrlError::Parse)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use message::Code;
+
+    #[test]
+    fn compose_from_options() {
+        let destination = "[2001:db8::2:1]:5683".parse().unwrap();
+        let uri = compose(&Options::new(), &destination).unwrap();
+        assert_eq!(uri.as_str(), "coap://[2001:db8::2:1]/");
+
+        let mut options = Options::new();
+        options.push(UriHost::new("example.net".to_string()));
+        options.push(UriPort::new(61616));
+        options.push(UriPath::new("a b".to_string()));
+        options.push(UriPath::new("c/d".to_string()));
+        options.push(UriQuery::new("x=1&2".to_string()));
+        options.push(UriQuery::new("y=?/".to_string()));
+        let uri = compose(&options, &"192.0.2.1:5683".parse().unwrap()).unwrap();
+        assert_eq!(uri.as_str(), "coap://example.net:61616/a%20b/c%2Fd?x=1%262&y=?/");
+    }
+
+    #[test]
+    fn compose_inverts_decompose() {
+        for uri in &["coap://198.51.100.1:61616//%2F//?%2F%2F&?%26",
+                     "coap://example.net/.well-known/core",
+                     "coap://xn--18j4d.example/%E3%81%93%E3%82%93%E3%81%AB%E3%81%A1%E3%81%AF"] {
+            let uri = Url::parse(uri).unwrap();
+            let (_, options) = decompose(&uri).unwrap();
+            let destination = match uri.host() {
+                Some(::url::Host::Ipv4(ip)) => SocketAddr::new(IpAddr::V4(ip), uri.port().unwrap_or(5683)),
+                _ => "192.0.2.1:5683".parse().unwrap(),
+            };
+
+            let (_, recomposed) = decompose(&compose(&options, &destination).unwrap()).unwrap();
+            assert_eq!(recomposed, options);
+        }
+    }
+
+    #[test]
+    fn coap_uri_parts() {
+        let uri: CoapUri = "coaps+tcp://[2001:db8::1]/sensors/t%C3%BCr%2F1?unit=C&raw".parse().unwrap();
+        assert_eq!(uri.scheme(), Scheme::CoapsTcp);
+        assert!(uri.scheme().is_secure());
+        assert_eq!(uri.host(), "[2001:db8::1]");
+        assert_eq!(uri.port(), 5684);
+        assert_eq!(uri.path_segments(), vec!["sensors", "tür/1"]);
+        assert_eq!(uri.query(), vec!["unit=C", "raw"]);
+
+        let uri = CoapUri::parse("coap+ws://Example.NET:8080").unwrap();
+        assert_eq!(uri.port(), 8080);
+        assert_eq!(uri.endpoint(), Endpoint::Unresolved("example.net".to_string(), 8080));
+        assert!(uri.path_segments().is_empty());
+
+        match CoapUri::parse("http://example.net/") {
+            Err(UrlError::UnsupportedScheme(ref scheme)) if scheme == "http" => (),
+            other => panic!("{:?}", other),
+        }
+        match CoapUri::parse("coap://example.net/#top") {
+            Err(UrlError::FragmentSpecified) => (),
+            other => panic!("{:?}", other),
+        }
+        match CoapUri::parse("coap://example.net/%FF") {
+            Err(UrlError::NonUtf8(_)) => (),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn coap_uri_join() {
+        let base = CoapUri::parse("coap://example.net/sensors/temp?unit=C").unwrap();
+
+        assert_eq!(base.join("humidity").unwrap().as_str(), "coap://example.net/sensors/humidity");
+        assert_eq!(base.join("../actuators/fan").unwrap().as_str(), "coap://example.net/actuators/fan");
+        assert_eq!(base.join("?unit=F").unwrap().as_str(), "coap://example.net/sensors/temp?unit=F");
+        assert_eq!(base.join("coaps://other.example/").unwrap().scheme(), Scheme::Coaps);
+    }
+
+    #[test]
+    fn coap_uri_from_parts() {
+        let uri = CoapUri::parse("coap://198.51.100.1:61616/a%2Fb?x&y").unwrap();
+        let (endpoint, options) = uri.decompose();
+        assert_eq!(CoapUri::from_parts(Scheme::Coap, &endpoint, &options).unwrap(), uri);
+
+        let uri = CoapUri::parse("coap+tcp://example.net/.well-known/core").unwrap();
+        let (endpoint, options) = uri.decompose();
+        assert_eq!(CoapUri::from_parts(Scheme::CoapTcp, &endpoint, &options).unwrap(), uri);
+
+        let mut options = Options::new();
+        options.push(UriPath::new("temp".to_string()));
+        let endpoint = Endpoint::Unresolved("example.net".to_string(), 5683);
+        assert_eq!(CoapUri::from_parts(Scheme::Coap, &endpoint, &options).unwrap().as_str(), "coap://example.net/temp");
+    }
+
+    #[test]
+    fn location_of_created_resource() {
+        let request_uri = Url::parse("coap://example.net:61616/sensors?new").unwrap();
+
+        let mut response = Message::new().with_code(Code::Created);
+        assert_eq!(location(&request_uri, &response).unwrap(), None);
+
+        response.set_location("/sensors/7 b?rev=2").unwrap();
+        let uri = location(&request_uri, &response).unwrap().unwrap();
+        assert_eq!(uri.as_str(), "coap://example.net:61616/sensors/7%20b?rev=2");
+
+        response.options.remove::<LocationPath>();
+        let uri = location(&request_uri, &response).unwrap().unwrap();
+        assert_eq!(uri.as_str(), "coap://example.net:61616/sensors?rev=2");
+    }
+}